@@ -0,0 +1,14 @@
+// Applies the edits the input decodes to (see src/edits.rs) to a Document
+// and to the model, run with
+//
+//     cargo +nightly fuzz run document_edits
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Err(error) = mtx::check(data) {
+        panic!("{}", error);
+    }
+});
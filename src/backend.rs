@@ -0,0 +1,240 @@
+// What the Terminal runs on. The termion backend is the real terminal,
+// with mouse reporting, bracketed paste and focus reporting turned on, the
+// memory backend (only built for tests) takes its keys and mouse events
+// from a script and keeps the last frame it was given so tests can read
+// the screen.
+
+use crate::screen::Screen;
+use std::io::{self, Stdout, Write};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+use termion::event::Event;
+use termion::input::{MouseTerminal, TermRead};
+use termion::raw::{IntoRawMode, RawTerminal};
+#[cfg(test)]
+use {
+    crate::Position,
+    std::{cell::RefCell, collections::VecDeque, rc::Rc},
+    termion::event::Key,
+};
+
+// With bracketed paste on, pasted text comes between these two, which
+// termion doesn't know and passes on as Event::Unsupported
+pub const PASTE_START: &[u8] = b"\x1b[200~";
+pub const PASTE_END: &[u8] = b"\x1b[201~";
+const ENABLE_PASTE: &str = "\x1b[?2004h";
+const DISABLE_PASTE: &str = "\x1b[?2004l";
+// Sent when the terminal gets the focus back, the one for losing it is
+// ignored
+pub const FOCUS_IN: &[u8] = b"\x1b[I";
+const ENABLE_FOCUS: &str = "\x1b[?1004h";
+const DISABLE_FOCUS: &str = "\x1b[?1004l";
+
+pub trait Backend {
+    // Width and height of the whole terminal
+    fn size(&self) -> Result<(u16, u16), io::Error>;
+    fn read_event(&mut self) -> Result<Event, io::Error>;
+    // Waits up to `timeout` for an event, true when there is one to read
+    fn poll(&mut self, timeout: Duration) -> Result<bool, io::Error>;
+    // Shows `frame`, which replaces `shown`, and leaves the cursor where
+    // the frame's cursor is
+    fn draw(&mut self, frame: &Screen, shown: &Screen)
+        -> Result<(), io::Error>;
+    fn clear(&mut self) -> Result<(), io::Error>;
+}
+
+pub struct TermionBackend {
+    // Events are read on a thread of their own so that waiting for one
+    // can time out, an event that poll() got ahead of read_event() is
+    // kept aside
+    events: Receiver<Result<Event, io::Error>>,
+    pending: Option<Result<Event, io::Error>>,
+    stdout: MouseTerminal<RawTerminal<Stdout>>,
+}
+
+impl TermionBackend {
+    pub fn new() -> Result<Self, io::Error> {
+        let mut stdout = MouseTerminal::from(io::stdout().into_raw_mode()?);
+        write!(stdout, "{ENABLE_PASTE}{ENABLE_FOCUS}")?;
+        stdout.flush()?;
+        let (sender, events) = mpsc::channel();
+        thread::spawn(move || {
+            // The same iterator has to be kept around, termion reads two
+            // bytes at a time and keeps the second one in the iterator for
+            // the next key
+            let mut stdin = io::stdin().events();
+            loop {
+                if let Some(event) = stdin.next() {
+                    if sender.send(event).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        Ok(Self {
+            events,
+            pending: None,
+            stdout,
+        })
+    }
+}
+
+impl Drop for TermionBackend {
+    fn drop(&mut self) {
+        // Mouse reporting and raw mode are turned off by their own types
+        let _ = write!(self.stdout, "{DISABLE_PASTE}{DISABLE_FOCUS}");
+        let _ = self.stdout.flush();
+    }
+}
+
+fn closed() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "Input closed")
+}
+
+impl Backend for TermionBackend {
+    fn size(&self) -> Result<(u16, u16), io::Error> {
+        termion::terminal_size()
+    }
+
+    fn read_event(&mut self) -> Result<Event, io::Error> {
+        match self.pending.take() {
+            Some(event) => event,
+            None => self.events.recv().map_err(|_| closed())?,
+        }
+    }
+
+    fn poll(&mut self, timeout: Duration) -> Result<bool, io::Error> {
+        if self.pending.is_some() {
+            return Ok(true);
+        }
+        match self.events.recv_timeout(timeout) {
+            Ok(event) => {
+                self.pending = Some(event);
+                Ok(true)
+            }
+            Err(RecvTimeoutError::Timeout) => Ok(false),
+            Err(RecvTimeoutError::Disconnected) => Err(closed()),
+        }
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn draw(
+        &mut self,
+        frame: &Screen,
+        shown: &Screen,
+    ) -> Result<(), io::Error> {
+        // The cursor is hidden while the changes are written
+        let x = frame.cursor.x.saturating_add(1) as u16;
+        let y = frame.cursor.y.saturating_add(1) as u16;
+        let mut stdout = io::stdout();
+        write!(
+            stdout,
+            "{}{}{}{}",
+            termion::cursor::Hide,
+            frame.diff(shown),
+            termion::cursor::Goto(x, y),
+            termion::cursor::Show
+        )?;
+        stdout.flush()
+    }
+
+    fn clear(&mut self) -> Result<(), io::Error> {
+        let mut stdout = io::stdout();
+        write!(
+            stdout,
+            "{}{}",
+            termion::clear::All,
+            termion::cursor::Goto(1, 1)
+        )?;
+        stdout.flush()
+    }
+}
+
+#[cfg(test)]
+struct MemoryState {
+    width: u16,
+    height: u16,
+    events: VecDeque<Event>,
+    screen: Screen,
+}
+
+// Clones share the same state, so a test can keep one to push keys and
+// read the screen while the editor owns another
+#[cfg(test)]
+#[derive(Clone)]
+pub struct MemoryBackend {
+    state: Rc<RefCell<MemoryState>>,
+}
+
+#[cfg(test)]
+impl MemoryBackend {
+    pub fn new(width: u16, height: u16) -> Self {
+        Self {
+            state: Rc::new(RefCell::new(MemoryState {
+                width,
+                height,
+                events: VecDeque::new(),
+                screen: Screen::new(width.into(), height.into()),
+            })),
+        }
+    }
+
+    pub fn push_keys(&self, keys: impl IntoIterator<Item = Key>) {
+        self.push_events(keys.into_iter().map(Event::Key));
+    }
+
+    pub fn push_events(&self, events: impl IntoIterator<Item = Event>) {
+        self.state.borrow_mut().events.extend(events);
+    }
+
+    pub fn has_keys(&self) -> bool {
+        !self.state.borrow().events.is_empty()
+    }
+
+    pub fn resize(&self, width: u16, height: u16) {
+        let mut state = self.state.borrow_mut();
+        state.width = width;
+        state.height = height;
+    }
+
+    pub fn line(&self, y: usize) -> String {
+        self.state.borrow().screen.line(y)
+    }
+
+    pub fn cursor(&self) -> Position {
+        self.state.borrow().screen.cursor.clone()
+    }
+}
+
+#[cfg(test)]
+impl Backend for MemoryBackend {
+    fn size(&self) -> Result<(u16, u16), io::Error> {
+        let state = self.state.borrow();
+        Ok((state.width, state.height))
+    }
+
+    fn read_event(&mut self) -> Result<Event, io::Error> {
+        self.state.borrow_mut().events.pop_front().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::UnexpectedEof, "No more keys")
+        })
+    }
+
+    fn poll(&mut self, _timeout: Duration) -> Result<bool, io::Error> {
+        Ok(self.has_keys())
+    }
+
+    fn draw(
+        &mut self,
+        frame: &Screen,
+        _shown: &Screen,
+    ) -> Result<(), io::Error> {
+        self.state.borrow_mut().screen.clone_from(frame);
+        Ok(())
+    }
+
+    fn clear(&mut self) -> Result<(), io::Error> {
+        self.state.borrow_mut().screen.clear();
+        Ok(())
+    }
+}
@@ -0,0 +1,192 @@
+// The buffer list, every file being edited is a buffer with its own
+// Document, cursor, scroll offset and selection (the undo history lives in
+// the Document).
+//
+// The current buffer is special: while it is current its state lives in
+// the Editor's own fields so that editing code doesn't have to go through
+// the list, and its slot here is left empty. The Editor parks that state
+// back in its slot for anything that needs to see every buffer, like
+// switching buffers or finding one by name.
+
+use crate::Document;
+use crate::Position;
+use crate::SelectedText;
+
+#[derive(Default)]
+pub struct Buffer {
+    // Buffers are numbered from 1 in the order they are created, numbers
+    // of deleted buffers are not reused
+    pub number: usize,
+    pub document: Document,
+    pub cursor_position: Position,
+    pub offset: Position,
+    pub hl_text: SelectedText,
+}
+
+pub struct Buffers {
+    list: Vec<Buffer>,
+    current: usize,
+    // Number of the previously current buffer
+    alternate: Option<usize>,
+    last_number: usize,
+}
+
+impl Default for Buffers {
+    fn default() -> Self {
+        // There is always at least one buffer, the current one
+        Self {
+            list: vec![Buffer {
+                number: 1,
+                ..Buffer::default()
+            }],
+            current: 0,
+            alternate: None,
+            last_number: 1,
+        }
+    }
+}
+
+impl Buffers {
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    #[must_use]
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    #[must_use]
+    pub fn number(&self, index: usize) -> Option<usize> {
+        self.list.get(index).map(|buffer| buffer.number)
+    }
+
+    #[must_use]
+    pub fn alternate(&self) -> Option<usize> {
+        self.alternate.and_then(|number| self.index_of(number))
+    }
+
+    #[must_use]
+    pub fn index_of(&self, number: usize) -> Option<usize> {
+        self.list.iter().position(|buffer| buffer.number == number)
+    }
+
+    #[must_use]
+    pub fn get(&self, index: usize) -> Option<&Buffer> {
+        self.list.get(index)
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut Buffer> {
+        self.list.get_mut(index)
+    }
+
+    pub fn add(&mut self, document: Document) -> usize {
+        // Returns the index of the new buffer, which is added last
+        self.last_number = self.last_number.saturating_add(1);
+        self.list.push(Buffer {
+            number: self.last_number,
+            document,
+            ..Buffer::default()
+        });
+        self.list.len().saturating_sub(1)
+    }
+
+    // Index of the buffer after (or before) the current one, wrapping
+    // around the list
+    #[must_use]
+    pub fn step(&self, forward: bool) -> usize {
+        let len = self.list.len().max(1);
+        if forward {
+            self.current.saturating_add(1) % len
+        } else {
+            self.current.checked_sub(1).unwrap_or(len.saturating_sub(1))
+        }
+    }
+
+    pub fn set_current(&mut self, index: usize) {
+        if index < self.list.len() && index != self.current {
+            self.alternate = self.number(self.current);
+            self.current = index;
+        }
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Buffer> {
+        // Removing the current buffer is up to the caller, who has to
+        // make another one current first
+        if index == self.current || index >= self.list.len() {
+            return None;
+        }
+        let buffer = self.list.remove(index);
+        if index < self.current {
+            self.current = self.current.saturating_sub(1);
+        }
+        if self.alternate == Some(buffer.number) {
+            self.alternate = None;
+        }
+        Some(buffer)
+    }
+
+    pub fn find(&self, name: &str) -> Result<usize, String> {
+        // Finds a buffer by number, by file name or by a part of its file
+        // name when only one buffer matches it
+        if let Ok(number) = name.parse::<usize>() {
+            return self
+                .index_of(number)
+                .ok_or_else(|| format!("Buffer {number} does not exist"));
+        }
+        let names: Vec<Option<&str>> = self
+            .list
+            .iter()
+            .map(|buffer| buffer.document.file_name.as_deref())
+            .collect();
+        if let Some(index) = names.iter().position(|file| *file == Some(name)) {
+            return Ok(index);
+        }
+        let mut matches = names
+            .iter()
+            .enumerate()
+            .filter(|(_, file)| file.is_some_and(|file| file.contains(name)));
+        match (matches.next(), matches.next()) {
+            (Some((index, _)), None) => Ok(index),
+            (None, _) => Err(format!("No matching buffer for {name}")),
+            (Some(_), Some(_)) => {
+                Err(format!("More than one match for {name}"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Buffers;
+    use crate::Document;
+
+    fn named(name: &str) -> Document {
+        let mut document = Document::default();
+        document.file_name = Some(name.to_string());
+        document
+    }
+
+    #[test]
+    fn test_buffers() {
+        let mut buffers = Buffers::default();
+        let main = buffers.add(named("src/main.rs"));
+        let editor = buffers.add(named("src/editor.rs"));
+        assert_eq!(buffers.number(editor), Some(3));
+        assert_eq!(buffers.step(false), editor);
+        assert_eq!(buffers.find("3"), Ok(editor));
+        assert_eq!(buffers.find("main"), Ok(main));
+        assert!(buffers.find("src").is_err());
+        assert!(buffers.find("7").is_err());
+
+        buffers.set_current(editor);
+        assert_eq!(buffers.alternate(), Some(0));
+        assert_eq!(buffers.step(true), 0);
+        assert!(buffers.remove(editor).is_none());
+        assert!(buffers.remove(0).is_some());
+        assert_eq!(buffers.current(), 1);
+        assert_eq!(buffers.alternate(), None);
+        assert_eq!(buffers.find("editor"), Ok(1));
+    }
+}
@@ -25,7 +25,7 @@ pub mod cursor_cmds {
             Mode::Normal => {
                 if at.y >= doc_len {
                     at.y = doc_len.saturating_sub(1);
-                    at.x = doc.row(at.y).unwrap().len();
+                    at.x = doc.row_len(at.y).unwrap_or(0);
                 } else if at.x >= line_len {
                     at.x = line_len.saturating_sub(1);
                 }
@@ -299,3 +299,305 @@ pub mod visual_cmds {
         }
     }
 }
+
+pub mod search_cmds {
+    use crate::quickfix::Entry;
+    use crate::regex::{Error, Regex};
+    use crate::Document;
+    use crate::Position;
+    use crate::Row;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    pub fn find_next(
+        at: &Position,
+        doc: &Document,
+        regex: &Regex,
+        forward: bool,
+    ) -> Result<Option<(Position, bool)>, Error> {
+        // Returns where the next match starts and whether the search
+        // had to wrap around the document to find it
+        let text = doc.text();
+        let cursor = doc.offset_of(at);
+        let mut steps = 0;
+
+        if forward {
+            let next = text
+                .get(cursor..)
+                .and_then(|rest| rest.chars().next())
+                .map_or(text.len(), |c| cursor.saturating_add(c.len_utf8()));
+            if let Some(caps) = regex.captures_from(&text, next, &mut steps)? {
+                return Ok(Some((doc.position_of(caps.start()), false)));
+            }
+            return Ok(regex
+                .captures_from(&text, 0, &mut steps)?
+                .filter(|caps| caps.start() <= cursor)
+                .map(|caps| (doc.position_of(caps.start()), true)));
+        }
+
+        // Going backwards means finding the last match before the cursor
+        // or, failing that, the last one in the whole document
+        let mut before = None;
+        let mut last = None;
+        let mut from = 0;
+        while let Some(caps) = regex.captures_from(&text, from, &mut steps)? {
+            if caps.start() < cursor {
+                before = Some(caps.start());
+            }
+            last = Some(caps.start());
+            from = if caps.end() > caps.start() {
+                caps.end()
+            } else {
+                match text.get(caps.end()..).and_then(|r| r.chars().next()) {
+                    Some(c) => caps.end().saturating_add(c.len_utf8()),
+                    None => break,
+                }
+            };
+        }
+        Ok(match (before, last) {
+            (Some(start), _) => Some((doc.position_of(start), false)),
+            (None, Some(start)) => Some((doc.position_of(start), true)),
+            (None, None) => None,
+        })
+    }
+
+    fn read_text(path: &PathBuf) -> Option<String> {
+        // Binary files (anything with a NUL byte near the start) and files
+        // that aren't valid UTF-8 are skipped
+        let bytes = fs::read(path).ok()?;
+        if bytes.iter().take(8192).any(|byte| *byte == 0) {
+            return None;
+        }
+        String::from_utf8(bytes).ok()
+    }
+
+    #[must_use]
+    pub fn grep(
+        regex: &Regex,
+        files: &[PathBuf],
+    ) -> (Vec<Entry>, Vec<PathBuf>) {
+        // Searches the files directly instead of opening them as
+        // Documents, with one entry for every line a match starts in. A file
+        // the search gives up on keeps the matches found before that and is
+        // returned with the others like it, the rest are still searched
+        let mut entries = Vec::new();
+        let mut aborted = Vec::new();
+        for path in files {
+            let Some(text) = read_text(path) else {
+                continue;
+            };
+            if grep_text(regex, path, &text, &mut entries).is_err() {
+                aborted.push(path.clone());
+            }
+        }
+        (entries, aborted)
+    }
+
+    fn grep_text(
+        regex: &Regex,
+        path: &Path,
+        text: &str,
+        entries: &mut Vec<Entry>,
+    ) -> Result<(), Error> {
+        // Like in a Document, the last line has no newline after it
+        let text = text.strip_suffix('\n').unwrap_or(text);
+        let mut line: usize = 0;
+        let mut line_start = 0;
+        let mut from = 0;
+        let mut steps = 0;
+        while let Some(caps) = regex.captures_from(text, from, &mut steps)? {
+            let before = text.get(line_start..caps.start()).unwrap_or("");
+            if let Some(last) = before.rfind('\n') {
+                line = line.saturating_add(before.matches('\n').count());
+                line_start = line_start.saturating_add(last).saturating_add(1);
+            }
+            let before = text.get(line_start..caps.start()).unwrap_or("");
+            let line_end = text
+                .get(line_start..)
+                .and_then(|rest| rest.find('\n'))
+                .map_or(text.len(), |end| line_start.saturating_add(end));
+            let row = Row::from(text.get(line_start..line_end).unwrap_or(""));
+            entries.push(Entry {
+                path: path.to_path_buf(),
+                position: Position {
+                    x: row.grapheme_index(before.len()),
+                    y: line,
+                },
+                text: row.as_str().trim().to_string(),
+            });
+            // Later matches in the same line are skipped
+            from = line_end.saturating_add(1);
+            if from > text.len() {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub mod substitute_cmds {
+    use crate::regex::Captures;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    enum Case {
+        Upper,
+        Lower,
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum Piece {
+        Text(String),
+        Group(usize),
+        // `\u` and `\l` only change the next character
+        Next(Case),
+        // `\U` and `\L` change everything until `\E` or `\e`
+        Until(Case),
+        End,
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub struct Replacement {
+        pieces: Vec<Piece>,
+    }
+
+    impl Replacement {
+        #[must_use]
+        pub fn parse(template: &str) -> Self {
+            // Vim style replacement: `&` and `\0` are the whole match,
+            // `\1`..`\9` capture groups and `\r`/`\n` a line break
+            let mut pieces = Vec::new();
+            let mut text = String::new();
+            let mut chars = template.chars();
+            while let Some(c) = chars.next() {
+                let piece = match c {
+                    '&' => Piece::Group(0),
+                    '\\' => match chars.next() {
+                        Some(digit @ '0'..='9') => {
+                            Piece::Group(digit as usize - '0' as usize)
+                        }
+                        Some('u') => Piece::Next(Case::Upper),
+                        Some('l') => Piece::Next(Case::Lower),
+                        Some('U') => Piece::Until(Case::Upper),
+                        Some('L') => Piece::Until(Case::Lower),
+                        Some('E' | 'e') => Piece::End,
+                        Some('n' | 'r') => {
+                            text.push('\n');
+                            continue;
+                        }
+                        Some('t') => {
+                            text.push('\t');
+                            continue;
+                        }
+                        Some(c) => {
+                            text.push(c);
+                            continue;
+                        }
+                        None => {
+                            text.push('\\');
+                            continue;
+                        }
+                    },
+                    c => {
+                        text.push(c);
+                        continue;
+                    }
+                };
+                if !text.is_empty() {
+                    pieces.push(Piece::Text(std::mem::take(&mut text)));
+                }
+                pieces.push(piece);
+            }
+            if !text.is_empty() {
+                pieces.push(Piece::Text(text));
+            }
+            Self { pieces }
+        }
+
+        #[must_use]
+        pub fn expand(&self, caps: &Captures, haystack: &str) -> String {
+            let mut result = String::new();
+            let mut next: Option<Case> = None;
+            let mut until: Option<Case> = None;
+            for piece in &self.pieces {
+                let text = match piece {
+                    Piece::Text(text) => text.as_str(),
+                    Piece::Group(group) => match caps.get(*group) {
+                        Some((start, end)) => &haystack[start..end],
+                        None => "",
+                    },
+                    Piece::Next(case) => {
+                        next = Some(*case);
+                        continue;
+                    }
+                    Piece::Until(case) => {
+                        until = Some(*case);
+                        continue;
+                    }
+                    Piece::End => {
+                        until = None;
+                        continue;
+                    }
+                };
+                for c in text.chars() {
+                    match next.take().or(until) {
+                        Some(Case::Upper) => result.extend(c.to_uppercase()),
+                        Some(Case::Lower) => result.extend(c.to_lowercase()),
+                        None => result.push(c),
+                    }
+                }
+            }
+            result
+        }
+    }
+
+    #[must_use]
+    pub fn count_newlines(text: &str) -> usize {
+        text.bytes().filter(|byte| *byte == b'\n').count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::commands::search_cmds;
+    use crate::commands::substitute_cmds::Replacement;
+    use crate::regex::{CaseMode, Regex, Syntax};
+    use std::fs;
+
+    #[test]
+    fn test_replacement_expand() {
+        let regex = Regex::new(
+            "\\(\\w\\+\\) \\(\\w\\+\\)",
+            Syntax::Vim,
+            CaseMode::Sensitive,
+        )
+        .unwrap();
+        let text = "hello world";
+        let caps = regex.captures_from(text, 0, &mut 0).unwrap().unwrap();
+        let expand =
+            |template: &str| Replacement::parse(template).expand(&caps, text);
+        assert_eq!(expand("\\2 \\1"), "world hello");
+        assert_eq!(expand("\\u\\1 \\U\\2\\E!"), "Hello WORLD!");
+        assert_eq!(expand("\\L[&]"), "[hello world]");
+        assert_eq!(expand("a\\rb\\&"), "a\nb&");
+    }
+
+    #[test]
+    fn test_grep() {
+        // The first file takes the search past its step budget, which
+        // mustn't lose the match in the second one
+        let dir = std::env::temp_dir();
+        let slow = dir.join(format!("mtx-grep-slow-{}", std::process::id()));
+        let fast = dir.join(format!("mtx-grep-fast-{}", std::process::id()));
+        fs::write(&slow, "a".repeat(40)).unwrap();
+        fs::write(&fast, "xx\nab\n").unwrap();
+        let regex =
+            Regex::new("\\(a*\\)*b", Syntax::Vim, CaseMode::Sensitive).unwrap();
+        let (entries, aborted) =
+            search_cmds::grep(&regex, &[slow.clone(), fast.clone()]);
+        fs::remove_file(&fast).unwrap();
+        fs::remove_file(&slow).unwrap();
+        assert_eq!(aborted, vec![slow]);
+        assert_eq!(entries.len(), 1);
+        assert_eq!((entries[0].path.clone(), entries[0].position.y), (fast, 1));
+    }
+}
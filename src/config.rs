@@ -0,0 +1,136 @@
+// The mtx.conf files read at startup, one in the user's config directory
+// ($XDG_CONFIG_HOME/mtx/mtx.conf, or ~/.config/mtx/mtx.conf) and one at the
+// root of the project the editor is started in, which is the closest
+// directory above the working directory with a .git in it (or the working
+// directory itself). The project's file is read last, so its settings win.
+//
+// Every line is an ex command, as typed after ':' (which can be left in),
+// empty lines and lines starting with '"' or '#' are comments.
+//
+// The files are read again whenever they change, which is found out by
+// looking at their modification times every now and then.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+const FILE_NAME: &str = "mtx.conf";
+
+fn user_path() -> Option<PathBuf> {
+    let config =
+        env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| {
+                env::var_os("HOME").map(|home| Path::new(&home).join(".config"))
+            })?;
+    Some(config.join("mtx").join(FILE_NAME))
+}
+
+fn project_path() -> Option<PathBuf> {
+    let cwd = env::current_dir().ok()?;
+    let root = cwd
+        .ancestors()
+        .find(|dir| dir.join(".git").exists())
+        .unwrap_or(&cwd);
+    Some(root.join(FILE_NAME))
+}
+
+#[must_use]
+pub fn paths() -> Vec<PathBuf> {
+    // Tests don't get to read the user's settings
+    if cfg!(test) {
+        return Vec::new();
+    }
+    let mut paths: Vec<PathBuf> = user_path().into_iter().collect();
+    if let Some(project) = project_path() {
+        // The project may well be the config directory itself
+        if !paths.contains(&project) {
+            paths.push(project);
+        }
+    }
+    paths
+}
+
+#[must_use]
+pub fn commands(text: &str) -> Vec<(usize, &str)> {
+    // The commands in a config file with their line numbers (from 1)
+    text.lines()
+        .enumerate()
+        .map(|(index, line)| (index.saturating_add(1), line.trim()))
+        .filter(|(_, line)| {
+            !line.is_empty() && !line.starts_with('"') && !line.starts_with('#')
+        })
+        .map(|(number, line)| {
+            (number, line.strip_prefix(':').unwrap_or(line).trim_start())
+        })
+        .collect()
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+#[derive(Debug, Default)]
+pub struct Watcher {
+    paths: Vec<PathBuf>,
+    // When each file was last modified, None for the missing ones, so
+    // creating or removing a file counts as a change too
+    stamps: Vec<Option<SystemTime>>,
+}
+
+impl Watcher {
+    #[must_use]
+    pub fn new(paths: Vec<PathBuf>) -> Self {
+        let stamps = paths.iter().map(|path| modified(path)).collect();
+        Self { paths, stamps }
+    }
+
+    #[must_use]
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+
+    pub fn changed(&mut self) -> bool {
+        // Whether any of the files changed since the last call
+        let stamps: Vec<_> =
+            self.paths.iter().map(|path| modified(path)).collect();
+        if stamps == self.stamps {
+            return false;
+        }
+        self.stamps = stamps;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{commands, Watcher};
+    use std::fs;
+
+    #[test]
+    fn test_commands() {
+        let text = "\" Indentation\nset ts=4\n\n  :set nu\n# colors\nset statusbg=#202020\n";
+        assert_eq!(
+            commands(text),
+            vec![(2, "set ts=4"), (4, "set nu"), (6, "set statusbg=#202020")]
+        );
+    }
+
+    #[test]
+    fn test_watcher() {
+        let path = std::env::temp_dir()
+            .join(format!("mtx-config-{}.conf", std::process::id()));
+        fs::write(&path, "set ts=4\n").unwrap();
+        let mut watcher = Watcher::new(vec![path.clone()]);
+        assert!(!watcher.changed());
+        fs::remove_file(&path).unwrap();
+        assert!(watcher.changed());
+        assert!(!watcher.changed());
+        fs::write(&path, "set ts=2\n").unwrap();
+        assert!(watcher.changed());
+        fs::remove_file(&path).unwrap();
+    }
+}
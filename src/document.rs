@@ -1,44 +1,317 @@
+use crate::encoding::Encoding;
+use crate::explorer::Listing;
+use crate::fileformat::{self, Layout};
+use crate::hex::Hex;
+use crate::options::{Opt, Options, Value};
+use crate::save::{self, Stamp};
+use crate::swap::{self, Contents, Swap};
 use crate::Position;
 use crate::Row;
 use std::cmp::Ordering;
+use std::collections::VecDeque;
 use std::fs;
-use std::io::{Error, Write};
+use std::io::Error;
+use std::path::Path;
+
+// Maximum amount of changes that can be undone
+const UNDO_LEVELS: usize = 1000;
+
+// An undo (or redo) step: the `len` rows from `start` on are replaced by
+// `rows` to get back to the text before the change, and the cursor goes
+// back to `cursor`. Only the lines that changed are kept
+#[derive(Debug)]
+struct Change {
+    start: usize,
+    len: usize,
+    rows: Vec<Row>,
+    cursor: Position,
+}
+
+impl Change {
+    fn between(from: &[Row], to: &[Row], cursor: Position) -> Self {
+        // The change that turns `from` into `to`
+        let prefix = from
+            .iter()
+            .zip(to)
+            .take_while(|(old, new)| old == new)
+            .count();
+        let suffix = from
+            .iter()
+            .rev()
+            .zip(to.iter().rev())
+            .take(from.len().min(to.len()).saturating_sub(prefix))
+            .take_while(|(old, new)| old == new)
+            .count();
+        Self {
+            start: prefix,
+            len: from.len().saturating_sub(prefix).saturating_sub(suffix),
+            rows: to
+                .get(prefix..to.len().saturating_sub(suffix))
+                .unwrap_or_default()
+                .to_vec(),
+            cursor,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0 && self.rows.is_empty()
+    }
+
+    fn apply(self, rows: &mut Vec<Row>, cursor: &Position) -> (Position, Self) {
+        // Returns where the cursor goes, and the change that undoes this one
+        // and takes the cursor back to `cursor`
+        let end = self.start.saturating_add(self.len).min(rows.len());
+        let start = self.start.min(end);
+        let len = self.rows.len();
+        let removed = rows.splice(start..end, self.rows).collect();
+        let inverse = Self {
+            start,
+            len,
+            rows: removed,
+            cursor: cursor.clone(),
+        };
+        (self.cursor, inverse)
+    }
+
+    fn apply_to_lines(&self, lines: &mut Vec<String>) {
+        // As apply(), on the text of the rows (see Document::swap_contents)
+        let end = self.start.saturating_add(self.len).min(lines.len());
+        let start = self.start.min(end);
+        let text = self.rows.iter().map(|row| row.as_str().to_string());
+        lines.splice(start..end, text);
+    }
+}
 
 #[derive(Default, Debug)]
 pub struct Document {
     rows: Vec<Row>,
     pub file_name: Option<String>,
     dirty: bool,
+    // Goes up with every change, see swap.rs
+    version: usize,
+    undo_stack: VecDeque<Change>,
+    redo_stack: VecDeque<Change>,
+    // The rows and cursor at the last checkpoint, which become an undo
+    // step (see commit()) once the change after it is done
+    before: Option<(Vec<Row>, Position)>,
+    // Set while a command made of others (as :global) runs, whose changes
+    // are all undone at once from the checkpoint taken before it
+    in_change: bool,
+    // Set when the document is a directory listing
+    listing: Option<Listing>,
+    // Options set with :setlocal for this buffer
+    pub options: Options,
+    pub swap: Swap,
+    // Opened read-only because of someone else's swap file, which keeps
+    // it from being saved
+    pub read_only: bool,
+    // The file as it was last read or written, and a later change to it
+    // the user chose to keep editing over, which isn't asked about again
+    // until saving
+    pub disk: Option<Stamp>,
+    pub dismissed: Option<Stamp>,
+    // The encoding it was opened in when that was given rather than found,
+    // which reloading keeps to
+    encoding: Option<Encoding>,
+    // Set while the buffer is shown in hex mode, then the rows are made
+    // from the bytes in it, see hex.rs
+    hex: Option<Hex>,
 }
 
 impl Document {
     pub fn open(filename: &str) -> Result<Self, std::io::Error> {
-        let contents = fs::read_to_string(filename)?;
-        let rows: Vec<Row> = contents.lines().map(Row::from).collect();
-        Ok(Self {
-            rows,
+        Self::open_as(filename, None)
+    }
+
+    pub fn open_as(
+        filename: &str,
+        encoding: Option<Encoding>,
+    ) -> Result<Self, std::io::Error> {
+        // Reads the file in `encoding`, or the one it looks to be in
+        if Path::new(filename).is_dir() {
+            let listing = Listing::read(Path::new(filename))?;
+            return Ok(Self {
+                rows: listing.rows(),
+                file_name: Some(format!("{}/", filename.trim_end_matches('/'))),
+                listing: Some(listing),
+                ..Self::default()
+            });
+        }
+        let contents = fs::read(filename)?;
+        let mut document = Self {
             file_name: Some(filename.to_string()),
-            dirty: false,
-        })
+            disk: Some(Stamp::new(Path::new(filename), &contents)),
+            encoding,
+            ..Self::default()
+        };
+        document.read_contents(&contents, encoding);
+        Ok(document)
     }
 
-    pub fn save(&mut self, file_name: Option<String>) -> Result<(), Error> {
-        if let Some(file_name) = file_name {
-            let mut file = fs::File::create(file_name)?;
-            for row in &self.rows {
-                file.write_all(row.as_bytes())?;
-                file.write_all(b"\n")?;
-            }
-            self.dirty = false;
+    fn read_contents(&mut self, contents: &[u8], encoding: Option<Encoding>) {
+        // The lines of the file become the rows, and how they were laid out
+        // the buffer's options
+        let (lines, layout) = fileformat::read(contents, encoding);
+        self.rows = lines.iter().map(String::as_str).map(Row::from).collect();
+        self.options
+            .set(Opt::FileFormat, Value::Format(layout.format));
+        self.options
+            .set(Opt::EndOfLine, Value::Bool(layout.end_of_line));
+        self.options.set(Opt::Bomb, Value::Bool(layout.bom));
+        self.options
+            .set(Opt::FileEncoding, Value::Encoding(layout.encoding));
+    }
+
+    pub fn disk_change(&mut self) -> Option<Stamp> {
+        // The file as it is now, if something changed it since it was last
+        // read or written. A file that was only touched isn't a change, one
+        // that is gone isn't either since saving puts it back
+        if self.listing.is_some() {
+            return None;
+        }
+        let path = Path::new(self.file_name.as_deref()?);
+        let metadata = fs::metadata(path).ok()?;
+        if self
+            .disk
+            .as_ref()
+            .is_some_and(|disk| disk.matches(&metadata))
+        {
+            return None;
+        }
+        let stamp = Stamp::read(path).ok()?;
+        if self
+            .disk
+            .as_ref()
+            .is_some_and(|disk| disk.same_contents(&stamp))
+        {
+            self.disk = Some(stamp);
+            return None;
+        }
+        Some(stamp)
+    }
+
+    pub fn reload(
+        &mut self,
+        keep_undo: bool,
+        cursor: &Position,
+    ) -> Result<(), Error> {
+        // Reads the file again, dropping any unsaved changes. With
+        // `keep_undo` they can still be undone back to, as if the reload
+        // was a change of its own
+        let Some(file_name) = self.file_name.clone() else {
             return Ok(());
+        };
+        let contents = fs::read(&file_name)?;
+        // Undo steps hold text, which the rows in hex mode aren't
+        if keep_undo && self.hex.is_none() {
+            self.checkpoint(cursor);
+        } else if !keep_undo {
+            self.undo_stack.clear();
+            self.redo_stack.clear();
+            self.before = None;
+        }
+        self.read_contents(&contents, self.encoding);
+        if self.hex.is_some() {
+            self.show_hex(contents.clone());
+        }
+        self.touch();
+        self.dirty = false;
+        self.disk = Some(Stamp::new(Path::new(&file_name), &contents));
+        self.dismissed = None;
+        Ok(())
+    }
+
+    #[must_use]
+    pub fn listing(&self) -> Option<&Listing> {
+        self.listing.as_ref()
+    }
+
+    #[must_use]
+    pub fn hex(&self) -> Option<&Hex> {
+        self.hex.as_ref()
+    }
+
+    fn show_hex(&mut self, bytes: Vec<u8>) {
+        let hex = Hex::new(bytes);
+        self.rows = hex
+            .lines()
+            .iter()
+            .map(String::as_str)
+            .map(Row::from)
+            .collect();
+        self.hex = Some(hex);
+    }
+
+    pub fn enter_hex(
+        &mut self,
+        layout: Layout,
+        cursor: &Position,
+    ) -> Result<(), String> {
+        // Shows the bytes the buffer would be saved as. The text before is
+        // an undo step, to go back to once out of hex mode
+        if self.listing.is_some() {
+            return Err("Can't show a directory listing in hex".to_string());
+        }
+        let bytes =
+            fileformat::write(self.rows.iter().map(Row::as_str), layout)?;
+        self.checkpoint(cursor);
+        self.show_hex(bytes);
+        Ok(())
+    }
+
+    pub fn leave_hex(&mut self, encoding: Encoding) {
+        // Reads the bytes back as text
+        if let Some(hex) = self.hex.take() {
+            self.read_contents(hex.bytes(), Some(encoding));
         }
+    }
 
-        if let Some(file_name) = &self.file_name {
-            let mut file = fs::File::create(file_name)?;
-            for row in &self.rows {
-                file.write_all(row.as_bytes())?;
-                file.write_all(b"\n")?;
+    pub fn overwrite_byte(
+        &mut self,
+        at: &Position,
+        c: char,
+    ) -> Option<Position> {
+        // In hex mode, see Hex::overwrite()
+        let hex = self.hex.as_mut()?;
+        let next = hex.overwrite(at, c)?;
+        let line = Row::from(hex.line(at.y).as_str());
+        if let Some(row) = self.rows.get_mut(at.y) {
+            *row = line;
+        }
+        self.touch();
+        Some(next)
+    }
+
+    pub fn save(
+        &mut self,
+        file_name: Option<String>,
+        layout: Layout,
+        backup: bool,
+    ) -> Result<(), Error> {
+        // Listings are saved by applying their changes to the directory
+        // instead, see explorer.rs
+        if self.listing.is_some() {
+            return Err(Error::other("Can't write a directory listing"));
+        }
+        if self.read_only {
+            return Err(Error::other("Buffer is read-only"));
+        }
+        let Some(file_name) = file_name.or_else(|| self.file_name.clone())
+        else {
+            return Ok(());
+        };
+        // In hex mode the bytes are written as they are
+        let contents = match &self.hex {
+            Some(hex) => hex.bytes().to_vec(),
+            None => {
+                fileformat::write(self.rows.iter().map(Row::as_str), layout)
+                    .map_err(Error::other)?
             }
+        };
+        save::write(Path::new(&file_name), &contents, backup)?;
+        if self.file_name.as_ref() == Some(&file_name) {
+            self.disk = Some(Stamp::new(Path::new(&file_name), &contents));
+            self.dismissed = None;
         }
         self.dirty = false;
         Ok(())
@@ -50,26 +323,51 @@ impl Document {
         match at.y.cmp(&len) {
             Ordering::Greater => (),
             Ordering::Equal => {
-                self.dirty = true;
+                self.touch();
                 let mut row = Row::default();
                 row.insert(0, c);
                 self.rows.push(row);
             }
             Ordering::Less => {
-                self.dirty = true;
+                self.touch();
                 let row = &mut self.rows[at.y];
                 row.insert(at.x, c);
             }
         }
     }
 
+    #[allow(clippy::indexing_slicing)]
+    pub fn insert_text(&mut self, at: &Position, text: &str) -> Position {
+        // Inserts text that may span many lines in one go, returns the
+        // position right after it
+        let len = self.len();
+        if at.y > len {
+            return at.clone();
+        }
+        self.touch();
+        if at.y == len {
+            self.rows.push(Row::default());
+        }
+        let rest = self.rows[at.y].split(at.x);
+        let mut lines = text.split('\n');
+        self.rows[at.y].append(&Row::from(lines.next().unwrap_or_default()));
+        let next = at.y.saturating_add(1);
+        let rows: Vec<Row> = lines.map(Row::from).collect();
+        let y = at.y.saturating_add(rows.len());
+        self.rows.splice(next..next, rows);
+        let row = &mut self.rows[y];
+        let x = row.len();
+        row.append(&rest);
+        Position { x, y }
+    }
+
     #[allow(clippy::indexing_slicing)]
     pub fn insert_newline(&mut self, at: &Position) {
         let len = self.len();
         if at.y > len {
             return;
         }
-        self.dirty = true;
+        self.touch();
         if at.y == len {
             self.rows.push(Row::default());
             return;
@@ -80,13 +378,12 @@ impl Document {
 
     #[allow(clippy::indexing_slicing)]
     pub fn delete(&mut self, at: &Position) {
-        // Panics when deleting the last line sometimes
         let len = self.rows.len();
         if at.y >= len {
             return;
         }
 
-        self.dirty = true;
+        self.touch();
         if at.x == self.rows[at.y].len() && at.y.saturating_add(1) < len {
             // When at the end of a line
             let next_row = self.rows.remove(at.y.saturating_add(1));
@@ -105,61 +402,72 @@ impl Document {
     }
 
     pub fn delete_line(&mut self, at: usize) {
+        self.touch();
         self.rows.remove(at);
     }
 
     pub fn delete_lines(&mut self, start: usize, end: usize) {
-        // Go in reverse since after deleting a line all further
-        // indexes are now -1, so we don't have to deal with that.
-        for i in (start..end.saturating_add(1)).rev() {
-            self.rows.remove(i);
+        // Deletes the lines from start to end, both included, the ones
+        // past the end of the document are ignored
+        let end = end.saturating_add(1).min(self.rows.len());
+        if start >= end {
+            return;
         }
+        self.touch();
+        self.rows.drain(start..end);
     }
 
+    #[allow(clippy::indexing_slicing)]
     pub fn delete_slice(&mut self, start: &Position, end: &Position) {
-        // TODO: clean this shit
-        // Deletes a (continuous) slice of text that may span multiple
-        // lines or not
-        // NOTE: start must be smaller than end
-
-        if start.x == 0
-            && end.x == self.row(end.y).unwrap().len().saturating_sub(1)
-        {
-            self.delete_lines(start.y, end.y);
+        // Deletes the text from start to end, both included, where a
+        // position at the end of a line stands for its newline. A slice
+        // that starts at the beginning of a line and reaches the last
+        // character of another deletes those lines entirely
+        let Some(last) = self.rows.len().checked_sub(1) else {
+            return;
+        };
+        if start.y > last {
             return;
         }
-
-        match start.y.cmp(&end.y) {
-            Ordering::Greater => (),
-            Ordering::Equal => {
-                self.row_as_mut(start.y)
-                    .unwrap()
-                    .delete_slice(start.x, end.x);
+        let start = Position {
+            x: start.x.min(self.rows[start.y].len()),
+            y: start.y,
+        };
+        let end = if end.y > last {
+            Position {
+                x: self.rows[last].len(),
+                y: last,
             }
-            Ordering::Less => {
-                self.delete_until_eol(start);
-                let mut y = start.y.saturating_add(1);
-                let next_line = start.y.saturating_add(1);
-                while y < end.y {
-                    self.delete_line(next_line);
-                    y = y.saturating_add(1);
-                }
-                let last_row = self.row(next_line).unwrap();
-                let last_row = if !last_row.is_empty() {
-                    Row::from(last_row.slice(end.x.saturating_add(1), 0))
-                } else {
-                    if let Some(last_row) =
-                        self.row(next_line.saturating_add(1))
-                    {
-                        // If line after cursor is not empty
-                        Row::from(last_row.slice(0, 0))
-                    } else {
-                        return;
-                    }
-                };
-                self.row_as_mut(start.y).unwrap().append(&last_row);
-                self.delete_line(next_line);
+        } else {
+            Position {
+                x: end.x.min(self.rows[end.y].len()),
+                y: end.y,
             }
+        };
+        if start > end {
+            return;
+        }
+        let end_len = self.rows[end.y].len();
+        if start.x == 0 && end.x.saturating_add(1) >= end_len {
+            self.delete_lines(start.y, end.y);
+            return;
+        }
+
+        self.touch();
+        // What is left of the last line goes after what is left of the
+        // first one, which is the line after it when the newline goes
+        let (rest, last_removed) = if end.x == end_len {
+            let next = end.y.saturating_add(1);
+            (self.rows.get(next).cloned().unwrap_or_default(), next)
+        } else {
+            (self.rows[end.y].split(end.x.saturating_add(1)), end.y)
+        };
+        let last_removed = last_removed.min(last);
+        let row = &mut self.rows[start.y];
+        row.delete_until_eol(start.x);
+        row.append(&rest);
+        if start.y < last_removed {
+            self.rows.drain(start.y.saturating_add(1)..=last_removed);
         }
     }
 
@@ -172,11 +480,311 @@ impl Document {
     pub fn delete_to_eol(&mut self, start: &Position) {
         // Deletes from current position until end of line character,
         // appending the line below to this one
+        if start.y >= self.rows.len() {
+            return;
+        }
+        self.touch();
+        let next = start.y.saturating_add(1);
+        let next_row = (next < self.rows.len()).then(|| self.rows.remove(next));
+        if let Some(row) = self.row_as_mut(start.y) {
+            row.delete_until_eol(start.x);
+            if let Some(next_row) = next_row {
+                row.append(&next_row);
+            }
+        }
+    }
 
-        let next_row = self.rows.remove(start.y.saturating_add(1));
-        let row = self.row_as_mut(start.y).unwrap();
-        row.delete_until_eol(start.x);
-        row.append(&next_row);
+    pub fn set_text(&mut self, text: &str) {
+        // Replaces the whole document, the inverse of text(). Only the
+        // rows that actually changed are replaced, so the others keep
+        // their marks, and the replacements take the origins of the rows
+        // they replace line by line
+        let lines: Vec<&str> = text.split('\n').collect();
+        let prefix = self
+            .rows
+            .iter()
+            .zip(&lines)
+            .take_while(|(row, line)| row.as_str() == **line)
+            .count();
+        let suffix = self
+            .rows
+            .iter()
+            .rev()
+            .zip(lines.iter().rev())
+            .take(self.rows.len().min(lines.len()).saturating_sub(prefix))
+            .take_while(|(row, line)| row.as_str() == **line)
+            .count();
+        let end = self.rows.len().saturating_sub(suffix);
+        let old = self.rows.get(prefix..end).unwrap_or(&[]);
+        let changed: Vec<Row> = lines
+            .get(prefix..lines.len().saturating_sub(suffix))
+            .unwrap_or(&[])
+            .iter()
+            .enumerate()
+            .map(|(index, line)| {
+                let mut row = Row::from(*line);
+                let origin = old.get(index).and_then(Row::origin);
+                row.set_origin(origin.map(String::from));
+                row
+            })
+            .collect();
+        self.rows.splice(prefix..end, changed);
+        self.touch();
+    }
+
+    fn touch(&mut self) {
+        self.dirty = true;
+        self.version = self.version.wrapping_add(1);
+    }
+
+    pub fn set_modified(&mut self) {
+        // For changes to how the file is written rather than to the text
+        self.touch();
+    }
+
+    #[must_use]
+    pub fn version(&self) -> usize {
+        self.version
+    }
+
+    #[must_use]
+    pub fn swap_contents(&self) -> Contents {
+        let lines = |rows: &[Row]| -> Vec<String> {
+            rows.iter().map(|row| row.as_str().to_string()).collect()
+        };
+        let text = lines(&self.rows);
+        // The change since the last checkpoint isn't an undo step yet
+        let pending = self
+            .before
+            .as_ref()
+            .map(|(rows, cursor)| {
+                Change::between(&self.rows, rows, cursor.clone())
+            })
+            .filter(|change| !change.is_empty());
+        // Both stacks are used from their end, so the steps kept are the
+        // last ones of each that fit in swap::HISTORY_SIZE together. Each
+        // is the whole text it goes back to, made by undoing the changes
+        // one after another, so a big buffer gets few of them or none
+        let mut left = swap::HISTORY_SIZE;
+        let mut recent = |changes: Vec<&Change>| {
+            let mut current = text.clone();
+            let mut steps = Vec::new();
+            for change in changes.into_iter().take(swap::UNDO_STEPS) {
+                change.apply_to_lines(&mut current);
+                let size = current
+                    .iter()
+                    .map(|line| line.len().saturating_add(1))
+                    .sum();
+                let Some(rest) = left.checked_sub(size) else {
+                    break;
+                };
+                left = rest;
+                steps.push((current.clone(), change.cursor.clone()));
+            }
+            steps.reverse();
+            steps
+        };
+        let undo = recent(
+            pending.iter().chain(self.undo_stack.iter().rev()).collect(),
+        );
+        let redo = recent(self.redo_stack.iter().rev().collect());
+        Contents {
+            pid: std::process::id(),
+            text,
+            undo,
+            redo,
+        }
+    }
+
+    pub fn recover(&mut self, contents: Contents) {
+        // Takes the text and undo steps kept in a swap file, which are
+        // unsaved changes as far as the file is concerned
+        let rows = |lines: Vec<String>| -> Vec<Row> {
+            lines.iter().map(|line| Row::from(line.as_str())).collect()
+        };
+        self.rows = rows(contents.text);
+        // Every step is the change from the text after it (the next step,
+        // or the current text for the last one)
+        let current = self.rows.clone();
+        let changes = |steps: Vec<(Vec<String>, Position)>| {
+            let mut after = current.clone();
+            let mut changes: VecDeque<Change> = steps
+                .into_iter()
+                .rev()
+                .map(|(text, cursor)| {
+                    let before = rows(text);
+                    let change = Change::between(&after, &before, cursor);
+                    after = before;
+                    change
+                })
+                .collect();
+            changes.make_contiguous().reverse();
+            changes
+        };
+        self.undo_stack = changes(contents.undo);
+        self.redo_stack = changes(contents.redo);
+        self.before = None;
+        self.touch();
+    }
+
+    pub fn checkpoint(&mut self, cursor: &Position) {
+        // Saves the current state to make an undo step of, must be called
+        // before every change. The cursor is where it should go back to
+        // when the change is undone
+        if self.in_change {
+            return;
+        }
+        self.commit();
+        // Marks only matter while :global runs, a step that kept them
+        // would bring them back when undone
+        let rows = self
+            .rows
+            .iter()
+            .map(|row| {
+                let mut row = row.clone();
+                row.set_marked(false);
+                row
+            })
+            .collect();
+        self.before = Some((rows, cursor.clone()));
+        self.redo_stack.clear();
+    }
+
+    fn commit(&mut self) {
+        // Turns the change since the last checkpoint into an undo step, if
+        // it changed anything (e.g. entering and leaving insert mode
+        // doesn't)
+        let Some((rows, cursor)) = self.before.take() else {
+            return;
+        };
+        let change = Change::between(&self.rows, &rows, cursor);
+        if change.is_empty() {
+            return;
+        }
+        if self.undo_stack.len() >= UNDO_LEVELS {
+            self.undo_stack.pop_front();
+        }
+        self.undo_stack.push_back(change);
+    }
+
+    pub fn undo(&mut self, cursor: &Position) -> Option<Position> {
+        // Returns where the cursor should be after undoing, or None when
+        // there is nothing left to undo
+        self.commit();
+        Self::swap_change(
+            &mut self.rows,
+            &mut self.undo_stack,
+            &mut self.redo_stack,
+            cursor,
+        )
+        .inspect(|_| self.touch())
+    }
+
+    pub fn redo(&mut self, cursor: &Position) -> Option<Position> {
+        self.commit();
+        Self::swap_change(
+            &mut self.rows,
+            &mut self.redo_stack,
+            &mut self.undo_stack,
+            cursor,
+        )
+        .inspect(|_| self.touch())
+    }
+
+    fn swap_change(
+        rows: &mut Vec<Row>,
+        from: &mut VecDeque<Change>,
+        to: &mut VecDeque<Change>,
+        cursor: &Position,
+    ) -> Option<Position> {
+        // Steps that change nothing (as recovered ones can) are skipped
+        while let Some(change) = from.pop_back() {
+            if change.is_empty() {
+                continue;
+            }
+            let (position, inverse) = change.apply(rows, cursor);
+            to.push_back(inverse);
+            return Some(position);
+        }
+        None
+    }
+
+    #[must_use]
+    pub fn text(&self) -> String {
+        // Whole document as a single string, rows joined by '\n'
+        let rows: Vec<&str> = self.rows.iter().map(Row::as_str).collect();
+        rows.join("\n")
+    }
+
+    #[must_use]
+    pub fn offset_of(&self, at: &Position) -> usize {
+        // Byte offset of `at` into the string returned by text()
+        let mut offset: usize = 0;
+        for row in self.rows.iter().take(at.y) {
+            offset =
+                offset.saturating_add(row.as_str().len()).saturating_add(1);
+        }
+        match self.rows.get(at.y) {
+            Some(row) => offset.saturating_add(row.byte_index(at.x)),
+            None => offset.saturating_sub(1),
+        }
+    }
+
+    #[must_use]
+    pub fn position_of(&self, offset: usize) -> Position {
+        // Inverse of offset_of(), a '\n' maps to the end of its row
+        let mut start: usize = 0;
+        for (y, row) in self.rows.iter().enumerate() {
+            let end = start.saturating_add(row.as_str().len());
+            if offset <= end {
+                return Position {
+                    x: row.grapheme_index(offset.saturating_sub(start)),
+                    y,
+                };
+            }
+            start = end.saturating_add(1);
+        }
+        let y = self.rows.len().saturating_sub(1);
+        Position {
+            x: self.row_len(y).unwrap_or(0),
+            y,
+        }
+    }
+
+    pub fn mark_line(&mut self, at: usize) {
+        if let Some(row) = self.rows.get_mut(at) {
+            row.set_marked(true);
+        }
+    }
+
+    pub fn take_marked_line(&mut self, from: usize) -> Option<usize> {
+        // Unmarks the first marked line from line `from` on and returns its
+        // current index
+        let index = self
+            .rows
+            .iter()
+            .skip(from)
+            .position(Row::is_marked)?
+            .saturating_add(from);
+        self.rows.get_mut(index)?.set_marked(false);
+        Some(index)
+    }
+
+    pub fn clear_marks(&mut self) {
+        for row in &mut self.rows {
+            row.set_marked(false);
+        }
+    }
+
+    pub fn begin_change(&mut self, cursor: &Position) {
+        // Takes a single checkpoint for every change made until
+        // end_change(), which are then undone as one
+        self.checkpoint(cursor);
+        self.in_change = true;
+    }
+
+    pub fn end_change(&mut self) {
+        self.in_change = false;
     }
 
     #[must_use]
@@ -196,7 +804,7 @@ impl Document {
     }
 
     pub fn is_out_of_bounds(&self, at: &Position) -> bool {
-        return at.y >= self.len() || at.x >= self.row_len(at.y).unwrap();
+        self.row_len(at.y).is_none_or(|len| at.x >= len)
     }
 
     #[must_use]
@@ -231,8 +839,11 @@ impl PartialEq for Document {
 
 #[cfg(test)]
 mod tests {
+    use super::UNDO_LEVELS;
     use crate::Document;
     use crate::Position;
+    use std::fs::{self, File};
+    use std::time::{Duration, SystemTime};
 
     #[test]
     fn test_insert_empty() {
@@ -281,4 +892,78 @@ mod tests {
         doc.delete(&Position { x: 12, y: 0 });
         assert_eq!(doc_test, doc);
     }
+
+    #[test]
+    fn test_undo_redo() {
+        let mut doc = Document::open("./tests/2.in").unwrap();
+        let original = Document::open("./tests/2.in").unwrap();
+        let doc_test = Document::open("./tests/2.out").unwrap();
+        let cursor = Position { x: 10, y: 2 };
+        doc.checkpoint(&cursor);
+        doc.insert(&cursor, 'k');
+        assert_eq!(doc.undo(&cursor), Some(cursor.clone()));
+        assert_eq!(original, doc);
+        assert_eq!(doc.undo(&cursor), None);
+        assert_eq!(doc.redo(&cursor), Some(cursor));
+        assert_eq!(doc_test, doc);
+    }
+
+    #[test]
+    fn test_undo_steps() {
+        // Steps only keep the lines that changed, and go through the swap
+        // file as the whole texts they go back to
+        let mut doc = Document::default();
+        doc.set_text(&"line\n".repeat(1000));
+        let cursor = Position { x: 0, y: 500 };
+        for _ in 0..UNDO_LEVELS.saturating_add(10) {
+            doc.checkpoint(&cursor);
+            doc.insert(&cursor, 'a');
+        }
+        doc.checkpoint(&cursor);
+        doc.delete_line(2);
+        doc.commit();
+        assert_eq!(doc.undo_stack.len(), UNDO_LEVELS);
+        assert!(doc.undo_stack.iter().all(|change| change.rows.len() == 1));
+
+        let text = doc.text();
+        let mut recovered = Document::default();
+        recovered.recover(doc.swap_contents());
+        assert_eq!(recovered.text(), text);
+        for _ in 0..3 {
+            assert_eq!(recovered.undo(&cursor), doc.undo(&cursor));
+            assert_eq!(recovered.text(), doc.text());
+        }
+        assert!(doc
+            .text()
+            .contains(&"a".repeat(UNDO_LEVELS.saturating_add(8))));
+        assert_eq!(recovered.redo(&cursor), doc.redo(&cursor));
+        assert_eq!(recovered.text(), doc.text());
+    }
+
+    #[test]
+    fn test_disk_change() {
+        let file = std::env::temp_dir()
+            .join(format!("mtx-document-{}.txt", std::process::id()));
+        fs::write(&file, "a\nb\n").unwrap();
+        let mut doc = Document::open(&file.to_string_lossy()).unwrap();
+        assert_eq!(doc.disk_change(), None);
+        // Only touching the file doesn't count
+        let later = SystemTime::now() + Duration::from_secs(10);
+        File::options()
+            .write(true)
+            .open(&file)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        assert_eq!(doc.disk_change(), None);
+        fs::write(&file, "a\nb\nc\n").unwrap();
+        assert!(doc.disk_change().is_some());
+        let cursor = Position::default();
+        doc.reload(true, &cursor).unwrap();
+        assert_eq!(doc.text(), "a\nb\nc");
+        assert_eq!(doc.disk_change(), None);
+        assert!(doc.undo(&cursor).is_some());
+        assert_eq!(doc.text(), "a\nb");
+        fs::remove_file(&file).unwrap();
+    }
 }
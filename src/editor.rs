@@ -1,16 +1,58 @@
-use crate::commands::{cursor_cmds, edit_cmds, visual_cmds};
+#[cfg(test)]
+use crate::backend::MemoryBackend;
+use crate::backend::{FOCUS_IN, PASTE_END, PASTE_START};
+use crate::buffer::Buffers;
+use crate::commands::{
+    cursor_cmds, edit_cmds, search_cmds, substitute_cmds, visual_cmds,
+};
+use crate::config::{self, Watcher};
+use crate::encoding::{self, Encoding};
+use crate::ex::{self, Command, Range, SubstituteFlags};
+use crate::explorer::Changes;
+use crate::fileformat::Layout;
+use crate::finder::{self, Finder, Source};
+use crate::hex::{self, Column, Hex, BYTES_PER_LINE};
+use crate::keymap::{Keymap, Lookup, MapMode, Mapping};
+use crate::keys;
+use crate::options::{self, Opt, Options, Scope, Setting, Value};
+use crate::quickfix::Quickfix;
+use crate::regex::{CaseMode, Regex, Syntax};
+use crate::swap::{self, Contents, Swap};
+use crate::tab::Tabs;
+use crate::view::{self, View};
+use crate::walk;
+use crate::window::{Direction, Rect, Windows};
 use crate::Document;
 use crate::Row;
 use crate::Terminal;
 use std::cmp::Ordering;
+use std::collections::VecDeque;
+use std::convert::TryFrom;
 use std::env;
-use std::time::Instant;
-use termion::color;
-use termion::event::Key;
+use std::fs;
+use std::io::ErrorKind;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use termion::event::{Event, Key, MouseButton, MouseEvent};
+use unicode_segmentation::UnicodeSegmentation;
 
-const STATUS_BG_COLOR: color::Rgb = color::Rgb(75, 75, 75);
-const HIGHLIGHT_BG_COLOR: color::Rgb = color::Rgb(75, 75, 75);
-const STATUS_FG_COLOR: color::Rgb = color::Rgb(200, 200, 200);
+// Most entries the quickfix pane shows at once
+const QUICKFIX_HEIGHT: usize = 10;
+// How often the size of the terminal is checked while waiting for a key
+const RESIZE_POLL: Duration = Duration::from_millis(100);
+// How often swap files are brought up to date with unsaved changes
+const SWAP_INTERVAL: Duration = Duration::from_secs(2);
+// How often open files are checked for changes made outside the editor
+const DISK_POLL: Duration = Duration::from_secs(1);
+// Presses on the same cell closer together than this make double and
+// triple clicks
+const MULTI_CLICK: Duration = Duration::from_millis(400);
+// Lines a window scrolls for every step of the mouse wheel
+const WHEEL_LINES: usize = 3;
+// Mappings that can expand in a row before giving up, as vim's
+// 'maxmapdepth'
+const MAX_MAP_DEPTH: usize = 1000;
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 #[repr(u8)]
@@ -70,8 +112,68 @@ pub struct Editor {
     offset: Position,
     document: Document,
     hl_text: SelectedText,
+    // Highlighted match while confirming a substitution
+    match_hl: Option<SelectedText>,
     status_message: StatusMessage,
     mode: Mode,
+    search: SearchState,
+    quickfix: Quickfix,
+    quickfix_open: bool,
+    buffers: Buffers,
+    windows: Windows,
+    tabs: Tabs,
+    // Open while picking a file
+    finder: Option<Finder>,
+    clicks: Clicks,
+    // Global options, buffer-local ones are in the Document and
+    // window-local ones for the current window are next
+    options: Options,
+    window_options: Options,
+    keymap: Keymap,
+    // The config files, read again when they change
+    config: Watcher,
+    // When the swap files were last brought up to date, and the open
+    // files last checked for changes
+    swapped_at: Instant,
+    checked_at: Instant,
+    // Keys to be read before the terminal's, the rest of a mapping or of
+    // keys that turned out not to be one. The flag is whether mappings
+    // still apply to the key
+    typeahead: VecDeque<(Key, bool)>,
+}
+
+#[derive(Default)]
+struct Clicks {
+    // When and where the left button was last pressed, and how many
+    // presses in a row landed on that cell
+    last: Option<(Instant, u16, u16)>,
+    count: usize,
+    // Where a press on the text left the cursor, dragging from there
+    // selects while the button is held
+    anchor: Option<Position>,
+}
+
+struct SearchState {
+    last_pattern: Option<String>,
+    forward: bool,
+    syntax: Syntax,
+}
+
+impl Default for SearchState {
+    fn default() -> Self {
+        Self {
+            last_pattern: None,
+            forward: true,
+            syntax: Syntax::Vim,
+        }
+    }
+}
+
+impl Default for Editor {
+    fn default() -> Self {
+        let args: Vec<String> = env::args().collect();
+        Self::new(Terminal::default(), args.get(1..).unwrap_or_default())
+    }
 }
 
 impl Editor {
@@ -81,58 +183,518 @@ impl Editor {
                 die(&error);
             }
             if self.should_quit {
+                self.remove_swaps();
                 break;
             }
+            if let Err(error) = self.wait_for_key() {
+                die(&error);
+            }
             if let Err(error) = self.process_keypress() {
                 die(&error);
             }
+            self.sync_swaps();
         }
     }
 
-    pub fn default() -> Self {
-        let args: Vec<String> = env::args().collect();
+    fn wait_for_key(&mut self) -> Result<(), std::io::Error> {
+        // Terminals don't tell when they are resized, nor files when they
+        // change, so the size and the config files are checked every now
+        // and then until a key comes in
+        while self.typeahead.is_empty() && !self.terminal.poll(RESIZE_POLL)? {
+            self.resize()?;
+            self.sync_swaps();
+            if self.checked_at.elapsed() >= DISK_POLL {
+                self.check_files();
+                self.refresh_screen()?;
+            }
+            if self.config.changed() {
+                self.reload_config();
+                self.refresh_screen()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn resize(&mut self) -> Result<(), std::io::Error> {
+        if !self.terminal.resize()? {
+            return Ok(());
+        }
+        // Every window keeps its cursor in view in its new size
+        self.scroll();
+        let current = self.windows.current();
+        for (id, rect) in self.windows.layout(self.window_area()) {
+            if id == current {
+                continue;
+            }
+            let Some(window) = self.windows.get(id) else {
+                continue;
+            };
+            let document = self.window_document(window.buffer);
+            let mut offset = window.offset.clone();
+            self.view_of(document, &window.options, &rect).scroll(
+                document,
+                &window.cursor_position,
+                &mut offset,
+            );
+            if let Some(window) = self.windows.get_mut(id) {
+                window.offset = offset;
+            }
+        }
+        self.refresh_screen()
+    }
+
+    pub fn new(terminal: Terminal, file_names: &[String]) -> Self {
         let mut initial_status = String::from("HELP: Ctrl-Q = quit");
-        let document = if let Some(file_name) = args.get(1) {
-            if let Ok(doc) = Document::open(file_name) {
-                doc
-            } else {
-                initial_status =
-                    format!("Err: could not open file {file_name}");
-                Document::default()
+        let (file_names, encoding) = match parse_args(file_names) {
+            Ok(args) => args,
+            Err(error) => {
+                initial_status = error;
+                (Vec::new(), None)
             }
-        } else {
-            Document::default()
         };
+        // Every file given is opened in its own buffer, the first one
+        // being the current buffer. One that doesn't exist yet gets an empty
+        // buffer, which saving creates it from
+        let mut documents = Vec::new();
+        for file_name in file_names {
+            match Document::open_as(file_name, encoding) {
+                Ok(doc) => documents.push(doc),
+                Err(error) if error.kind() == ErrorKind::NotFound => {
+                    let mut doc = Document::default();
+                    doc.file_name = Some(file_name.clone());
+                    documents.push(doc);
+                }
+                Err(_) => {
+                    initial_status =
+                        format!("Err: could not open file {file_name}");
+                }
+            }
+        }
+        let mut documents = documents.into_iter();
+        let document = documents.next().unwrap_or_default();
+        let mut buffers = Buffers::default();
+        for doc in documents {
+            buffers.add(doc);
+        }
 
-        Self {
+        let mut editor = Self {
             should_quit: false,
-            terminal: Terminal::default(),
+            terminal,
             document,
             cursor_position: Position::default(),
             offset: Position::default(),
             hl_text: SelectedText::default(),
+            match_hl: None,
             status_message: StatusMessage::from(initial_status),
             mode: Mode::Normal,
+            search: SearchState::default(),
+            quickfix: Quickfix::default(),
+            quickfix_open: false,
+            buffers,
+            windows: Windows::default(),
+            tabs: Tabs::default(),
+            finder: None,
+            clicks: Clicks::default(),
+            options: Options::default(),
+            window_options: Options::default(),
+            keymap: Keymap::default(),
+            config: Watcher::new(config::paths()),
+            swapped_at: Instant::now(),
+            checked_at: Instant::now(),
+            typeahead: VecDeque::new(),
+        };
+        editor.load_config();
+        editor.remember_file();
+        editor.check_swap();
+        editor
+    }
+
+    fn config_files(&self) -> Vec<(String, String)> {
+        // The name and text of every config file there is
+        self.config
+            .paths()
+            .iter()
+            .filter_map(|path| {
+                let text = fs::read_to_string(path).ok()?;
+                Some((path.to_string_lossy().into_owned(), text))
+            })
+            .collect()
+    }
+
+    fn load_config(&mut self) {
+        // The first error is shown, the lines after it still run
+        let mut errors = Vec::new();
+        for (name, text) in self.config_files() {
+            errors.extend(self.configure(&name, &text));
+        }
+        if let Some(error) = errors.first() {
+            self.status_message = StatusMessage::from(error.clone());
+        }
+    }
+
+    fn reload_config(&mut self) {
+        let files = self.config_files();
+        self.status_message = match self.reconfigure(&files) {
+            Ok(()) => StatusMessage::from("Config reloaded".to_string()),
+            Err(error) => StatusMessage::from(error),
+        };
+    }
+
+    fn reconfigure(
+        &mut self,
+        files: &[(String, String)],
+    ) -> Result<(), String> {
+        // Runs the config files again from the default options and no
+        // mappings. On an error everything is put back as it was, so a
+        // half written file doesn't undo the last good one
+        let options = mem::take(&mut self.options);
+        let keymap = mem::take(&mut self.keymap);
+        let document_options = self.document.options.clone();
+        let window_options = self.window_options.clone();
+        let mut errors = Vec::new();
+        for (name, text) in files {
+            errors.extend(self.configure(name, text));
+        }
+        if let Some(error) = errors.into_iter().next() {
+            self.options = options;
+            self.keymap = keymap;
+            self.document.options = document_options;
+            self.window_options = window_options;
+            self.scroll();
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    fn configure(&mut self, name: &str, text: &str) -> Vec<String> {
+        // Runs the commands in a config file, returning what went wrong
+        // with the line it happened at
+        let mut errors = Vec::new();
+        for (number, line) in config::commands(text) {
+            let result = match ex::parse(line) {
+                Ok(Command::Set { args, local }) => self.set(&args, local),
+                Ok(Command::Map {
+                    modes,
+                    lhs: Some(lhs),
+                    rhs: Some(rhs),
+                    noremap,
+                }) => self.map(&modes, &lhs, &rhs, noremap),
+                Ok(Command::Unmap { modes, lhs }) => self.unmap(&modes, &lhs),
+                Ok(_) => Err(format!("Not allowed in {name}: {line}")),
+                Err(error) => Err(error),
+            };
+            if let Err(error) = result {
+                errors.push(format!("{name}:{number}: {error}"));
+            }
+        }
+        errors
+    }
+
+    fn check_swap(&mut self) {
+        // Looks for a swap file the first time a named buffer is shown,
+        // asking what to do with one that is already there
+        if self.document.swap != Swap::Unchecked {
+            return;
+        }
+        let Some(file_name) = self.document.file_name.clone() else {
+            return;
+        };
+        if self.document.listing().is_some() {
+            self.document.swap = Swap::Disabled;
+            return;
+        }
+        let path = swap::path(Path::new(&file_name));
+        self.document.swap = Swap::File {
+            path: path.clone(),
+            written: None,
+        };
+        if !path.exists() {
+            return;
+        }
+        let contents = fs::read_to_string(&path)
+            .map_err(|error| error.to_string())
+            .and_then(|text| Contents::decode(&text));
+        let owner = match &contents {
+            Ok(contents) if swap::is_running(contents.pid) => format!(
+                "It belongs to mtx (pid {}), which is still running",
+                contents.pid
+            ),
+            Ok(contents) => format!(
+                "It was left by mtx (pid {}), which is no longer running",
+                contents.pid
+            ),
+            Err(error) => format!("It can't be recovered: {error}"),
+        };
+        let lines = vec![
+            format!("Found a swap file for {file_name}: {}", path.display()),
+            owner,
+        ];
+        let mut contents = contents.ok();
+        loop {
+            let key = match self.show_lines(
+                &lines,
+                "[r]ecover, [o]pen read-only, [d]elete it: ",
+            ) {
+                Ok(key) => key,
+                Err(error) => die(&error),
+            };
+            match key {
+                Key::Char('r') => {
+                    let Some(contents) = contents.take() else {
+                        continue;
+                    };
+                    self.document.recover(contents);
+                    // The swap file is ours from now on
+                    self.status_message = match swap::sync(&mut self.document)
+                    {
+                        Ok(()) => StatusMessage::from(format!(
+                            "Recovered {file_name}, save it to keep the changes"
+                        )),
+                        Err(error) => StatusMessage::from(format!(
+                            "Recovered {file_name}, but can't write {}: {error}",
+                            path.display()
+                        )),
+                    };
+                }
+                Key::Char('o') | Key::Esc | Key::Ctrl('c') => {
+                    self.document.read_only = true;
+                    self.document.swap = Swap::Disabled;
+                    self.status_message = StatusMessage::from(format!(
+                        "Opened {file_name} read-only"
+                    ));
+                }
+                Key::Char('d') => {
+                    self.status_message = match swap::remove(&path) {
+                        Ok(()) => StatusMessage::from(self.file_info()),
+                        Err(error) => StatusMessage::from(format!(
+                            "Can't delete {}: {error}",
+                            path.display()
+                        )),
+                    };
+                }
+                _ => continue,
+            }
+            return;
+        }
+    }
+
+    fn check_files(&mut self) {
+        // Buffers without unsaved changes are read again when their file
+        // changes, the current one asks first if it has some. A file that
+        // can't be read keeps the stamp it had, so it is tried again on the
+        // next check
+        self.checked_at = Instant::now();
+        for index in 0..self.buffers.len() {
+            if let Some(buffer) = self.buffers.get_mut(index) {
+                let document = &mut buffer.document;
+                if !document.is_dirty() && document.disk_change().is_some() {
+                    let cursor = buffer.cursor_position.clone();
+                    if let Err(error) = document.reload(false, &cursor) {
+                        let name =
+                            document.file_name.clone().unwrap_or_default();
+                        self.status_message = StatusMessage::from(format!(
+                            "Can't read {name}: {error}"
+                        ));
+                    }
+                }
+            }
+        }
+        self.check_file(false);
+    }
+
+    fn check_file(&mut self, saving: bool) -> bool {
+        // Whether the current buffer can go on being edited or, when
+        // `saving`, be written to its file, which the user is asked about
+        // when someone else changed the file meanwhile
+        let Some(stamp) = self.document.disk_change() else {
+            return true;
+        };
+        let name = self.document.file_name.clone().unwrap_or_default();
+        if !saving {
+            if !self.document.is_dirty() {
+                self.reload(false);
+                self.status_message = StatusMessage::from(format!(
+                    "{name} changed on disk, reloaded"
+                ));
+                return true;
+            }
+            if self.document.dismissed.as_ref() == Some(&stamp) {
+                return true;
+            }
+        }
+        let own = if self.document.is_dirty() {
+            ", and the buffer has changes of its own"
+        } else {
+            ""
+        };
+        let lines =
+            vec![format!("{name} changed on disk since it was read{own}")];
+        let prompt = if saving {
+            "[r]eload, reload and [k]eep undo, [o]verwrite, Esc to cancel: "
+        } else {
+            "[r]eload, reload and [k]eep undo, [o]verwrite, Esc to go on: "
+        };
+        loop {
+            let key = match self.show_lines(&lines, prompt) {
+                Ok(key) => key,
+                Err(error) => die(&error),
+            };
+            match key {
+                Key::Char(c @ ('r' | 'k')) => {
+                    self.reload(c == 'k');
+                    return false;
+                }
+                Key::Char('o') => {
+                    // What is on disk now is what gets replaced
+                    self.document.disk = Some(stamp);
+                    if !saving {
+                        self.save(false);
+                    }
+                    return saving;
+                }
+                Key::Esc | Key::Ctrl('c') => {
+                    self.status_message = if saving {
+                        StatusMessage::from("Save aborted".to_string())
+                    } else {
+                        StatusMessage::from(String::new())
+                    };
+                    self.document.dismissed = Some(stamp);
+                    return !saving;
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn reload(&mut self, keep_undo: bool) {
+        let name = self.document.file_name.clone().unwrap_or_default();
+        match self.document.reload(keep_undo, &self.cursor_position) {
+            Ok(()) => {
+                self.status_message =
+                    StatusMessage::from(format!("{name} reloaded"));
+            }
+            Err(error) => {
+                self.status_message =
+                    StatusMessage::from(format!("Can't read {name}: {error}"));
+            }
+        }
+        self.hl_text = SelectedText::default();
+        self.match_hl = None;
+        cursor_cmds::update_cursor(
+            &mut self.cursor_position,
+            &self.document,
+            &self.mode,
+        );
+        self.scroll();
+    }
+
+    fn sync_swaps(&mut self) {
+        // Every buffer's swap file gets its unsaved changes, but only every
+        // SWAP_INTERVAL so typing doesn't wait for the disk
+        if self.swapped_at.elapsed() < SWAP_INTERVAL {
+            return;
+        }
+        self.swapped_at = Instant::now();
+        let sync = |document: &mut Document| {
+            // Reported once, the buffer goes on without one
+            swap::sync(document).inspect_err(|_| {
+                document.swap = Swap::Disabled;
+            })
+        };
+        let mut errors = Vec::new();
+        errors.extend(sync(&mut self.document).err());
+        for index in 0..self.buffers.len() {
+            if let Some(buffer) = self.buffers.get_mut(index) {
+                errors.extend(sync(&mut buffer.document).err());
+            }
+        }
+        if let Some(error) = errors.first() {
+            self.status_message =
+                StatusMessage::from(format!("Can't write swap file: {error}"));
+        }
+    }
+
+    fn remove_swaps(&mut self) {
+        swap::discard(&mut self.document);
+        for index in 0..self.buffers.len() {
+            if let Some(buffer) = self.buffers.get_mut(index) {
+                swap::discard(&mut buffer.document);
+            }
         }
     }
 
     fn refresh_screen(&self) -> Result<(), std::io::Error> {
-        Terminal::cursor_hide();
-        Terminal::cursor_position(&Position::default());
+        self.terminal.cursor_position(&Position::default());
         if self.should_quit {
-            Terminal::clear_screen();
+            self.terminal.clear_screen()?;
         } else {
-            self.draw_rows();
-            self.draw_status_bar();
+            // Every frame is drawn from scratch, flush() works out which
+            // parts of it actually changed
+            self.terminal.clear();
+            self.draw_tab_line();
+            self.draw_windows();
+            if self.quickfix_open {
+                self.draw_quickfix();
+            }
+            // The finder is drawn over everything else
+            if let Some(finder) = &self.finder {
+                self.draw_finder(finder);
+            }
             self.draw_message_bar();
             self.draw_cursor();
         }
-        Terminal::cursor_show();
-        Terminal::flush()
+        self.terminal.flush()
     }
 
     fn process_keypress(&mut self) -> Result<(), std::io::Error> {
-        let pressed_key = Terminal::read_key()?;
+        // Keys left from a mapping go first, otherwise the terminal may
+        // have something other than a key
+        let first = if self.typeahead.is_empty() {
+            Some(self.terminal.read_event()?)
+        } else {
+            None
+        };
+        let first = match first {
+            None => None,
+            Some(Event::Key(key)) => Some(key),
+            Some(Event::Mouse(event)) => {
+                self.mouse(event);
+                self.snap_hex();
+                self.scroll();
+                return Ok(());
+            }
+            Some(Event::Unsupported(bytes)) if bytes == PASTE_START => {
+                let text = self.read_paste()?;
+                self.paste(&text);
+                self.scroll();
+                return Ok(());
+            }
+            Some(Event::Unsupported(bytes)) if bytes == FOCUS_IN => {
+                // Coming back from elsewhere, where the files may have
+                // been changed
+                self.check_files();
+                return Ok(());
+            }
+            Some(Event::Unsupported(_)) => return Ok(()),
+        };
+        let mode = match self.mode {
+            Mode::Normal => MapMode::Normal,
+            Mode::Insert => MapMode::Insert,
+            Mode::Visual => MapMode::Visual,
+            Mode::Command => MapMode::CommandLine,
+        };
+        let Some(pressed_key) = self.read_mapped(mode, first)? else {
+            return Ok(());
+        };
+        let leader = self.option(Opt::Leader).key();
+        let text_height = self.text_height();
+        if self.document.hex().is_some()
+            && self.hex_keypress(pressed_key, leader)?
+        {
+            self.snap_hex();
+            self.scroll();
+            return Ok(());
+        }
         match self.mode {
             Mode::Normal => match pressed_key {
                 // Moving around
@@ -164,30 +726,39 @@ impl Editor {
                 Key::Ctrl('d') => cursor_cmds::move_page_up(
                     &mut self.cursor_position,
                     &mut self.offset,
-                    self.terminal.size().height as usize,
+                    text_height,
                 ),
                 Key::Ctrl('u') => cursor_cmds::move_page_down(
                     &mut self.cursor_position,
                     &mut self.offset,
                     &self.document,
-                    self.terminal.size().height as usize,
+                    text_height,
                 ),
 
                 // Edit commands
+                Key::Char('x' | 'd' | 'D' | 'J' | 'o' | 'O' | 'i' | 'a')
+                    if self.refuse_read_only() => {}
                 Key::Char('x') => {
+                    self.document.checkpoint(&self.cursor_position);
                     edit_cmds::delete(
                         &self.cursor_position,
                         &mut self.document,
                     );
                 }
-                Key::Char('d') => match Terminal::read_key()? {
-                    Key::Char('d') => edit_cmds::delete_line(
-                        &mut self.cursor_position,
-                        &mut self.document,
-                    ),
-                    _ => (),
-                },
+                Key::Char('d') => {
+                    match self.read_mapped(MapMode::OperatorPending, None)? {
+                        Some(Key::Char('d')) => {
+                            self.document.checkpoint(&self.cursor_position);
+                            edit_cmds::delete_line(
+                                &mut self.cursor_position,
+                                &mut self.document,
+                            );
+                        }
+                        _ => (),
+                    }
+                }
                 Key::Char('D') => {
+                    self.document.checkpoint(&self.cursor_position);
                     edit_cmds::delete_until_eol(
                         &mut self.cursor_position,
                         &mut self.document,
@@ -199,12 +770,14 @@ impl Editor {
                     )
                 }
                 Key::Char('J') => {
+                    self.document.checkpoint(&self.cursor_position);
                     edit_cmds::delete_to_eol(
                         &self.cursor_position,
                         &mut self.document,
                     );
                 }
                 Key::Char('o') => {
+                    self.document.checkpoint(&self.cursor_position);
                     edit_cmds::insert_newline_below(
                         &mut self.cursor_position,
                         &mut self.document,
@@ -216,6 +789,7 @@ impl Editor {
                     self.mode = Mode::Insert;
                 }
                 Key::Char('O') => {
+                    self.document.checkpoint(&self.cursor_position);
                     edit_cmds::insert_newline_above(
                         &mut self.cursor_position,
                         &mut self.document,
@@ -223,9 +797,23 @@ impl Editor {
                     cursor_cmds::move_cursor_up(&mut self.cursor_position);
                     self.mode = Mode::Insert;
                 }
+                Key::Char('u') => self.undo(false),
+                Key::Ctrl('r') => self.undo(true),
+                Key::Char('\n') => self.open_entry(),
+                Key::Char('-') => self.open_parent(),
+                Key::Ctrl('w') => self.window_command()?,
+                Key::Char('g') => match self.read_key()? {
+                    Key::Char('t') => self.switch_tab(self.tabs.step(true)),
+                    Key::Char('T') => self.switch_tab(self.tabs.step(false)),
+                    _ => (),
+                },
 
                 // Changing modes
-                Key::Char('i') => self.mode = Mode::Insert,
+                Key::Char('i') => {
+                    // The whole insert session is a single undo step
+                    self.document.checkpoint(&self.cursor_position);
+                    self.mode = Mode::Insert;
+                }
                 Key::Char('v') => {
                     visual_cmds::enter_visual_mode(
                         &self.cursor_position,
@@ -234,6 +822,7 @@ impl Editor {
                     );
                 }
                 Key::Char('a') => {
+                    self.document.checkpoint(&self.cursor_position);
                     cursor_cmds::move_cursor_right(
                         &mut self.cursor_position,
                         &self.document,
@@ -243,10 +832,20 @@ impl Editor {
                     self.mode = Mode::Insert;
                 }
 
+                // Search
+                Key::Char('/') => self.search(true),
+                Key::Char('?') => self.search(false),
+                Key::Char('n') => self.search_next(false),
+                Key::Char('N') => self.search_next(true),
+
                 // Misc
-                Key::Char(' ') => match Terminal::read_key()? {
+                Key::Char(':') => self.command_line("")?,
+                key if key == leader => match self.read_key()? {
                     Key::Char('s') => self.save(false),
                     Key::Char('w') => self.save(true),
+                    Key::Char('f') => self.find(Source::Files)?,
+                    Key::Char('b') => self.find(Source::Buffers)?,
+                    Key::Char('r') => self.find(Source::Recent)?,
                     _ => (),
                 },
                 _ => (),
@@ -321,13 +920,13 @@ impl Editor {
                 Key::Ctrl('d') => cursor_cmds::move_page_up(
                     &mut self.cursor_position,
                     &mut self.offset,
-                    self.terminal.size().height as usize,
+                    text_height,
                 ),
                 Key::Ctrl('u') => cursor_cmds::move_page_down(
                     &mut self.cursor_position,
                     &mut self.offset,
                     &self.document,
-                    self.terminal.size().height as usize,
+                    text_height,
                 ),
                 _ => (),
             },
@@ -335,7 +934,9 @@ impl Editor {
                 Key::Ctrl('q') => self.should_quit = true,
                 Key::Char('v') => self.mode = Mode::Normal,
 
+                Key::Char('x') if self.refuse_read_only() => {}
                 Key::Char('x') => {
+                    self.document.checkpoint(&self.cursor_position);
                     edit_cmds::delete_selection(
                         &self.hl_text.start,
                         &self.hl_text.end,
@@ -351,6 +952,10 @@ impl Editor {
                 }
 
                 Key::Ctrl('c') => self.mode = Mode::Normal,
+                Key::Char(':') => {
+                    self.mode = Mode::Normal;
+                    self.command_line("'<,'>")?;
+                }
                 Key::Char('h') | Key::Left | Key::Backspace => {
                     cursor_cmds::move_cursor_left(
                         &mut self.cursor_position,
@@ -399,7 +1004,7 @@ impl Editor {
                     cursor_cmds::move_page_up(
                         &mut self.cursor_position,
                         &mut self.offset,
-                        self.terminal.size().height as usize,
+                        text_height,
                     );
                     visual_cmds::update_selection(
                         &self.cursor_position,
@@ -412,7 +1017,7 @@ impl Editor {
                         &mut self.cursor_position,
                         &mut self.offset,
                         &self.document,
-                        self.terminal.size().height as usize,
+                        text_height,
                     );
                     visual_cmds::update_selection(
                         &self.cursor_position,
@@ -425,10 +1030,141 @@ impl Editor {
             Mode::Command => {}
         }
 
+        self.snap_hex();
         self.scroll();
         Ok(())
     }
 
+    fn hex_keypress(
+        &mut self,
+        key: Key,
+        leader: Key,
+    ) -> Result<bool, std::io::Error> {
+        // Keys that work differently in hex mode, where the cursor moves
+        // over bytes and insert mode overwrites them. Returns false for the
+        // keys that do the same as in text, and keys that would edit the
+        // rows as text do nothing
+        let Some(hex) = self.document.hex() else {
+            return Ok(false);
+        };
+        let at = self.cursor_position.clone();
+        let insert = self.mode == Mode::Insert;
+        match key {
+            Key::Ctrl('q' | 'w' | 'd' | 'u') => return Ok(false),
+            Key::Ctrl('c' | 's') if insert => return Ok(false),
+            Key::Esc if insert => self.mode = Mode::Normal,
+            Key::Char(':' | '/' | '?' | 'n' | 'N' | 'g') if !insert => {
+                return Ok(false);
+            }
+            key if key == leader && !insert => return Ok(false),
+            Key::Char('\t') => self.cursor_position = hex.toggle(&at),
+            Key::Char(c) if insert => self.overwrite_byte(c, true),
+            Key::Left | Key::Backspace | Key::Char('h') => {
+                self.cursor_position = hex.step(&at, false);
+            }
+            Key::Right | Key::Char('l') => {
+                self.cursor_position = hex.step(&at, true);
+            }
+            Key::Up | Key::Char('k') => {
+                self.cursor_position.y = at.y.saturating_sub(1);
+            }
+            Key::Down | Key::Char('j') => {
+                self.cursor_position.y = at.y.saturating_add(1);
+            }
+            Key::Char('0') => self.cursor_position.x = 0,
+            Key::Char('$') => {
+                let column = match hex.cell(&at) {
+                    Some((_, Column::Ascii)) => Column::Ascii,
+                    _ => Column::Low,
+                };
+                self.cursor_position.x =
+                    Hex::position(BYTES_PER_LINE - 1, column).x;
+            }
+            Key::Char('G') => self.cursor_position.y = usize::MAX,
+            Key::Char('r') => {
+                if let Key::Char(c) = self.read_key()? {
+                    self.overwrite_byte(c, false);
+                }
+            }
+            Key::Char('i' | 'a' | 'R') => self.mode = Mode::Insert,
+            _ => (),
+        }
+        Ok(true)
+    }
+
+    fn overwrite_byte(&mut self, c: char, advance: bool) {
+        if self.refuse_read_only() {
+            return;
+        }
+        match self.document.overwrite_byte(&self.cursor_position, c) {
+            Some(next) if advance => self.cursor_position = next,
+            Some(_) => (),
+            None => {
+                self.status_message = StatusMessage::from(
+                    "Only hex digits go there, or ASCII in the text column"
+                        .to_string(),
+                );
+            }
+        }
+    }
+
+    fn snap_hex(&mut self) {
+        // Keeps the cursor on a byte in hex mode, which has no visual mode
+        if let Some(hex) = self.document.hex() {
+            self.cursor_position = hex.snap(&self.cursor_position);
+            if self.mode == Mode::Visual {
+                self.mode = Mode::Normal;
+            }
+        }
+    }
+
+    fn toggle_hex(&mut self) {
+        // The cursor stays on the same byte, as far as the text tells
+        let encoding = self.option(Opt::FileEncoding).encoding();
+        if let Some(hex) = self.document.hex() {
+            let offset = hex
+                .cell(&self.cursor_position)
+                .map_or(0, |(offset, _)| offset);
+            self.document.leave_hex(encoding);
+            self.cursor_position = self.document.position_of(offset);
+            cursor_cmds::update_cursor(
+                &mut self.cursor_position,
+                &self.document,
+                &self.mode,
+            );
+            return;
+        }
+        let offset = self.document.offset_of(&self.cursor_position);
+        match self
+            .document
+            .enter_hex(self.layout(), &self.cursor_position)
+        {
+            Ok(()) => {
+                self.cursor_position = Hex::position(offset, Column::High);
+                self.snap_hex();
+            }
+            Err(error) => self.status_message = StatusMessage::from(error),
+        }
+    }
+
+    fn goto_offset(&mut self, offset: usize) {
+        if let Some(hex) = self.document.hex() {
+            let column = match hex.cell(&self.cursor_position) {
+                Some((_, Column::Ascii)) => Column::Ascii,
+                _ => Column::High,
+            };
+            self.cursor_position = Hex::position(offset, column);
+            self.snap_hex();
+        } else {
+            self.cursor_position = self.document.position_of(offset);
+            cursor_cmds::update_cursor(
+                &mut self.cursor_position,
+                &self.document,
+                &self.mode,
+            );
+        }
+    }
+
     fn save(&mut self, save_as: bool) {
         // Currently the file_name is directly attached
         // to the file that it is saved to, maybe provide an option
@@ -436,6 +1172,12 @@ impl Editor {
         // file_name
         let mut arg = None;
 
+        if self.document.listing().is_some() {
+            if let Err(error) = self.save_listing() {
+                die(&error);
+            }
+            return;
+        }
         if self.document.file_name.is_none() {
             let new_name = self.prompt("Save as: ").unwrap_or(None);
             if new_name.is_none() {
@@ -454,116 +1196,2099 @@ impl Editor {
             arg = name;
         }
 
-        if self.document.save(arg).is_ok() {
-            self.status_message = StatusMessage::from(format!(
-                "{} written",
-                self.document.file_name.clone().unwrap()
-            ));
-        } else {
-            self.status_message =
-                StatusMessage::from("Error writing file".to_string());
+        if arg.is_none() && !self.check_file(true) {
+            return;
+        }
+        let backup = self.option(Opt::Backup).flag();
+        match self.document.save(arg, self.layout(), backup) {
+            Ok(()) => {
+                self.status_message = StatusMessage::from(format!(
+                    "{} written",
+                    self.document.file_name.clone().unwrap()
+                ));
+                // A buffer that just got its name gets a swap file too
+                self.check_swap();
+            }
+            // The file on disk is left as it was
+            Err(error) => {
+                self.status_message =
+                    StatusMessage::from(format!("Error writing file: {error}"));
+            }
         }
     }
 
-    fn scroll(&mut self) {
-        let Position { x, y } = self.cursor_position;
-        let width = self.terminal.size().width as usize;
-        let height = self.terminal.size().height as usize;
-        let offset = &mut self.offset;
-        if y < offset.y {
-            offset.y = y;
-        } else if y >= offset.y.saturating_add(height) {
-            offset.y = y.saturating_sub(height).saturating_add(1);
-        }
-        if x < offset.x {
-            offset.x = x;
-        } else if x >= offset.x.saturating_add(width) {
-            offset.x = x.saturating_sub(width).saturating_add(1);
+    fn search(&mut self, forward: bool) {
+        let prompt = if forward { "/" } else { "?" };
+        match self.prompt(prompt) {
+            Ok(Some(pattern)) => {
+                self.search.last_pattern = Some(pattern);
+                self.search.forward = forward;
+                self.search_next(false);
+            }
+            Ok(None) => (),
+            Err(error) => die(&error),
         }
     }
 
-    fn prompt(
-        &mut self,
-        prompt: &str,
-    ) -> Result<Option<String>, std::io::Error> {
-        // Is there a way to not have to add a
-        // self.mode = insert before every return?
-        // maybe a wrapper for command line prompts?
-
-        let last_mode = self.mode.clone();
-        self.mode = Mode::Command;
-        let mut result = String::new();
-        loop {
+    fn search_next(&mut self, reverse: bool) {
+        let Some(pattern) = self.search.last_pattern.clone() else {
             self.status_message =
-                StatusMessage::from(format!("{prompt}{result}"));
-            self.refresh_screen()?;
-            match Terminal::read_key()? {
-                Key::Backspace => {
-                    result.truncate(result.len().saturating_sub(1))
-                }
-                Key::Char('\n') => break,
-                Key::Ctrl('q') => {
-                    result.truncate(0);
-                    self.mode = last_mode.clone();
-                    break;
-                }
-                Key::Char(c) => {
-                    if !c.is_control() {
-                        result.push(c);
+                StatusMessage::from("No previous search pattern".to_string());
+            return;
+        };
+        let forward = self.search.forward != reverse;
+        if self.document.hex().is_some() {
+            self.search_bytes(&pattern, forward);
+            return;
+        }
+        let found = Regex::new(&pattern, self.search.syntax, self.case_mode())
+            .and_then(|regex| {
+                search_cmds::find_next(
+                    &self.cursor_position,
+                    &self.document,
+                    &regex,
+                    forward,
+                )
+            });
+        match found {
+            Ok(Some((position, wrapped))) => {
+                self.cursor_position = position;
+                let message = match (wrapped, forward) {
+                    (false, _) => String::new(),
+                    (true, true) => {
+                        "search hit BOTTOM, continuing at TOP".to_string()
                     }
-                }
-                _ => (),
+                    (true, false) => {
+                        "search hit TOP, continuing at BOTTOM".to_string()
+                    }
+                };
+                self.status_message = StatusMessage::from(message);
+            }
+            Ok(None) => {
+                self.status_message = StatusMessage::from(format!(
+                    "Pattern not found: {pattern}"
+                ));
+            }
+            Err(error) => {
+                self.status_message = StatusMessage::from(error.to_string());
             }
         }
-        self.status_message = StatusMessage::from(String::new());
-        if result.is_empty() {
-            self.mode = last_mode;
-            return Ok(None);
-        }
-        self.mode = last_mode;
-        Ok(Some(result))
     }
 
-    fn draw_cursor(&self) {
-        match self.mode {
-            Mode::Insert | Mode::Normal | Mode::Visual => {
-                let Position { x, y } = self.cursor_position;
-                let width = if let Some(row) = self.document.row(y) {
-                    row.len()
-                } else {
-                    0
-                };
-                if x > width {
-                    Terminal::cursor_position(&Position {
-                        x: width.saturating_sub(self.offset.x),
-                        y: y.saturating_sub(self.offset.y),
-                    });
-                } else {
-                    Terminal::cursor_position(&Position {
-                        x: x.saturating_sub(self.offset.x),
-                        y: y.saturating_sub(self.offset.y),
-                    });
-                }
+    fn search_bytes(&mut self, pattern: &str, forward: bool) {
+        // Hex mode looks for bytes rather than a regex, see hex::pattern()
+        let Some(hex) = self.document.hex() else {
+            return;
+        };
+        let needle = match hex::pattern(pattern) {
+            Ok(needle) => needle,
+            Err(error) => {
+                self.status_message = StatusMessage::from(error);
+                return;
             }
-            Mode::Command => Terminal::cursor_position(&Position {
-                // Since we can't move right or left on command prompt
-                // assume cursor is at the end of string
+        };
+        let (from, column) =
+            hex.cell(&self.cursor_position).unwrap_or((0, Column::High));
+        let column = if column == Column::Ascii {
+            column
+        } else {
+            Column::High
+        };
+        let message = match hex.find(&needle, from, forward) {
+            Some((offset, wrapped)) => {
+                self.cursor_position = Hex::position(offset, column);
+                match (wrapped, forward) {
+                    (false, _) => String::new(),
+                    (true, true) => {
+                        "search hit BOTTOM, continuing at TOP".to_string()
+                    }
+                    (true, false) => {
+                        "search hit TOP, continuing at BOTTOM".to_string()
+                    }
+                }
+            }
+            None => format!("Pattern not found: {pattern}"),
+        };
+        self.status_message = StatusMessage::from(message);
+    }
+
+    fn refuse_read_only(&mut self) -> bool {
+        // Changes to a buffer that can't be saved (see Document::read_only)
+        // are refused before they are made, rather than lost when saving
+        if self.document.read_only {
+            self.status_message =
+                StatusMessage::from("Buffer is read-only".to_string());
+        }
+        self.document.read_only
+    }
+
+    fn undo(&mut self, redo: bool) {
+        let cursor = if redo {
+            self.document.redo(&self.cursor_position)
+        } else {
+            self.document.undo(&self.cursor_position)
+        };
+        if let Some(cursor) = cursor {
+            self.cursor_position = cursor;
+            cursor_cmds::update_cursor(
+                &mut self.cursor_position,
+                &self.document,
+                &self.mode,
+            );
+        } else {
+            let message = if redo {
+                "Already at newest change"
+            } else {
+                "Already at oldest change"
+            };
+            self.status_message = StatusMessage::from(message.to_string());
+        }
+    }
+
+    fn command_line(&mut self, initial: &str) -> Result<(), std::io::Error> {
+        let Some(line) = self.prompt_with(":", initial)? else {
+            return Ok(());
+        };
+        match ex::parse(&line) {
+            Ok(command) => self.execute(command),
+            Err(error) => {
+                self.status_message = StatusMessage::from(error);
+                Ok(())
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_lines)]
+    fn execute(&mut self, command: Command) -> Result<(), std::io::Error> {
+        let edits = matches!(
+            command,
+            Command::Substitute { .. }
+                | Command::Delete { .. }
+                | Command::Global { .. }
+        );
+        if edits && self.document.hex().is_some() {
+            self.status_message =
+                StatusMessage::from("Not allowed in hex mode".to_string());
+            return Ok(());
+        }
+        if edits && self.refuse_read_only() {
+            return Ok(());
+        }
+        match command {
+            Command::Substitute {
+                range,
+                pattern,
+                replacement,
+                flags,
+            } => self.substitute(&range, pattern, &replacement, &flags),
+            Command::Delete { range } => {
+                self.delete_lines(&range);
+                Ok(())
+            }
+            Command::Global {
+                range,
+                pattern,
+                invert,
+                command,
+            } => self.global(&range, pattern, invert, &command),
+            Command::Grep { pattern, paths } => {
+                self.grep(pattern, &paths);
+                Ok(())
+            }
+            Command::QuickfixStep { forward } => {
+                if self.quickfix.is_empty() {
+                    self.status_message =
+                        StatusMessage::from("No quickfix list".to_string());
+                } else if let Some(index) = self.quickfix.step(forward) {
+                    self.open_quickfix_entry(index);
+                } else {
+                    self.status_message =
+                        StatusMessage::from("No more items".to_string());
+                }
+                Ok(())
+            }
+            Command::QuickfixOpen => {
+                self.quickfix_open = true;
+                Ok(())
+            }
+            Command::QuickfixClose => {
+                self.quickfix_open = false;
+                Ok(())
+            }
+            Command::Edit { file_name } => {
+                if self.edit(&file_name) {
+                    self.status_message = StatusMessage::from(self.file_info());
+                }
+                Ok(())
+            }
+            Command::ListBuffers => self.list_buffers(),
+            Command::Find { source } => self.find(source),
+            Command::Explore { dir } => {
+                self.explore(dir.as_deref());
+                Ok(())
+            }
+            Command::Buffer { name: Some(name) } => {
+                self.park_buffer();
+                let found = self.buffers.find(&name);
+                self.unpark_buffer();
+                match found {
+                    Ok(index) => self.switch_buffer(index),
+                    Err(error) => {
+                        self.status_message = StatusMessage::from(error);
+                    }
+                }
+                Ok(())
+            }
+            Command::Buffer { name: None } => Ok(()),
+            Command::BufferStep { forward } => {
+                self.switch_buffer(self.buffers.step(forward));
+                Ok(())
+            }
+            Command::BufferDelete { name, force } => {
+                self.delete_buffer(name.as_deref(), force);
+                Ok(())
+            }
+            Command::Split {
+                vertical,
+                file_name,
+            } => {
+                let direction = if vertical {
+                    Direction::Vertical
+                } else {
+                    Direction::Horizontal
+                };
+                self.split_window(direction, file_name.as_deref());
+                Ok(())
+            }
+            Command::CloseWindow => {
+                self.close_window();
+                Ok(())
+            }
+            Command::OnlyWindow => {
+                self.windows.only();
+                Ok(())
+            }
+            Command::NewTab { file_name } => {
+                self.new_tab(file_name.as_deref());
+                Ok(())
+            }
+            Command::TabStep { forward } => {
+                self.switch_tab(self.tabs.step(forward));
+                Ok(())
+            }
+            Command::CloseTab => {
+                self.close_tab();
+                Ok(())
+            }
+            Command::Set { args, local } => {
+                if let Err(error) = self.set(&args, local) {
+                    self.status_message = StatusMessage::from(error);
+                }
+                Ok(())
+            }
+            Command::Map {
+                modes,
+                lhs,
+                rhs: None,
+                ..
+            } => self.list_mappings(&modes, lhs.as_deref()),
+            Command::Map {
+                modes,
+                lhs,
+                rhs: Some(rhs),
+                noremap,
+            } => {
+                let lhs = lhs.unwrap_or_default();
+                if let Err(error) = self.map(&modes, &lhs, &rhs, noremap) {
+                    self.status_message = StatusMessage::from(error);
+                }
+                Ok(())
+            }
+            Command::Unmap { modes, lhs } => {
+                if let Err(error) = self.unmap(&modes, &lhs) {
+                    self.status_message = StatusMessage::from(error);
+                }
+                Ok(())
+            }
+            Command::Hex => {
+                self.toggle_hex();
+                Ok(())
+            }
+            Command::Goto { offset } => {
+                self.goto_offset(offset);
+                Ok(())
+            }
+        }
+    }
+
+    fn switch_tab(&mut self, index: usize) {
+        let current = self.tabs.current();
+        if index == current || index >= self.tabs.len() {
+            return;
+        }
+        self.save_window();
+        self.tabs.swap(current, &mut self.windows);
+        self.tabs.set_current(index);
+        self.tabs.swap(index, &mut self.windows);
+        self.load_window();
+    }
+
+    fn new_tab(&mut self, file_name: Option<&str>) {
+        // The new tab starts with a single window on an empty buffer,
+        // which edit() reuses when a file is given
+        self.park_buffer();
+        let index = self.buffers.add(Document::default());
+        let number = self.buffers.number(index).unwrap_or(1);
+        self.unpark_buffer();
+        let tab = self.tabs.add(Windows::new(number));
+        self.switch_tab(tab);
+        if let Some(file_name) = file_name {
+            if self.edit(file_name) {
+                self.status_message = StatusMessage::from(self.file_info());
+            }
+        }
+    }
+
+    fn close_tab(&mut self) {
+        if self.tabs.len() == 1 {
+            self.status_message =
+                StatusMessage::from("Cannot close last tab page".to_string());
+            return;
+        }
+        // The tab on the right becomes current, or the one on the left
+        // when closing the last tab
+        let closing = self.tabs.current();
+        let next = if closing.saturating_add(1) < self.tabs.len() {
+            closing.saturating_add(1)
+        } else {
+            closing.saturating_sub(1)
+        };
+        self.switch_tab(next);
+        if let Err(error) = self.tabs.remove(closing) {
+            self.status_message = StatusMessage::from(error);
+        }
+    }
+
+    fn window_command(&mut self) -> Result<(), std::io::Error> {
+        // The key after Ctrl-w
+        match self.read_key()? {
+            Key::Char(c @ ('h' | 'j' | 'k' | 'l'))
+            | Key::Ctrl(c @ ('h' | 'j' | 'k' | 'l')) => self.focus_window(c),
+            Key::Left => self.focus_window('h'),
+            Key::Down => self.focus_window('j'),
+            Key::Up => self.focus_window('k'),
+            Key::Right => self.focus_window('l'),
+            Key::Char('w') | Key::Ctrl('w') => {
+                self.switch_window(self.windows.next(self.window_area()));
+            }
+            Key::Char('s' | 'S') | Key::Ctrl('s') => {
+                self.split_window(Direction::Horizontal, None);
+            }
+            Key::Char('v') | Key::Ctrl('v') => {
+                self.split_window(Direction::Vertical, None);
+            }
+            Key::Char('c' | 'q') | Key::Ctrl('c' | 'q') => self.close_window(),
+            Key::Char('o') | Key::Ctrl('o') => self.windows.only(),
+            Key::Char('+') => self.windows.resize(Direction::Horizontal, 1),
+            Key::Char('-') => self.windows.resize(Direction::Horizontal, -1),
+            Key::Char('>') => self.windows.resize(Direction::Vertical, 1),
+            Key::Char('<') => self.windows.resize(Direction::Vertical, -1),
+            Key::Char('=') => self.windows.equalize(),
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn save_window(&mut self) {
+        // Stores the editor's view into the current window, the opposite
+        // of what switch_window() does when a window becomes current
+        let buffer = self.buffers.number(self.buffers.current());
+        if let Some(window) = self.windows.get_mut(self.windows.current()) {
+            window.buffer = buffer.unwrap_or(window.buffer);
+            window.cursor_position = self.cursor_position.clone();
+            window.offset = self.offset.clone();
+            window.options = self.window_options.clone();
+        }
+    }
+
+    fn switch_window(&mut self, id: usize) {
+        if id == self.windows.current() {
+            return;
+        }
+        if self.windows.get(id).is_none() {
+            return;
+        }
+        self.save_window();
+        self.windows.set_current(id);
+        self.load_window();
+    }
+
+    fn load_window(&mut self) {
+        // Makes the view of the current window the editor's, switching
+        // to its buffer if needed
+        let Some(window) = self.windows.get(self.windows.current()).cloned()
+        else {
+            return;
+        };
+        if let Some(index) = self.buffers.index_of(window.buffer) {
+            if index != self.buffers.current() {
+                self.park_buffer();
+                self.buffers.set_current(index);
+                self.unpark_buffer();
+            }
+        }
+        self.cursor_position = window.cursor_position;
+        self.offset = window.offset;
+        self.window_options = window.options;
+        self.match_hl = None;
+        // The buffer may have been edited from another window since
+        cursor_cmds::update_cursor(
+            &mut self.cursor_position,
+            &self.document,
+            &self.mode,
+        );
+    }
+
+    fn read_paste(&mut self) -> Result<String, std::io::Error> {
+        // Everything up to the end of a bracketed paste, which only
+        // leaves characters as they are
+        let mut text = String::new();
+        loop {
+            match self.terminal.read_event()? {
+                Event::Key(Key::Char(c)) => text.push(c),
+                Event::Unsupported(bytes) if bytes == PASTE_END => {
+                    return Ok(text);
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn paste(&mut self, text: &str) {
+        // Pasted text goes in all at once, in normal mode as its own undo
+        // step with the cursor left on its last character. In hex mode it
+        // overwrites bytes as if typed
+        if self.refuse_read_only() {
+            return;
+        }
+        if self.document.hex().is_some() {
+            if self.mode == Mode::Insert {
+                for c in text.chars() {
+                    self.overwrite_byte(c, true);
+                }
+            }
+            return;
+        }
+        match self.mode {
+            Mode::Insert => {
+                self.cursor_position =
+                    self.document.insert_text(&self.cursor_position, text);
+            }
+            Mode::Normal => {
+                if text.is_empty() {
+                    return;
+                }
+                self.document.checkpoint(&self.cursor_position);
+                let end =
+                    self.document.insert_text(&self.cursor_position, text);
+                self.cursor_position = Position {
+                    x: end.x.saturating_sub(1),
+                    y: end.y,
+                };
+                cursor_cmds::update_cursor(
+                    &mut self.cursor_position,
+                    &self.document,
+                    &self.mode,
+                );
+            }
+            Mode::Visual | Mode::Command => (),
+        }
+    }
+
+    fn window_at(&self, x: usize, y: usize) -> Option<(usize, Rect)> {
+        self.windows
+            .layout(self.window_area())
+            .into_iter()
+            .find(|(_, rect)| {
+                (rect.x..rect.x.saturating_add(rect.width)).contains(&x)
+                    && (rect.y..rect.y.saturating_add(rect.height)).contains(&y)
+            })
+    }
+
+    fn text_position(&self, x: u16, y: u16, rect: &Rect) -> Position {
+        // The position in the current window's document shown at the
+        // terminal cell x, y (from 1, as the mouse reports them), cells
+        // outside the window's text count as its nearest edge
+        let view = self.view_of(&self.document, &self.window_options, rect);
+        let column = usize::from(x)
+            .saturating_sub(1)
+            .saturating_sub(rect.x)
+            .saturating_sub(view.gutter)
+            .min(view.width.saturating_sub(1));
+        let row = usize::from(y)
+            .saturating_sub(1)
+            .saturating_sub(rect.y)
+            .min(rect.height.saturating_sub(2));
+        view.at(&self.document, &self.offset, column, row)
+    }
+
+    fn mouse(&mut self, event: MouseEvent) {
+        match event {
+            MouseEvent::Press(MouseButton::Left, x, y) => self.click(x, y),
+            MouseEvent::Press(MouseButton::WheelUp, x, y) => {
+                self.wheel(x, y, false);
+            }
+            MouseEvent::Press(MouseButton::WheelDown, x, y) => {
+                self.wheel(x, y, true);
+            }
+            MouseEvent::Hold(x, y) => self.drag(x, y),
+            MouseEvent::Release(..) => self.clicks.anchor = None,
+            MouseEvent::Press(..) => (),
+        }
+    }
+
+    fn click(&mut self, x: u16, y: u16) {
+        // Places the cursor, a double click selects a word and a triple
+        // click the whole line
+        let now = Instant::now();
+        let repeated =
+            self.clicks.last.is_some_and(|(time, last_x, last_y)| {
+                (last_x, last_y) == (x, y)
+                    && now.duration_since(time) < MULTI_CLICK
+            });
+        self.clicks.count = if repeated {
+            self.clicks.count % 3 + 1
+        } else {
+            1
+        };
+        self.clicks.last = Some((now, x, y));
+        self.clicks.anchor = None;
+
+        let column = usize::from(x).saturating_sub(1);
+        let row = usize::from(y).saturating_sub(1);
+        let Some((id, rect)) = self.window_at(column, row) else {
+            return;
+        };
+        self.switch_window(id);
+        // A click on the status line only makes the window the current one
+        let status_line = rect.y.saturating_add(rect.height).saturating_sub(1);
+        if row >= status_line || self.document.is_empty() {
+            return;
+        }
+        if self.mode == Mode::Visual {
+            self.mode = Mode::Normal;
+        }
+        self.cursor_position = self.text_position(x, y, &rect);
+        cursor_cmds::update_cursor(
+            &mut self.cursor_position,
+            &self.document,
+            &self.mode,
+        );
+        let at = self.cursor_position.clone();
+        let line = self.document.row(at.y).map_or("", Row::as_str);
+        match self.clicks.count {
+            1 => self.clicks.anchor = Some(at),
+            2 => {
+                if let Some((start, end)) = word_bounds(line, at.x) {
+                    self.select(
+                        Position { x: start, y: at.y },
+                        Position { x: end, y: at.y },
+                    );
+                }
+            }
+            _ => {
+                let end = line.graphemes(true).count().saturating_sub(1);
+                self.select(
+                    Position { x: 0, y: at.y },
+                    Position { x: end, y: at.y },
+                );
+            }
+        }
+    }
+
+    fn select(&mut self, start: Position, end: Position) {
+        self.mode = Mode::Visual;
+        self.hl_text = SelectedText {
+            start,
+            end: end.clone(),
+        };
+        self.cursor_position = end;
+    }
+
+    fn drag(&mut self, x: u16, y: u16) {
+        // Selects from where the button was pressed to where it is now
+        let Some(anchor) = self.clicks.anchor.clone() else {
+            return;
+        };
+        let at = self.text_position(x, y, &self.window_rect());
+        if self.mode != Mode::Visual {
+            if at == anchor {
+                return;
+            }
+            visual_cmds::enter_visual_mode(
+                &anchor,
+                &mut self.hl_text,
+                &mut self.mode,
+            );
+        }
+        self.cursor_position = at;
+        cursor_cmds::update_cursor(
+            &mut self.cursor_position,
+            &self.document,
+            &self.mode,
+        );
+        visual_cmds::update_selection(
+            &self.cursor_position,
+            &mut self.hl_text,
+            &self.document,
+        );
+    }
+
+    fn wheel(&mut self, x: u16, y: u16, down: bool) {
+        // Scrolls the window under the pointer, whose cursor only moves
+        // when it would go out of view
+        let column = usize::from(x).saturating_sub(1);
+        let row = usize::from(y).saturating_sub(1);
+        let Some((id, rect)) = self.window_at(column, row) else {
+            return;
+        };
+        let height = rect.height.saturating_sub(1).max(1);
+        if id == self.windows.current() {
+            let len = self.document.len();
+            scroll_lines(
+                &mut self.offset,
+                &mut self.cursor_position,
+                len,
+                height,
+                down,
+            );
+            if self.document.is_empty() {
+                return;
+            }
+            cursor_cmds::update_cursor(
+                &mut self.cursor_position,
+                &self.document,
+                &self.mode,
+            );
+            if self.mode == Mode::Visual {
+                visual_cmds::update_selection(
+                    &self.cursor_position,
+                    &mut self.hl_text,
+                    &self.document,
+                );
+            }
+            return;
+        }
+        let len = self
+            .windows
+            .get(id)
+            .map_or(0, |window| self.window_document(window.buffer).len());
+        if let Some(window) = self.windows.get_mut(id) {
+            scroll_lines(
+                &mut window.offset,
+                &mut window.cursor_position,
+                len,
+                height,
+                down,
+            );
+        }
+    }
+
+    fn focus_window(&mut self, direction: char) {
+        let cursor = Position {
+            x: self.cursor_position.x.saturating_sub(self.offset.x),
+            y: self.cursor_position.y.saturating_sub(self.offset.y),
+        };
+        if let Some(id) =
+            self.windows
+                .neighbour(self.window_area(), &cursor, direction)
+        {
+            self.switch_window(id);
+        }
+    }
+
+    fn split_window(&mut self, direction: Direction, file_name: Option<&str>) {
+        // The new window shows the same buffer with the same view, so only
+        // the current window id changes
+        let rect = self.window_rect();
+        let size = match direction {
+            Direction::Horizontal => rect.height,
+            Direction::Vertical => rect.width.saturating_sub(1),
+        };
+        if size < 2 {
+            self.status_message =
+                StatusMessage::from("Not enough room".to_string());
+            return;
+        }
+        self.save_window();
+        let id = self.windows.split(direction, size);
+        self.windows.set_current(id);
+        if let Some(file_name) = file_name {
+            if self.edit(file_name) {
+                self.status_message = StatusMessage::from(self.file_info());
+            }
+        }
+    }
+
+    fn close_window(&mut self) {
+        if self.windows.len() == 1 {
+            self.status_message =
+                StatusMessage::from("Cannot close last window".to_string());
+            return;
+        }
+        let closing = self.windows.current();
+        self.switch_window(self.windows.next(self.window_area()));
+        if let Err(error) = self.windows.close(closing) {
+            self.status_message = StatusMessage::from(error);
+        }
+    }
+
+    fn park_buffer(&mut self) {
+        // Moves the current buffer's state from the editor into its slot
+        // in the buffer list, unpark_buffer() moves it back out
+        if let Some(buffer) = self.buffers.get_mut(self.buffers.current()) {
+            buffer.document = mem::take(&mut self.document);
+            buffer.cursor_position = mem::take(&mut self.cursor_position);
+            buffer.offset = mem::take(&mut self.offset);
+            buffer.hl_text = mem::take(&mut self.hl_text);
+        }
+    }
+
+    fn unpark_buffer(&mut self) {
+        if let Some(buffer) = self.buffers.get_mut(self.buffers.current()) {
+            self.document = mem::take(&mut buffer.document);
+            self.cursor_position = mem::take(&mut buffer.cursor_position);
+            self.offset = mem::take(&mut buffer.offset);
+            self.hl_text = mem::take(&mut buffer.hl_text);
+        }
+    }
+
+    fn switch_buffer(&mut self, index: usize) {
+        if index != self.buffers.current() {
+            self.park_buffer();
+            self.buffers.set_current(index);
+            self.unpark_buffer();
+            self.match_hl = None;
+        }
+        self.remember_file();
+        self.status_message = StatusMessage::from(self.file_info());
+        self.check_swap();
+        self.check_file(false);
+    }
+
+    fn remember_file(&self) {
+        // Adds the current file to the recent files, listings are left out
+        if self.document.listing().is_none() {
+            if let Some(file_name) = &self.document.file_name {
+                finder::remember(Path::new(file_name));
+            }
+        }
+    }
+
+    fn file_info(&self) -> String {
+        let name = self.document.file_name.as_deref().unwrap_or("[No Name]");
+        let modified = if self.document.is_dirty() {
+            " [Modified]"
+        } else {
+            ""
+        };
+        format!("\"{name}\"{modified} {} lines", self.document.len())
+    }
+
+    fn edit(&mut self, file_name: &str) -> bool {
+        // Makes the buffer of `file_name` current, opening the file in a
+        // new buffer if there is none. Returns whether it succeeded
+        if self.is_current_file(Path::new(file_name)) {
+            return true;
+        }
+        self.park_buffer();
+        let existing = (0..self.buffers.len()).find(|index| {
+            self.buffers.get(*index).is_some_and(|buffer| {
+                buffer.document.file_name.as_deref().is_some_and(|name| {
+                    same_file(Path::new(name), Path::new(file_name))
+                })
+            })
+        });
+        self.unpark_buffer();
+        if let Some(index) = existing {
+            self.switch_buffer(index);
+            return true;
+        }
+
+        let document = match Document::open(file_name) {
+            Ok(document) => document,
+            Err(error) if error.kind() == ErrorKind::NotFound => {
+                // Saving it creates the file
+                let mut document = Document::default();
+                document.file_name = Some(file_name.to_string());
+                document
+            }
+            Err(_) => {
+                self.status_message = StatusMessage::from(format!(
+                    "Err: could not open file {file_name}"
+                ));
+                return false;
+            }
+        };
+        // An empty buffer nothing was done in is reused instead of being
+        // left behind in the list
+        if self.document.file_name.is_none()
+            && !self.document.is_dirty()
+            && self.document.is_empty()
+        {
+            self.document = document;
+            self.cursor_position = Position::default();
+            self.offset = Position::default();
+            self.hl_text = SelectedText::default();
+            self.match_hl = None;
+            self.remember_file();
+            self.check_swap();
+            return true;
+        }
+        let index = self.buffers.add(document);
+        self.switch_buffer(index);
+        true
+    }
+
+    fn explore(&mut self, dir: Option<&str>) {
+        match dir {
+            Some(dir) if Path::new(dir).is_dir() => {
+                self.open_directory(Path::new(dir), None);
+            }
+            Some(dir) => {
+                self.status_message =
+                    StatusMessage::from(format!("Not a directory: {dir}"));
+            }
+            None => match self.document.listing() {
+                // Reads the listing again
+                Some(listing) => {
+                    let path = listing.path.clone();
+                    self.open_directory(&path, None);
+                }
+                None if self.document.file_name.is_some() => {
+                    self.open_parent();
+                }
+                None => self.open_directory(Path::new("."), None),
+            },
+        }
+    }
+
+    fn open_entry(&mut self) {
+        // Enter on a line of a listing opens the entry it names
+        let Some(listing) = self.document.listing() else {
+            return;
+        };
+        let Some(name) = self
+            .document
+            .row(self.cursor_position.y)
+            .map(|row| row.as_str().trim().to_string())
+            .filter(|name| !name.is_empty())
+        else {
+            return;
+        };
+        let path = listing.join(&name);
+        if path.is_dir() {
+            self.open_directory(&path, None);
+        } else if path.exists() {
+            if self.edit(&path.to_string_lossy()) {
+                self.status_message = StatusMessage::from(self.file_info());
+            }
+        } else {
+            self.status_message = StatusMessage::from(format!(
+                "{name} does not exist, save the listing to create it"
+            ));
+        }
+    }
+
+    fn open_parent(&mut self) {
+        // Lists the directory of the current file, or the parent of the
+        // current listing, with the cursor on the entry we came from
+        let (current, is_dir) = match self.document.listing() {
+            Some(listing) => (listing.path.clone(), true),
+            None => match &self.document.file_name {
+                Some(file_name) => (PathBuf::from(file_name), false),
+                None => (PathBuf::from("."), true),
+            },
+        };
+        // Paths like "." and ".." have no name to go up from
+        let current = match current.file_name() {
+            Some(_) => current,
+            None => current.canonicalize().unwrap_or(current),
+        };
+        let (Some(parent), Some(name)) =
+            (current.parent(), current.file_name())
+        else {
+            return;
+        };
+        let parent = if parent.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            parent
+        };
+        let mut name = name.to_string_lossy().to_string();
+        if is_dir {
+            name.push('/');
+        }
+        let parent = parent.to_path_buf();
+        self.open_directory(&parent, Some(&name));
+    }
+
+    fn open_directory(&mut self, path: &Path, select: Option<&str>) {
+        // A listing without unsaved changes is replaced by the new one,
+        // otherwise the directory gets a buffer of its own
+        if self.document.listing().is_some() && !self.document.is_dirty() {
+            match Document::open(&path.to_string_lossy()) {
+                Ok(document) => {
+                    self.document = document;
+                    self.cursor_position = Position::default();
+                    self.offset = Position::default();
+                    self.hl_text = SelectedText::default();
+                    self.match_hl = None;
+                }
+                Err(error) => {
+                    self.status_message = StatusMessage::from(format!(
+                        "Err: could not read {}: {error}",
+                        path.display()
+                    ));
+                    return;
+                }
+            }
+        } else if !self.edit(&path.to_string_lossy()) {
+            return;
+        }
+        if let Some(select) = select {
+            let row = (0..self.document.len()).find(|index| {
+                self.document
+                    .row(*index)
+                    .is_some_and(|row| row.as_str() == select)
+            });
+            if let Some(y) = row {
+                self.cursor_position = Position { x: 0, y };
+            }
+        }
+    }
+
+    fn save_listing(&mut self) -> Result<(), std::io::Error> {
+        // Shows what saving the listing would do to the files and asks
+        // before doing it
+        let Some(listing) = self.document.listing().cloned() else {
+            return Ok(());
+        };
+        let rows = (0..self.document.len())
+            .filter_map(|index| self.document.row(index));
+        let changes = match Changes::diff(&listing, rows) {
+            Ok(changes) => changes,
+            Err(error) => {
+                self.status_message = StatusMessage::from(error);
+                return Ok(());
+            }
+        };
+        if changes.is_empty() {
+            self.status_message =
+                StatusMessage::from("No changes to apply".to_string());
+            return Ok(());
+        }
+        let key =
+            self.show_lines(&changes.describe(), "Apply these changes? (y/n)")?;
+        if key != Key::Char('y') {
+            self.status_message =
+                StatusMessage::from("Changes not applied".to_string());
+            return Ok(());
+        }
+        let result = changes.apply(&listing);
+        // Read it again even if applying failed halfway, so the listing
+        // shows what is on disk
+        match Document::open(&listing.path.to_string_lossy()) {
+            Ok(document) => {
+                self.document = document;
+                self.hl_text = SelectedText::default();
+                self.match_hl = None;
+                cursor_cmds::update_cursor(
+                    &mut self.cursor_position,
+                    &self.document,
+                    &self.mode,
+                );
+            }
+            Err(error) => {
+                self.status_message = StatusMessage::from(format!(
+                    "Err: could not read {}: {error}",
+                    listing.path.display()
+                ));
+                return Ok(());
+            }
+        }
+        self.status_message = StatusMessage::from(match result {
+            Ok(()) => format!("{} changes applied", changes.describe().len()),
+            Err(error) => format!("Err: {error}"),
+        });
+        Ok(())
+    }
+
+    fn find(&mut self, source: Source) -> Result<(), std::io::Error> {
+        let items = match source {
+            Source::Files => walk::files(&[PathBuf::from(".")])
+                .iter()
+                .map(|path| path.to_string_lossy().to_string())
+                .collect(),
+            Source::Buffers => {
+                self.park_buffer();
+                let names = (0..self.buffers.len())
+                    .filter_map(|index| self.buffers.get(index))
+                    .filter_map(|buffer| buffer.document.file_name.clone())
+                    .collect();
+                self.unpark_buffer();
+                names
+            }
+            Source::Recent => {
+                // Shown relative to the working directory when inside it
+                let dir = env::current_dir().unwrap_or_default();
+                finder::recent_files()
+                    .iter()
+                    .map(Path::new)
+                    .filter(|path| path.is_file())
+                    .map(|path| {
+                        let path = path.strip_prefix(&dir).unwrap_or(path);
+                        path.to_string_lossy().to_string()
+                    })
+                    .collect()
+            }
+        };
+        self.finder = Some(Finder::new(source, items));
+        let picked = loop {
+            self.refresh_screen()?;
+            let key = self.read_key()?;
+            let Some(finder) = self.finder.as_mut() else {
+                break None;
+            };
+            match key {
+                Key::Esc | Key::Ctrl('q' | 'c') => break None,
+                Key::Char('\n') => {
+                    break finder.selection().map(str::to_string)
+                }
+                Key::Backspace => finder.pop(),
+                Key::Up | Key::Ctrl('p') => finder.select(false),
+                Key::Down | Key::Ctrl('n') => finder.select(true),
+                Key::Char(c) if !c.is_control() => finder.push(c),
+                _ => (),
+            }
+        };
+        self.finder = None;
+        if let Some(file_name) = picked {
+            if self.edit(&file_name) {
+                self.status_message = StatusMessage::from(self.file_info());
+            }
+        }
+        Ok(())
+    }
+
+    fn preview(&self, file_name: &str, height: usize) -> Vec<String> {
+        // Files that are open show what is in their buffer, which may not
+        // have been saved yet
+        let path = Path::new(file_name);
+        let document = if self.is_current_file(path) {
+            Some(&self.document)
+        } else {
+            (0..self.buffers.len())
+                .filter_map(|index| self.buffers.get(index))
+                .map(|buffer| &buffer.document)
+                .find(|document| {
+                    document
+                        .file_name
+                        .as_deref()
+                        .is_some_and(|name| same_file(Path::new(name), path))
+                })
+        };
+        if let Some(document) = document {
+            return (0..height)
+                .filter_map(|y| document.row(y))
+                .map(|row| row.as_str().to_string())
+                .collect();
+        }
+        match fs::read(path) {
+            Ok(bytes) if bytes.iter().take(8192).any(|byte| *byte == 0) => {
+                vec!["[Binary file]".to_string()]
+            }
+            Ok(bytes) => String::from_utf8_lossy(&bytes)
+                .lines()
+                .take(height)
+                .map(str::to_string)
+                .collect(),
+            Err(error) => vec![format!("[{error}]")],
+        }
+    }
+
+    fn list_buffers(&mut self) -> Result<(), std::io::Error> {
+        self.park_buffer();
+        let current = self.buffers.current();
+        let alternate = self.buffers.alternate();
+        let mut lines = Vec::new();
+        for index in 0..self.buffers.len() {
+            let Some(buffer) = self.buffers.get(index) else {
+                continue;
+            };
+            let flag = if index == current {
+                "%a"
+            } else if Some(index) == alternate {
+                "# "
+            } else {
+                "  "
+            };
+            let modified = if buffer.document.is_dirty() { "+" } else { " " };
+            let name =
+                buffer.document.file_name.as_deref().unwrap_or("[No Name]");
+            lines.push(format!(
+                "{:>3} {flag} {modified} \"{name}\"  line {}",
+                buffer.number,
+                buffer.cursor_position.y.saturating_add(1)
+            ));
+        }
+        self.unpark_buffer();
+        self.show_lines(&lines, "Press any key to continue")?;
+        Ok(())
+    }
+
+    fn delete_buffer(&mut self, name: Option<&str>, force: bool) {
+        self.park_buffer();
+        let index = match name.map(|name| self.buffers.find(name)) {
+            None => self.buffers.current(),
+            Some(Ok(index)) => index,
+            Some(Err(error)) => {
+                self.unpark_buffer();
+                self.status_message = StatusMessage::from(error);
+                return;
+            }
+        };
+        let dirty = self.buffers.get(index).and_then(|buffer| {
+            buffer.document.is_dirty().then_some(buffer.number)
+        });
+        if let (Some(number), false) = (dirty, force) {
+            self.unpark_buffer();
+            self.status_message = StatusMessage::from(format!(
+                "No write since last change for buffer {number} \
+                 (add ! to override)"
+            ));
+            return;
+        }
+        if index == self.buffers.current() {
+            // Another buffer has to become current first, an empty one
+            // when this is the last buffer
+            let next = if self.buffers.len() == 1 {
+                self.buffers.add(Document::default())
+            } else {
+                self.buffers
+                    .alternate()
+                    .unwrap_or_else(|| self.buffers.step(true))
+            };
+            self.buffers.set_current(next);
+        }
+        let deleted = self.buffers.remove(index).map(|mut buffer| {
+            swap::discard(&mut buffer.document);
+            buffer.number
+        });
+        self.unpark_buffer();
+        self.match_hl = None;
+        // Other windows showing the deleted buffer, in any tab, show the
+        // current one
+        let current = self.buffers.number(self.buffers.current());
+        let windows = self
+            .tabs
+            .iter_mut()
+            .flat_map(Windows::iter_mut)
+            .chain(self.windows.iter_mut());
+        for window in windows {
+            if deleted.is_some() && Some(window.buffer) == deleted {
+                window.buffer = current.unwrap_or(window.buffer);
+                window.cursor_position = Position::default();
+                window.offset = Position::default();
+            }
+        }
+    }
+
+    fn show_lines(
+        &mut self,
+        lines: &[String],
+        prompt: &str,
+    ) -> Result<Key, std::io::Error> {
+        // Output that doesn't fit in the message bar is drawn over the
+        // bottom of the screen until a key is pressed
+        self.status_message = StatusMessage::from(String::new());
+        self.refresh_screen()?;
+        let width = self.terminal.size().width as usize;
+        let bottom = self.message_row();
+        // Everything below the tab line can be covered
+        let room = bottom.saturating_sub(1);
+        let lines = lines.get(lines.len().saturating_sub(room)..);
+        let lines = lines.unwrap_or(&[]);
+        let first = bottom.saturating_sub(lines.len());
+        for (line, text) in lines.iter().enumerate() {
+            self.terminal.cursor_position(&Position {
+                x: 0,
+                y: first.saturating_add(line),
+            });
+            self.terminal.clear_current_line();
+            let text: String = text.chars().take(width).collect();
+            self.terminal.print(&text);
+        }
+        self.terminal.cursor_position(&Position { x: 0, y: bottom });
+        self.terminal.clear_current_line();
+        self.terminal.print(prompt);
+        self.terminal.flush()?;
+        self.read_key()
+    }
+
+    fn grep(&mut self, pattern: Option<String>, paths: &[String]) {
+        let Some((regex, pattern)) =
+            self.compile_pattern(pattern, self.case_mode())
+        else {
+            return;
+        };
+        let roots: Vec<PathBuf> = if paths.is_empty() {
+            vec![PathBuf::from(".")]
+        } else {
+            paths.iter().map(PathBuf::from).collect()
+        };
+        let (entries, aborted) =
+            search_cmds::grep(&regex, &walk::files(&roots));
+        if entries.is_empty() {
+            self.status_message =
+                StatusMessage::from(format!("No match: {pattern}"));
+        } else {
+            let mut title = format!(":grep {pattern}");
+            for path in paths {
+                title = format!("{title} {path}");
+            }
+            self.quickfix = Quickfix::new(title, entries);
+            self.open_quickfix_entry(0);
+        }
+        // Files the pattern was too expensive for are named after the
+        // matches from the others are in the list
+        if let Some(first) = aborted.first() {
+            let others = match aborted.len() {
+                1 => String::new(),
+                count => {
+                    format!(" and {} other files", count.saturating_sub(1))
+                }
+            };
+            self.status_message = StatusMessage::from(format!(
+                "Pattern too expensive, search aborted in {}{others}",
+                first.display()
+            ));
+        }
+    }
+
+    fn is_current_file(&self, path: &Path) -> bool {
+        self.document
+            .file_name
+            .as_deref()
+            .is_some_and(|file_name| same_file(Path::new(file_name), path))
+    }
+
+    fn open_quickfix_entry(&mut self, index: usize) {
+        // The file is opened in its own buffer, leaving the current one
+        // and any changes to it in the buffer list
+        let Some(entry) = self.quickfix.entries().get(index).cloned() else {
+            return;
+        };
+        if !self.edit(&entry.path.to_string_lossy()) {
+            return;
+        }
+        self.quickfix.select(index);
+        self.match_hl = None;
+        self.cursor_position = entry.position;
+        cursor_cmds::update_cursor(
+            &mut self.cursor_position,
+            &self.document,
+            &self.mode,
+        );
+        self.status_message = StatusMessage::from(format!(
+            "({} of {}): {}",
+            index.saturating_add(1),
+            self.quickfix.len(),
+            entry.text
+        ));
+    }
+
+    fn selected_lines(&self) -> (usize, usize) {
+        let SelectedText { start, end } = &self.hl_text;
+        (start.y.min(end.y), start.y.max(end.y))
+    }
+
+    fn resolve_range(&mut self, range: &Range) -> Option<(usize, usize)> {
+        match range.resolve(
+            self.cursor_position.y,
+            self.document.len(),
+            Some(self.selected_lines()),
+        ) {
+            Ok(lines) => Some(lines),
+            Err(error) => {
+                self.status_message = StatusMessage::from(error);
+                None
+            }
+        }
+    }
+
+    fn compile_pattern(
+        &mut self,
+        pattern: Option<String>,
+        case: CaseMode,
+    ) -> Option<(Regex, String)> {
+        // An empty pattern reuses the last one, and a new one becomes
+        // the last pattern for `n` and `N`
+        let Some(pattern) =
+            pattern.or_else(|| self.search.last_pattern.clone())
+        else {
+            self.status_message = StatusMessage::from(
+                "No previous regular expression".to_string(),
+            );
+            return None;
+        };
+        match Regex::new(&pattern, self.search.syntax, case) {
+            Ok(regex) => {
+                self.search.last_pattern = Some(pattern.clone());
+                Some((regex, pattern))
+            }
+            Err(error) => {
+                self.status_message = StatusMessage::from(error.to_string());
+                None
+            }
+        }
+    }
+
+    fn delete_lines(&mut self, range: &Range) {
+        let Some((first, last)) = self.resolve_range(range) else {
+            return;
+        };
+        if self.document.is_empty() {
+            return;
+        }
+        self.document.checkpoint(&self.cursor_position);
+        self.document.delete_lines(first, last);
+        self.cursor_position = Position { x: 0, y: first };
+        cursor_cmds::update_cursor(
+            &mut self.cursor_position,
+            &self.document,
+            &self.mode,
+        );
+        let count = last.saturating_sub(first).saturating_add(1);
+        if count > 2 {
+            self.status_message =
+                StatusMessage::from(format!("{count} fewer lines"));
+        }
+    }
+
+    fn global(
+        &mut self,
+        range: &Range,
+        pattern: Option<String>,
+        invert: bool,
+        command: &str,
+    ) -> Result<(), std::io::Error> {
+        let command = match ex::parse(command) {
+            Ok(Command::Global { .. }) => {
+                self.status_message = StatusMessage::from(
+                    "Cannot do :global recursively".to_string(),
+                );
+                return Ok(());
+            }
+            Ok(command) => command,
+            Err(error) => {
+                self.status_message = StatusMessage::from(error);
+                return Ok(());
+            }
+        };
+        let Some((first, last)) = self.resolve_range(range) else {
+            return Ok(());
+        };
+        let case = self.case_mode();
+        let Some((regex, pattern)) = self.compile_pattern(pattern, case) else {
+            return Ok(());
+        };
+
+        // Find which lines have a match starting in them. Lines are only
+        // marked at the end so a failed search leaves nothing behind, and
+        // none are left from before
+        self.document.clear_marks();
+        let text = self.document.text();
+        let mut matching = vec![false; last.saturating_sub(first) + 1];
+        let mut from = self.document.offset_of(&Position { x: 0, y: first });
+        let mut scanned = from;
+        let mut line = first;
+        let mut steps = 0;
+        loop {
+            let start = match regex.captures_from(&text, from, &mut steps) {
+                Ok(Some(caps)) => caps.start(),
+                Ok(None) => break,
+                Err(error) => {
+                    self.status_message =
+                        StatusMessage::from(error.to_string());
+                    return Ok(());
+                }
+            };
+            line = line.saturating_add(substitute_cmds::count_newlines(
+                &text[scanned..start],
+            ));
+            scanned = start;
+            if let Some(matched) = matching.get_mut(line.saturating_sub(first))
+            {
+                *matched = true;
+            } else {
+                break;
+            }
+            match text[start..].find('\n') {
+                Some(newline) => {
+                    from = start.saturating_add(newline).saturating_add(1);
+                }
+                None => break,
+            }
+        }
+        for (index, matched) in matching.iter().enumerate() {
+            if *matched != invert {
+                self.document.mark_line(first.saturating_add(index));
+            }
+        }
+
+        // The command is then run on each marked line, since marks live
+        // in the rows themselves they follow the lines when the command
+        // inserts or deletes rows. The lines before the one just visited
+        // have no marks left, so the next one is looked for from there (less
+        // the lines the command deleted). All changes are undone at once
+        self.document.begin_change(&self.cursor_position);
+        let len = self.document.len();
+        let mut visited = false;
+        let mut result = Ok(());
+        let mut from = 0;
+        while let Some(y) = self.document.take_marked_line(from) {
+            visited = true;
+            self.cursor_position = Position { x: 0, y };
+            let before = self.document.len();
+            result = self.execute(command.clone());
+            if result.is_err() {
+                break;
+            }
+            let deleted = before.saturating_sub(self.document.len());
+            from = y.saturating_sub(deleted);
+        }
+        self.document.clear_marks();
+        self.document.end_change();
+        cursor_cmds::update_cursor(
+            &mut self.cursor_position,
+            &self.document,
+            &self.mode,
+        );
+
+        if !visited {
+            let message = if invert {
+                format!("Pattern found in every line: {pattern}")
+            } else {
+                format!("Pattern not found: {pattern}")
+            };
+            self.status_message = StatusMessage::from(message);
+        } else if self.document.len() < len {
+            let fewer = len.saturating_sub(self.document.len());
+            self.status_message =
+                StatusMessage::from(format!("{fewer} fewer lines"));
+        }
+        result
+    }
+
+    #[allow(clippy::too_many_lines)]
+    fn substitute(
+        &mut self,
+        range: &Range,
+        pattern: Option<String>,
+        replacement: &str,
+        flags: &SubstituteFlags,
+    ) -> Result<(), std::io::Error> {
+        let Some((first, last)) = self.resolve_range(range) else {
+            return Ok(());
+        };
+        let case = match flags.ignore_case {
+            Some(true) => CaseMode::Insensitive,
+            Some(false) => CaseMode::Sensitive,
+            None => self.case_mode(),
+        };
+        let Some((regex, pattern)) = self.compile_pattern(pattern, case) else {
+            return Ok(());
+        };
+        let replacement = substitute_cmds::Replacement::parse(replacement);
+
+        // The substitution works on the whole text of the document, which
+        // lets matches span multiple lines. `line` is always the number of
+        // the line that contains `scanned` and `last` is moved along as
+        // replacements add or remove line breaks
+        let mut text = self.document.text();
+        // Confirming shows every replacement as it is made, see below
+        let original = flags.confirm.then(|| text.clone());
+        let mut from = self.document.offset_of(&Position { x: 0, y: first });
+        let mut scanned = from;
+        let mut line = first;
+        let mut last = last;
+        let mut confirm = flags.confirm;
+        let mut changed = false;
+        let mut substitutions: usize = 0;
+        let mut lines: usize = 0;
+        let mut last_line: Option<usize> = None;
+        let mut found = false;
+        let mut error = None;
+        let mut steps = 0;
+
+        loop {
+            let caps = match regex.captures_from(&text, from, &mut steps) {
+                Ok(Some(caps)) => caps,
+                Ok(None) => break,
+                Err(err) => {
+                    error = Some(err.to_string());
+                    break;
+                }
+            };
+            let (start, end) = (caps.start(), caps.end());
+            line = line.saturating_add(substitute_cmds::count_newlines(
+                &text[scanned..start],
+            ));
+            if line > last {
+                break;
+            }
+            found = true;
+
+            let new = replacement.expand(&caps, &text);
+            let mut stop = false;
+            let mut skip = false;
+            if confirm {
+                match self.confirm_substitution(&text, start, end, &new)? {
+                    'y' => (),
+                    'l' => stop = true,
+                    'a' => confirm = false,
+                    'n' => skip = true,
+                    _ => break,
+                }
+            }
+
+            let matched_newlines =
+                substitute_cmds::count_newlines(&text[start..end]);
+            let end = if skip || flags.count_only {
+                line = line.saturating_add(matched_newlines);
+                end
+            } else {
+                if !changed {
+                    self.document.checkpoint(&self.cursor_position);
+                    changed = true;
+                }
+                let new_newlines = substitute_cmds::count_newlines(&new);
+                line = line.saturating_add(new_newlines);
+                last = last
+                    .saturating_add(new_newlines)
+                    .saturating_sub(matched_newlines);
+                text.replace_range(start..end, &new);
+                if confirm {
+                    self.document.set_text(&text);
+                }
+                start.saturating_add(new.len())
+            };
+            scanned = end;
+            if !skip {
+                substitutions = substitutions.saturating_add(1);
+                if last_line != Some(line) {
+                    lines = lines.saturating_add(1);
+                    last_line = Some(line);
+                }
+            }
+            if stop {
+                break;
+            }
+
+            // Without the `g` flag only the first match of each line is
+            // replaced, so continue on the line after the match
+            let rest = text.get(end..).unwrap_or("");
+            from = if !flags.global && !text[..end].ends_with('\n') {
+                match rest.find('\n') {
+                    Some(newline) => {
+                        end.saturating_add(newline).saturating_add(1)
+                    }
+                    None => break,
+                }
+            } else if start == end {
+                match rest.chars().next() {
+                    Some(c) => end.saturating_add(c.len_utf8()),
+                    None => break,
+                }
+            } else {
+                end
+            };
+        }
+
+        self.match_hl = None;
+        if error.is_some() {
+            // Running out of steps fails the whole command, which takes back
+            // the replacements already shown when confirming them
+            if let (true, Some(original)) = (changed, &original) {
+                self.document.set_text(original);
+            }
+        } else if changed {
+            self.document.set_text(&text);
+        }
+        if let (Some(line), false, None) = (last_line, flags.count_only, &error)
+        {
+            self.cursor_position = Position { x: 0, y: line };
+            cursor_cmds::update_cursor(
+                &mut self.cursor_position,
+                &self.document,
+                &self.mode,
+            );
+        }
+        if let Some(error) = error {
+            self.status_message = StatusMessage::from(error);
+        } else if substitutions > 0 {
+            let noun = match (flags.count_only, substitutions) {
+                (true, 1) => "match",
+                (true, _) => "matches",
+                (false, 1) => "substitution",
+                (false, _) => "substitutions",
+            };
+            self.status_message = StatusMessage::from(format!(
+                "{substitutions} {noun} on {lines} line{}",
+                if lines == 1 { "" } else { "s" },
+            ));
+        } else if !found {
+            self.status_message =
+                StatusMessage::from(format!("Pattern not found: {pattern}"));
+        }
+        Ok(())
+    }
+
+    fn confirm_substitution(
+        &mut self,
+        text: &str,
+        start: usize,
+        end: usize,
+        replacement: &str,
+    ) -> Result<char, std::io::Error> {
+        // Highlights the match and asks what to do with it, returns one of
+        // 'y'es, 'n'o, 'a'll, 'q'uit or 'l'ast
+        let start = self.document.position_of(start);
+        let end = match text.get(..end).and_then(|t| t.char_indices().last()) {
+            Some((last, _)) if end > self.document.offset_of(&start) => {
+                self.document.position_of(last)
+            }
+            _ => start.clone(),
+        };
+        self.match_hl = Some(SelectedText {
+            start: start.clone(),
+            end,
+        });
+        self.cursor_position = start;
+        self.scroll();
+        self.status_message = StatusMessage::from(format!(
+            "replace with {replacement} (y/n/a/q/l)?"
+        ));
+        loop {
+            self.refresh_screen()?;
+            match self.read_key()? {
+                Key::Char(c @ ('y' | 'n' | 'a' | 'q' | 'l')) => {
+                    self.status_message = StatusMessage::from(String::new());
+                    return Ok(c);
+                }
+                Key::Esc | Key::Ctrl('c') => {
+                    self.status_message = StatusMessage::from(String::new());
+                    return Ok('q');
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn scroll(&mut self) {
+        self.view().scroll(
+            &self.document,
+            &self.cursor_position,
+            &mut self.offset,
+        );
+    }
+
+    fn option(&self, opt: Opt) -> Value {
+        self.option_in(opt, &self.document, &self.window_options)
+    }
+
+    fn option_in(
+        &self,
+        opt: Opt,
+        document: &Document,
+        window: &Options,
+    ) -> Value {
+        // The value the option has for a window showing `document`, local
+        // values win over the global one
+        let local = match opt.scope() {
+            Scope::Global => None,
+            Scope::Buffer => document.options.get(opt),
+            Scope::Window => window.get(opt),
+        };
+        local
+            .or_else(|| self.options.get(opt))
+            .unwrap_or_else(|| opt.default())
+    }
+
+    fn set(&mut self, args: &[String], local: bool) -> Result<(), String> {
+        // Without arguments, shows the options that were changed
+        if args.is_empty() {
+            let changed: Vec<String> = Opt::all()
+                .map(|opt| (opt, self.option(opt)))
+                .filter(|(opt, value)| *value != opt.default())
+                .map(|(opt, value)| opt.show(value))
+                .collect();
+            self.status_message = StatusMessage::from(format!(
+                "--- Options --- {}",
+                changed.join("  ")
+            ));
+            return Ok(());
+        }
+        let mut shown = Vec::new();
+        let layout = self.layout();
+        for arg in args {
+            let (opt, setting) = options::parse_setting(arg)?;
+            let value = setting.apply(opt, self.option(opt));
+            if setting == Setting::Show {
+                shown.push(opt.show(value));
+                continue;
+            }
+            let scope = match opt.scope() {
+                Scope::Global => None,
+                Scope::Buffer => Some(&mut self.document.options),
+                Scope::Window => Some(&mut self.window_options),
+            };
+            match scope {
+                Some(options) if local => options.set(opt, value),
+                Some(options) => {
+                    options.unset(opt);
+                    self.options.set(opt, value);
+                }
+                None => self.options.set(opt, value),
+            }
+        }
+        if !shown.is_empty() {
+            self.status_message = StatusMessage::from(shown.join("  "));
+        }
+        // Converting the file to another format is a change to be saved
+        if self.layout() != layout {
+            self.document.set_modified();
+        }
+        self.scroll();
+        Ok(())
+    }
+
+    fn layout(&self) -> Layout {
+        // How the current buffer's lines are written to its file
+        Layout {
+            format: self.option(Opt::FileFormat).format(),
+            end_of_line: self.option(Opt::EndOfLine).flag(),
+            bom: self.option(Opt::Bomb).flag(),
+            encoding: self.option(Opt::FileEncoding).encoding(),
+        }
+    }
+
+    fn case_mode(&self) -> CaseMode {
+        match (
+            self.option(Opt::IgnoreCase).flag(),
+            self.option(Opt::SmartCase).flag(),
+        ) {
+            (false, _) => CaseMode::Sensitive,
+            (true, false) => CaseMode::Insensitive,
+            (true, true) => CaseMode::Smart,
+        }
+    }
+
+    fn map(
+        &mut self,
+        modes: &[MapMode],
+        lhs: &str,
+        rhs: &str,
+        noremap: bool,
+    ) -> Result<(), String> {
+        let leader = self.option(Opt::Leader).key();
+        let lhs = keys::parse_mapping(lhs, leader)?;
+        let rhs = keys::parse_mapping(rhs, leader)?;
+        if lhs.is_empty() {
+            return Err("Argument required".to_string());
+        }
+        for mode in modes {
+            self.keymap.map(Mapping {
+                mode: *mode,
+                lhs: lhs.clone(),
+                rhs: rhs.clone(),
+                noremap,
+            });
+        }
+        Ok(())
+    }
+
+    fn unmap(&mut self, modes: &[MapMode], lhs: &str) -> Result<(), String> {
+        let lhs = keys::parse_mapping(lhs, self.option(Opt::Leader).key())?;
+        let mut found = false;
+        for mode in modes {
+            found |= self.keymap.unmap(*mode, &lhs);
+        }
+        if found {
+            Ok(())
+        } else {
+            Err("No such mapping".to_string())
+        }
+    }
+
+    fn list_mappings(
+        &mut self,
+        modes: &[MapMode],
+        lhs: Option<&str>,
+    ) -> Result<(), std::io::Error> {
+        let leader = self.option(Opt::Leader).key();
+        let lhs = match keys::parse_mapping(lhs.unwrap_or_default(), leader) {
+            Ok(lhs) => lhs,
+            Err(error) => {
+                self.status_message = StatusMessage::from(error);
+                return Ok(());
+            }
+        };
+        let lines = self.keymap.list(modes, &lhs);
+        match lines.as_slice() {
+            [] => {
+                self.status_message =
+                    StatusMessage::from("No mapping found".to_string());
+            }
+            [line] => self.status_message = StatusMessage::from(line.clone()),
+            _ => {
+                self.show_lines(&lines, "Press any key to continue")?;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_key(&mut self) -> Result<Key, std::io::Error> {
+        // Keys left from a mapping come before the terminal's, mappings
+        // don't apply to what is read here
+        match self.typeahead.pop_front() {
+            Some((key, _)) => Ok(key),
+            None => self.terminal.read_key(),
+        }
+    }
+
+    fn read_mapped(
+        &mut self,
+        mode: MapMode,
+        first: Option<Key>,
+    ) -> Result<Option<Key>, std::io::Error> {
+        // Reads a key through the mappings of `mode`, starting with
+        // `first` if given. None when the keys were mapped to nothing
+        let mut first = first.map(|key| (key, true));
+        let mut pending: Vec<Key> = Vec::new();
+        let mut depth = 0;
+        loop {
+            let next = match first.take().or_else(|| self.typeahead.pop_front())
+            {
+                Some(next) => Some(next),
+                None if !pending.is_empty() => {
+                    // The keys so far start a longer mapping, whose rest
+                    // has 'timeoutlen' to come
+                    let timeout = self.option(Opt::TimeoutLen).number();
+                    let timeout = Duration::from_millis(
+                        u64::try_from(timeout).unwrap_or(u64::MAX),
+                    );
+                    if self.terminal.poll(timeout)? {
+                        Some((self.terminal.read_key()?, true))
+                    } else {
+                        None
+                    }
+                }
+                None if depth > 0 => return Ok(None),
+                None => Some((self.terminal.read_key()?, true)),
+            };
+            match next {
+                Some((key, false)) if pending.is_empty() => {
+                    return Ok(Some(key));
+                }
+                Some((key, false)) => self.typeahead.push_front((key, false)),
+                Some((key, true)) => {
+                    pending.push(key);
+                    if matches!(
+                        self.keymap.lookup(mode, &pending),
+                        Lookup::Prefix { .. }
+                    ) {
+                        continue;
+                    }
+                }
+                None => (),
+            }
+            // The longest start of the keys that is mapped is replaced by
+            // its mapping, the keys after it are read again
+            let mapped = (1..=pending.len()).rev().find_map(|len| {
+                match self.keymap.lookup(mode, pending.get(..len)?) {
+                    Lookup::Exact(mapping)
+                    | Lookup::Prefix {
+                        exact: Some(mapping),
+                    } => Some((len, mapping.clone())),
+                    _ => None,
+                }
+            });
+            let len = mapped.as_ref().map_or(1, |(len, _)| *len);
+            let rest = pending.split_off(len.min(pending.len()));
+            for key in rest.into_iter().rev() {
+                self.typeahead.push_front((key, true));
+            }
+            let Some((_, mapping)) = mapped else {
+                return Ok(pending.first().copied());
+            };
+            depth += 1;
+            if depth > MAX_MAP_DEPTH {
+                self.typeahead.clear();
+                self.status_message =
+                    StatusMessage::from("Recursive mapping".to_string());
+                return Ok(None);
+            }
+            // A rhs that starts with its own lhs doesn't map that part
+            // again, or `:map j jzz` would never end
+            let literal = if mapping.rhs.starts_with(&mapping.lhs) {
+                mapping.lhs.len()
+            } else {
+                0
+            };
+            for (index, key) in mapping.rhs.iter().enumerate().rev() {
+                let remap = !mapping.noremap && index >= literal;
+                self.typeahead.push_front((*key, remap));
+            }
+            pending.clear();
+        }
+    }
+
+    fn view(&self) -> View {
+        self.view_of(&self.document, &self.window_options, &self.window_rect())
+    }
+
+    fn view_of(
+        &self,
+        document: &Document,
+        window: &Options,
+        rect: &Rect,
+    ) -> View {
+        // How a window showing `document` in `rect` lays it out
+        let option = |opt| self.option_in(opt, document, window);
+        let gutter = if option(Opt::Number).flag() {
+            view::gutter_width(document.len())
+        } else {
+            0
+        };
+        View {
+            gutter,
+            width: rect.width.saturating_sub(gutter),
+            height: rect.height.saturating_sub(1).max(1),
+            tabstop: option(Opt::TabStop).number(),
+            wrap: option(Opt::Wrap).flag(),
+            scrolloff: option(Opt::ScrollOff).number(),
+        }
+    }
+
+    fn prompt(
+        &mut self,
+        prompt: &str,
+    ) -> Result<Option<String>, std::io::Error> {
+        self.prompt_with(prompt, "")
+    }
+
+    fn prompt_with(
+        &mut self,
+        prompt: &str,
+        initial: &str,
+    ) -> Result<Option<String>, std::io::Error> {
+        // Is there a way to not have to add a
+        // self.mode = insert before every return?
+        // maybe a wrapper for command line prompts?
+
+        let last_mode = self.mode.clone();
+        self.mode = Mode::Command;
+        let mut result = initial.to_string();
+        loop {
+            self.status_message =
+                StatusMessage::from(format!("{prompt}{result}"));
+            self.refresh_screen()?;
+            let Some(key) = self.read_mapped(MapMode::CommandLine, None)?
+            else {
+                continue;
+            };
+            match key {
+                Key::Backspace => {
+                    result.truncate(result.len().saturating_sub(1))
+                }
+                Key::Char('\n') => break,
+                Key::Ctrl('q') => {
+                    result.truncate(0);
+                    self.mode = last_mode.clone();
+                    break;
+                }
+                Key::Char(c) => {
+                    if !c.is_control() {
+                        result.push(c);
+                    }
+                }
+                _ => (),
+            }
+        }
+        self.status_message = StatusMessage::from(String::new());
+        if result.is_empty() {
+            self.mode = last_mode;
+            return Ok(None);
+        }
+        self.mode = last_mode;
+        Ok(Some(result))
+    }
+
+    fn draw_cursor(&self) {
+        if let Some(finder) = &self.finder {
+            // After the query, on the first line inside the frame
+            let rect = self.finder_rect();
+            let x = finder.query().chars().count().saturating_add(3);
+            self.terminal.cursor_position(&Position {
+                x: rect.x.saturating_add(x),
+                y: rect.y.saturating_add(1),
+            });
+            return;
+        }
+        match self.mode {
+            Mode::Insert | Mode::Normal | Mode::Visual => {
+                let rect = self.window_rect();
+                let view = self.view();
+                let Position { x, y } = view
+                    .position(
+                        &self.document,
+                        &self.offset,
+                        &self.cursor_position,
+                    )
+                    .unwrap_or_default();
+                self.terminal.cursor_position(&Position {
+                    x: rect.x.saturating_add(view.gutter).saturating_add(x),
+                    y: rect.y.saturating_add(y),
+                });
+            }
+            Mode::Command => self.terminal.cursor_position(&Position {
+                // Since we can't move right or left on command prompt
+                // assume cursor is at the end of string
                 x: self.status_message.text.len(),
-                y: (self.terminal.size().height + 1) as usize,
+                y: self.message_row(),
             }),
         }
     }
 
-    fn draw_status_bar(&self) {
-        let width = self.terminal.size().width as usize;
+    fn draw_status_bar(
+        &self,
+        document: &Document,
+        cursor_position: &Position,
+        rect: &Rect,
+        current: bool,
+    ) {
+        let width = rect.width;
         let mut status = "[No_name]".to_string();
-        let modified = if self.document.is_dirty() {
-            " {Modified}"
-        } else {
-            ""
+        let modified = match (document.is_dirty(), document.read_only) {
+            (true, true) => " {Modified} {RO}",
+            (true, false) => " {Modified}",
+            (false, true) => " {RO}",
+            (false, false) => "",
         };
 
-        if let Some(name) = &self.document.file_name {
+        if let Some(name) = &document.file_name {
             status = name.clone();
             status.truncate(20);
         }
@@ -572,18 +3297,24 @@ impl Editor {
 
         let line_indicator = format! {
             "{},{}   {}%",
-            self.cursor_position.y,
-            self.cursor_position.x,
+            cursor_position.y,
+            cursor_position.x,
             {
-                if self.cursor_position.y == 0 {
+                if cursor_position.y == 0 {
                     0
                 }
                 else {
-                    (self.cursor_position.y.saturating_mul(100))
-                        .saturating_div(self.document.len())
+                    (cursor_position.y.saturating_mul(100))
+                        .saturating_div(document.len())
                 }
             },
         };
+        // Hex mode shows which byte the cursor is on instead
+        let cell = document.hex().and_then(|hex| hex.cell(cursor_position));
+        let line_indicator = match cell {
+            Some((offset, _)) => format!("0x{offset:08x}"),
+            None => line_indicator,
+        };
 
         let len = status.len().saturating_add(line_indicator.len());
 
@@ -591,11 +3322,22 @@ impl Editor {
         status = format!("{status}{line_indicator}");
         status.truncate(width);
 
-        Terminal::set_bg_color(STATUS_BG_COLOR);
-        Terminal::set_fg_color(STATUS_FG_COLOR);
-        println!("{status}\r");
-        Terminal::reset_bg_color();
-        Terminal::reset_fg_color();
+        self.terminal.cursor_position(&Position {
+            x: rect.x,
+            y: rect.y.saturating_add(rect.height.saturating_sub(1)),
+        });
+        self.terminal
+            .set_bg_color(self.option(Opt::StatusBg).color());
+        if current {
+            self.terminal
+                .set_fg_color(self.option(Opt::StatusFg).color());
+        } else {
+            self.terminal
+                .set_fg_color(self.option(Opt::StatusNcFg).color());
+        }
+        self.terminal.print(&status);
+        self.terminal.reset_bg_color();
+        self.terminal.reset_fg_color();
     }
 
     fn draw_message_bar(&self) {
@@ -603,7 +3345,11 @@ impl Editor {
         // or the message was updated?
         let width = self.terminal.size().width as usize;
 
-        Terminal::clear_current_line();
+        self.terminal.cursor_position(&Position {
+            x: 0,
+            y: self.message_row(),
+        });
+        self.terminal.clear_current_line();
 
         let mut text = match self.mode {
             Mode::Visual => "-- VISUAL --".to_string(),
@@ -612,146 +3358,1165 @@ impl Editor {
         };
 
         text.truncate(width);
-        print!("{text}");
+        self.terminal.print(&text);
     }
 
-    fn draw_welcome_message(&self) {
+    fn draw_welcome_message(&self, width: usize) {
         let mut welcome_message = format!("mtx editor -- version {VERSION}");
-        let width = self.terminal.size().width as usize;
         let len = welcome_message.len();
         let padding = width.saturating_sub(len).saturating_div(2);
         let spaces = " ".repeat(padding.saturating_sub(1));
         welcome_message = format!("~{spaces}{welcome_message}");
         welcome_message.truncate(width);
-        println!("{welcome_message}\r");
+        self.terminal.print(&welcome_message);
     }
 
-    fn draw_rows(&self) {
-        // Separate this function into draw_starting_screen
-        // and draw_rows
-        let height = self.terminal.size().height;
-        for terminal_row in 0..height {
-            Terminal::clear_current_line();
-            let row_number =
-                self.offset.y.saturating_add(terminal_row as usize);
+    fn quickfix_height(&self) -> usize {
+        // The entries plus a title bar above them
+        if self.quickfix_open {
+            self.quickfix
+                .len()
+                .clamp(1, QUICKFIX_HEIGHT)
+                .saturating_add(1)
+        } else {
+            0
+        }
+    }
 
-            let (hl_text_start, hl_text_end) =
-                if self.hl_text.start <= self.hl_text.end {
-                    (&self.hl_text.start, &self.hl_text.end)
-                } else {
-                    (&self.hl_text.end, &self.hl_text.start)
-                };
+    fn message_row(&self) -> usize {
+        // The last row of the terminal
+        (self.terminal.size().height as usize).saturating_add(2)
+    }
 
-            if let Some(row) = self.document.row(row_number) {
-                if hl_text_start.y <= row_number
-                    && row_number <= hl_text_end.y
-                    && self.mode == Mode::Visual
-                {
-                    self.draw_highlighted_row(&row, row_number);
-                } else {
-                    self.draw_row(row);
-                }
-            } else if self.document.is_empty() && terminal_row == height / 3 {
-                self.draw_welcome_message();
+    fn window_area(&self) -> Rect {
+        // Everything between the tab line and the quickfix pane (or the
+        // message bar), the status bar is now part of each window
+        let height = (self.terminal.size().height as usize).saturating_add(1);
+        Rect {
+            x: 0,
+            y: 1,
+            width: self.terminal.size().width as usize,
+            height: height.saturating_sub(self.quickfix_height()),
+        }
+    }
+
+    fn window_rect(&self) -> Rect {
+        let current = self.windows.current();
+        self.windows
+            .layout(self.window_area())
+            .into_iter()
+            .find(|(id, _)| *id == current)
+            .map_or_else(|| self.window_area(), |(_, rect)| rect)
+    }
+
+    fn text_height(&self) -> usize {
+        // Rows of the current window left for the document, at least one
+        // is always kept
+        self.window_rect().height.saturating_sub(1).max(1)
+    }
+
+    fn finder_rect(&self) -> Rect {
+        // Centered over everything between the tab line and the message
+        // bar, frame included
+        let width = self.terminal.size().width as usize;
+        let height = self.message_row().saturating_sub(1);
+        let inner = Rect {
+            x: 0,
+            y: 1,
+            width: width.saturating_mul(9) / 10,
+            height: height.saturating_mul(4) / 5,
+        };
+        Rect {
+            x: width.saturating_sub(inner.width) / 2,
+            y: inner
+                .y
+                .saturating_add(height.saturating_sub(inner.height) / 2),
+            ..inner
+        }
+    }
+
+    fn draw_finder(&self, finder: &Finder) {
+        // A frame with the query and the matches on the left and a
+        // preview of the selected file on the right
+        let rect = self.finder_rect();
+        let inner = rect.width.saturating_sub(2);
+        let list_width = inner / 2;
+        let preview_width = inner.saturating_sub(list_width).saturating_sub(1);
+        let height = rect.height.saturating_sub(2);
+        let matches = finder.matches();
+        let preview = finder
+            .selection()
+            .map(|file_name| self.preview(file_name, height))
+            .unwrap_or_default();
+
+        let title = format!(
+            "- {} ({}/{}) ",
+            finder.source().title(),
+            matches.len(),
+            finder.len()
+        );
+        let title: String = title.chars().take(inner).collect();
+        self.terminal.cursor_position(&Position {
+            x: rect.x,
+            y: rect.y,
+        });
+        self.terminal.print(&format!("+{title:-<inner$}+"));
+
+        // The list scrolls just enough to keep the selection visible,
+        // below the line with the query
+        let rows = height.saturating_sub(1);
+        let first = finder.selected().saturating_add(1).saturating_sub(rows);
+        for line in 0..height {
+            self.terminal.cursor_position(&Position {
+                x: rect.x,
+                y: rect.y.saturating_add(line).saturating_add(1),
+            });
+            self.terminal.print("|");
+            if line == 0 {
+                self.terminal
+                    .print(&fit(&format!("> {}", finder.query()), list_width));
+            } else {
+                let index = first.saturating_add(line).saturating_sub(1);
+                self.draw_finder_match(finder, index, list_width);
+            }
+            self.terminal.print("|");
+            let text = preview.get(line).map_or("", String::as_str);
+            self.terminal
+                .print(&fit(&text.replace('\t', "    "), preview_width));
+            self.terminal.print("|");
+        }
+        self.terminal.cursor_position(&Position {
+            x: rect.x,
+            y: rect.y.saturating_add(height).saturating_add(1),
+        });
+        self.terminal.print(&format!("+{}+", "-".repeat(inner)));
+    }
+
+    fn draw_finder_match(&self, finder: &Finder, index: usize, width: usize) {
+        let Some(found) = finder.matches().get(index) else {
+            self.terminal.print(&" ".repeat(width));
+            return;
+        };
+        let name = finder.item(found.item).unwrap_or_default();
+        let selected = index == finder.selected();
+        if selected {
+            self.terminal
+                .set_bg_color(self.option(Opt::HighlightBg).color());
+        }
+        for (position, c) in fit(name, width).chars().enumerate() {
+            if found.positions.contains(&position) {
+                self.terminal
+                    .set_fg_color(self.option(Opt::FinderMatchFg).color());
             } else {
-                println!("~\r");
+                self.terminal.reset_fg_color();
             }
+            self.terminal.print(c.encode_utf8(&mut [0; 4]));
         }
+        self.terminal.reset_fg_color();
+        self.terminal.reset_bg_color();
     }
 
-    pub fn draw_row(&self, row: &Row) {
+    fn draw_quickfix(&self) {
         let width = self.terminal.size().width as usize;
-        let start = self.offset.x;
-        // Decrement size of coument in digits
-        let end = self.offset.x.saturating_add(width);
-        let row = row.render(start, end);
-
-        println!("{row}\r");
-    }
-
-    pub fn draw_highlighted_row(&self, row: &Row, row_number: usize) {
-        // This is a kind of complicated function, with lots of little things
-        // string slicing ends (e.g. &str[x..y]) ends on y - 1, and I want the
-        // highlighted text struct to be end-to-end inclusive, so everytime
-        // an "end" variable is used, add 1 to it.
-        // TODO this currently does not work when highlighting until a line break,
-        // which only happens when the '\n' is the last character highlighted,
-        // a simple bound check *should* fix it.
-
-        // Other than that, there are 4 possible permutations:
-        // 1 - Empty row:
-        // print " \r" just so it appears highlighted
-        // 2 - Highlighted text starts and end in the current row:
-        // Print text before highlight, highlighted text, and text after
-        // 3 - Highlighted text starts in current row but ends below it:
-        // Print text before highlight, and the rest of the row is highlighted
-        // 4 - Highlighted text start before current row and ends in it:
-        // Print highlighted text until end, print text after
-        // 5 - Highlighted text starts before current row and after it
-        // Print whole row highlighted
+        let height = self.quickfix_height().saturating_sub(1);
+        let entries = self.quickfix.entries();
+
+        let mut title = format!("[Quickfix List] {}", self.quickfix.title());
+        if !entries.is_empty() {
+            let position = format!(
+                "{}/{}",
+                self.quickfix.index().saturating_add(1),
+                entries.len()
+            );
+            let len = title.len().saturating_add(position.len());
+            title.push_str(&" ".repeat(width.saturating_sub(len)));
+            title.push_str(&position);
+        }
+        title.truncate(width);
+        let area = self.window_area();
+        let top = area.y.saturating_add(area.height);
+        self.terminal.cursor_position(&Position { x: 0, y: top });
+        self.terminal.clear_current_line();
+        self.terminal
+            .set_bg_color(self.option(Opt::StatusBg).color());
+        self.terminal
+            .set_fg_color(self.option(Opt::StatusFg).color());
+        self.terminal.print(&title);
+        self.terminal.reset_bg_color();
+        self.terminal.reset_fg_color();
+
+        // Scrolls just enough to keep the current entry visible
+        let first = self
+            .quickfix
+            .index()
+            .saturating_add(1)
+            .saturating_sub(height);
+        for line in 0..height {
+            self.terminal.cursor_position(&Position {
+                x: 0,
+                y: top.saturating_add(line).saturating_add(1),
+            });
+            self.terminal.clear_current_line();
+            let index = first.saturating_add(line);
+            let Some(entry) = entries.get(index) else {
+                self.terminal.print("~");
+                continue;
+            };
+            let text = format!(
+                "{}|{} col {}| {}",
+                entry.path.display(),
+                entry.position.y.saturating_add(1),
+                entry.position.x.saturating_add(1),
+                entry.text
+            );
+            let text: String = text.chars().take(width).collect();
+            if index == self.quickfix.index() {
+                self.terminal
+                    .set_bg_color(self.option(Opt::HighlightBg).color());
+                self.terminal.print(&text);
+                self.terminal.reset_bg_color();
+            } else {
+                self.terminal.print(&text);
+            }
+        }
+    }
 
+    fn draw_tab_line(&self) {
+        // A label for every tab with the name of the buffer in its current
+        // window, the current tab's label stands out from the rest
         let width = self.terminal.size().width as usize;
-        let start = self.offset.x;
-        // Decrement size of coument in digits
-        let end = self.offset.x.saturating_add(width);
-        let row = row.render(start, end);
+        let mut used = 0;
+        self.terminal.cursor_position(&Position::default());
+        self.terminal
+            .set_fg_color(self.option(Opt::StatusFg).color());
+        for index in 0..self.tabs.len() {
+            let windows = if index == self.tabs.current() {
+                Some(&self.windows)
+            } else {
+                self.tabs.get(index)
+            };
+            let buffer = windows
+                .and_then(|windows| windows.get(windows.current()))
+                .map_or(0, |window| window.buffer);
+            let document = if index == self.tabs.current() {
+                &self.document
+            } else {
+                self.window_document(buffer)
+            };
+            // Listings keep the trailing '/' of their directory
+            let name =
+                document.file_name.as_deref().map_or("[No Name]", |name| {
+                    let start = name
+                        .trim_end_matches('/')
+                        .rfind('/')
+                        .map_or(0, |index| index.saturating_add(1));
+                    name.get(start..).unwrap_or(name)
+                });
+            let modified = if document.is_dirty() { " +" } else { "" };
+            let label =
+                format!(" {} {name}{modified} ", index.saturating_add(1));
+            let label: String =
+                label.chars().take(width.saturating_sub(used)).collect();
+            used = used.saturating_add(label.chars().count());
+            if index == self.tabs.current() {
+                self.terminal.reset_bg_color();
+            } else {
+                self.terminal
+                    .set_bg_color(self.option(Opt::StatusBg).color());
+            }
+            self.terminal.print(&label);
+        }
+        self.terminal
+            .set_bg_color(self.option(Opt::StatusBg).color());
+        self.terminal.print(&" ".repeat(width.saturating_sub(used)));
+        self.terminal.reset_bg_color();
+        self.terminal.reset_fg_color();
+    }
+
+    fn window_document(&self, buffer: usize) -> &Document {
+        // The current buffer's Document is the editor's, the others are
+        // in the buffer list
+        if self.buffers.number(self.buffers.current()) == Some(buffer) {
+            return &self.document;
+        }
+        self.buffers
+            .index_of(buffer)
+            .and_then(|index| self.buffers.get(index))
+            .map_or(&self.document, |buffer| &buffer.document)
+    }
+
+    fn draw_windows(&self) {
+        let area = self.window_area();
+        for (id, rect) in self.windows.layout(area) {
+            let current = id == self.windows.current();
+            let (document, cursor_position, offset, options) = if current {
+                (
+                    &self.document,
+                    &self.cursor_position,
+                    &self.offset,
+                    &self.window_options,
+                )
+            } else if let Some(window) = self.windows.get(id) {
+                (
+                    self.window_document(window.buffer),
+                    &window.cursor_position,
+                    &window.offset,
+                    &window.options,
+                )
+            } else {
+                continue;
+            };
+            let view = self.view_of(document, options, &rect);
+            self.draw_rows(document, &rect, &view, offset, current);
+            self.draw_status_bar(document, cursor_position, &rect, current);
+
+            // Windows that don't reach the right edge have a separator
+            // column after them
+            let right = rect.x.saturating_add(rect.width);
+            if right < area.width {
+                self.terminal
+                    .set_bg_color(self.option(Opt::StatusBg).color());
+                self.terminal
+                    .set_fg_color(self.option(Opt::StatusNcFg).color());
+                for y in rect.y..rect.y.saturating_add(rect.height) {
+                    self.terminal.cursor_position(&Position { x: right, y });
+                    self.terminal.print("|");
+                }
+                self.terminal.reset_bg_color();
+                self.terminal.reset_fg_color();
+            }
+        }
+    }
+
+    fn draw_rows(
+        &self,
+        document: &Document,
+        rect: &Rect,
+        view: &View,
+        offset: &Position,
+        current: bool,
+    ) {
+        // Only the current window shows the selection and the match, both
+        // of which include their last character
+        let highlight = if !current {
+            None
+        } else if self.mode == Mode::Visual {
+            Some(&self.hl_text)
+        } else {
+            self.match_hl.as_ref()
+        };
+        let highlight = highlight.map(|hl| {
+            if hl.start <= hl.end {
+                (&hl.start, &hl.end)
+            } else {
+                (&hl.end, &hl.start)
+            }
+        });
+        let lines = view.lines(document, offset);
+        let height = rect.height.saturating_sub(1);
+        for terminal_row in 0..height {
+            // The row is blanked first since the window may not span the
+            // whole terminal line
+            let position = Position {
+                x: rect.x,
+                y: rect.y.saturating_add(terminal_row),
+            };
+            self.terminal.cursor_position(&position);
+            self.terminal.print(&" ".repeat(rect.width));
+            self.terminal.cursor_position(&position);
+
+            if let Some(&(y, start)) = lines.get(terminal_row) {
+                if view.gutter > 0 {
+                    // Rows a wrapped line goes on in don't get a number
+                    let first = terminal_row == 0
+                        || lines
+                            .get(terminal_row.saturating_sub(1))
+                            .is_some_and(|(above, _)| *above != y);
+                    let number = if first {
+                        y.saturating_add(1).to_string()
+                    } else {
+                        String::new()
+                    };
+                    let width = view.gutter.saturating_sub(1);
+                    self.terminal
+                        .set_fg_color(self.option(Opt::NumberFg).color());
+                    self.terminal.print(&format!("{number:>width$} "));
+                    self.terminal.reset_fg_color();
+                }
+                if let Some(row) = document.row(y) {
+                    self.draw_row(row, y, start, view, highlight);
+                }
+            } else if document.is_empty() && terminal_row == height / 3 {
+                self.draw_welcome_message(rect.width);
+            } else {
+                self.terminal.print("~");
+            }
+        }
+    }
 
-        // 1
+    fn draw_row(
+        &self,
+        row: &Row,
+        y: usize,
+        start: usize,
+        view: &View,
+        highlight: Option<(&Position, &Position)>,
+    ) {
+        // The part of the line from column `start` that fits in the
+        // window, tabs drawn as the spaces they take
+        let highlighted = |x: usize| {
+            highlight.is_some_and(|(from, to)| {
+                let at = Position { x, y };
+                *from <= at && at <= *to
+            })
+        };
+        // An empty line in the selection shows as a single selected cell
         if row.is_empty() {
-            Terminal::set_bg_color(HIGHLIGHT_BG_COLOR);
-            println!(" \r");
-            Terminal::reset_bg_color();
+            if highlight.is_some_and(|(from, to)| from.y <= y && y <= to.y) {
+                self.terminal
+                    .set_bg_color(self.option(Opt::HighlightBg).color());
+                self.terminal.print(" ");
+                self.terminal.reset_bg_color();
+            }
             return;
         }
-
-        let (text_start, text_end) = if &self.hl_text.start <= &self.hl_text.end
+        let end = start.saturating_add(view.width);
+        let mut on = false;
+        for (x, (grapheme, column, width)) in
+            view::cells(row.as_str(), view.tabstop)
+                .into_iter()
+                .enumerate()
         {
-            (&self.hl_text.start, &self.hl_text.end)
-        } else {
-            (&self.hl_text.end, &self.hl_text.start)
-        };
-
-        // 2
-        if text_end.y == text_start.y {
-            let row_start = &row[..text_start.x];
-            let hl_text = &row[text_start.x..text_end.x + 1];
-            let row_end = &row[text_end.x + 1..];
-
-            print!("{row_start}");
-            Terminal::set_bg_color(HIGHLIGHT_BG_COLOR);
-            print!("{hl_text}");
-            Terminal::reset_bg_color();
-            println!("{row_end}\r");
-
-            // 3
-        } else if text_start.y == row_number {
-            let row_start = &row[..text_start.x];
-            let hl_text = &row[text_start.x..];
-
-            print!("{row_start}");
-            Terminal::set_bg_color(HIGHLIGHT_BG_COLOR);
-            print!("{hl_text}");
-            Terminal::reset_bg_color();
-            println!("\r");
-
-            // 4
-        } else if text_end.y == row_number {
-            let hl_text = &row[..text_end.x + 1];
-            let row_end = &row[text_end.x + 1..];
-
-            Terminal::set_bg_color(HIGHLIGHT_BG_COLOR);
-            print!("{hl_text}");
-            Terminal::reset_bg_color();
-            println!("{row_end}\r");
-        } else {
-            Terminal::set_bg_color(HIGHLIGHT_BG_COLOR);
-            println!("{row}\r");
-            Terminal::reset_bg_color();
+            if column >= end {
+                break;
+            }
+            let from = column.max(start);
+            let to = column.saturating_add(width).min(end);
+            if from >= to {
+                continue;
+            }
+            if highlighted(x) != on {
+                on = !on;
+                if on {
+                    self.terminal
+                        .set_bg_color(self.option(Opt::HighlightBg).color());
+                } else {
+                    self.terminal.reset_bg_color();
+                }
+            }
+            if grapheme == "\t" {
+                self.terminal.print(&" ".repeat(to.saturating_sub(from)));
+            } else if let Some(byte) = encoding::escaped_grapheme(grapheme) {
+                // Only the part of <xx> inside the window, as for tabs
+                let escape = format!("<{byte:02x}>");
+                let skip = from.saturating_sub(column);
+                let shown = escape.get(skip..to.saturating_sub(column));
+                self.terminal.print(shown.unwrap_or_default());
+            } else if let Some(picture) = control_picture(grapheme) {
+                // A stray '\r' and the like would move the terminal's
+                // cursor, their symbols are shown instead
+                self.terminal.print(&picture.to_string());
+            } else {
+                self.terminal.print(grapheme);
+            }
+        }
+        if on {
+            self.terminal.reset_bg_color();
+        }
+    }
+}
+
+fn parse_args(
+    args: &[String],
+) -> Result<(Vec<&String>, Option<Encoding>), String> {
+    // The file names given on the command line, and the encoding given
+    // with `--encoding NAME` or `--encoding=NAME` to read them in
+    let mut file_names = Vec::new();
+    let mut encoding = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let name = match arg.strip_prefix("--encoding") {
+            Some("") => args.next().map(String::as_str),
+            Some(rest) if rest.starts_with('=') => rest.get(1..),
+            _ => {
+                file_names.push(arg);
+                continue;
+            }
+        };
+        let name = name.unwrap_or_default();
+        encoding = Some(
+            Encoding::from_name(name)
+                .ok_or_else(|| format!("Err: unknown encoding {name}"))?,
+        );
+    }
+    Ok((file_names, encoding))
+}
+
+fn control_picture(grapheme: &str) -> Option<char> {
+    let mut chars = grapheme.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c @ '\0'..='\u{1f}'), None) => {
+            char::from_u32(0x2400_u32.saturating_add(u32::from(c)))
         }
+        (Some('\u{7f}'), None) => Some('\u{2421}'),
+        _ => None,
     }
 }
 
-fn die(e: &std::io::Error) {
-    Terminal::clear_screen();
+fn die(e: &std::io::Error) -> ! {
+    print!("{}", termion::clear::All);
     panic!("{}", e);
 }
+
+fn scroll_lines(
+    offset: &mut Position,
+    cursor: &mut Position,
+    len: usize,
+    height: usize,
+    down: bool,
+) {
+    // Moves the view of a document `len` lines long by WHEEL_LINES, and
+    // the cursor just enough to stay in it
+    offset.y = if down {
+        offset
+            .y
+            .saturating_add(WHEEL_LINES)
+            .min(len.saturating_sub(1))
+    } else {
+        offset.y.saturating_sub(WHEEL_LINES)
+    };
+    let last = offset.y.saturating_add(height).saturating_sub(1);
+    cursor.y = cursor.y.clamp(offset.y, last);
+}
+
+fn word_bounds(line: &str, x: usize) -> Option<(usize, usize)> {
+    // The first and last grapheme of the word at x, where a word is a run
+    // of letters, digits and '_', of blanks or of anything else
+    let graphemes: Vec<&str> = line.graphemes(true).collect();
+    let class = |grapheme: &&str| {
+        let c = grapheme.chars().next().unwrap_or(' ');
+        if c.is_alphanumeric() || c == '_' {
+            0
+        } else if c.is_whitespace() {
+            1
+        } else {
+            2
+        }
+    };
+    let kind = class(graphemes.get(x)?);
+    let start = graphemes
+        .get(..x)?
+        .iter()
+        .rposition(|grapheme| class(grapheme) != kind)
+        .map_or(0, |before| before.saturating_add(1));
+    let end = graphemes
+        .get(x..)?
+        .iter()
+        .position(|grapheme| class(grapheme) != kind)
+        .map_or(graphemes.len(), |after| x.saturating_add(after));
+    Some((start, end.saturating_sub(1)))
+}
+
+fn fit(text: &str, width: usize) -> String {
+    // Cuts the text down to `width` characters, or pads it with spaces
+    let text: String = text.chars().take(width).collect();
+    format!("{text:<width$}")
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+#[cfg(test)]
+impl Editor {
+    pub fn headless(
+        backend: &MemoryBackend,
+        file_names: &[String],
+    ) -> Result<Self, std::io::Error> {
+        // Runs the editor on `backend` until its keys run out
+        let terminal = Terminal::new(Box::new(backend.clone()))?;
+        let mut editor = Self::new(terminal, file_names);
+        while (backend.has_keys() || !editor.typeahead.is_empty())
+            && !editor.should_quit
+        {
+            editor.refresh_screen()?;
+            editor.process_keypress()?;
+        }
+        editor.refresh_screen()?;
+        Ok(editor)
+    }
+
+    pub fn document(&self) -> &Document {
+        &self.document
+    }
+
+    pub fn cursor_position(&self) -> &Position {
+        &self.cursor_position
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Editor, Mode};
+    use crate::backend::{MemoryBackend, FOCUS_IN, PASTE_END, PASTE_START};
+    use crate::encoding::Encoding;
+    use crate::fileformat::Layout;
+    use crate::hex::Column;
+    use crate::keymap::MapMode;
+    use crate::options::{Opt, Value};
+    use crate::swap::{self, Contents};
+    use crate::view;
+    use crate::Position;
+    use std::fs;
+    use termion::event::{Event, Key, MouseButton, MouseEvent};
+
+    fn typed(text: &str) -> Vec<Key> {
+        text.chars().map(Key::Char).collect()
+    }
+
+    fn run(keys: Vec<Key>) -> (Editor, MemoryBackend) {
+        let backend = MemoryBackend::new(40, 10);
+        backend.push_keys(keys);
+        let editor = Editor::headless(&backend, &[]).unwrap();
+        (editor, backend)
+    }
+
+    #[test]
+    fn test_insert() {
+        let keys = [typed("ihello"), vec![Key::Ctrl('c')], typed("oworld")];
+        let (editor, backend) = run(keys.concat());
+        assert_eq!(editor.document.text(), "hello\nworld");
+        assert_eq!(backend.line(1).trim_end(), "hello");
+        assert_eq!(backend.line(2).trim_end(), "world");
+        assert_eq!(backend.line(9).trim_end(), "-- INSERT --");
+        assert_eq!(backend.cursor(), Position { x: 5, y: 2 });
+
+        // The whole insert session is undone at once
+        let keys = [typed("ione two"), vec![Key::Ctrl('c')], typed("u")];
+        let (editor, _) = run(keys.concat());
+        assert_eq!(editor.document.text(), "");
+    }
+
+    #[test]
+    fn test_command_line() {
+        let keys = [
+            typed("ifoo foo"),
+            vec![Key::Ctrl('c')],
+            typed(":s/foo/bar/g\n"),
+        ];
+        let (editor, _) = run(keys.concat());
+        assert_eq!(editor.document.text(), "bar bar");
+
+        // Both windows show the same buffer, side by side
+        let keys = [typed("iab"), vec![Key::Ctrl('c')], typed(":vsp\n")];
+        let (_, backend) = run(keys.concat());
+        assert!(backend.line(1).starts_with("ab "));
+        assert_eq!(backend.line(1).matches("ab").count(), 2);
+        assert!(backend.line(1).contains('|'));
+    }
+
+    #[test]
+    fn test_global_undo() {
+        // With the undo history full, :g is still undone at once
+        let keys = [
+            typed(&format!("i{}\ndrop\nkeep\ndrop", "y".repeat(1100))),
+            vec![Key::Ctrl('c')],
+            typed(&format!("kkk{}", "x".repeat(1050))),
+            typed(":g/drop/d\nu"),
+        ];
+        let (editor, _) = run(keys.concat());
+        assert_eq!(
+            editor.document.text(),
+            format!("{}\ndrop\nkeep\ndrop", "y".repeat(50))
+        );
+    }
+
+    #[test]
+    fn test_expensive_global() {
+        // Every line is cheap to search on its own, but not all of them
+        let text = format!("{}b\n", "a".repeat(16)).repeat(40);
+        let keys = [
+            typed(&format!("i{text}")),
+            vec![Key::Ctrl('c')],
+            typed(":g/\\(a*\\)*c\\|b/d\n"),
+        ];
+        let (editor, backend) = run(keys.concat());
+        assert_eq!(editor.document.text(), text);
+        assert!(backend.line(9).starts_with("Pattern too expensive"));
+    }
+
+    #[test]
+    fn test_mouse() {
+        let press = |x, y| {
+            vec![
+                Event::Mouse(MouseEvent::Press(MouseButton::Left, x, y)),
+                Event::Mouse(MouseEvent::Release(x, y)),
+            ]
+        };
+        let edit = |events: Vec<Vec<Event>>, keys: &str| {
+            let backend = MemoryBackend::new(40, 10);
+            backend.push_keys(typed("ihello world\nfoo bar"));
+            backend.push_keys([Key::Ctrl('c')]);
+            backend.push_events(events.concat());
+            backend.push_keys(typed(keys));
+            Editor::headless(&backend, &[]).unwrap()
+        };
+
+        // The tab line is above the text, and terminal cells start at 1
+        let editor = edit(vec![press(3, 3)], "");
+        assert_eq!(editor.cursor_position, Position { x: 2, y: 1 });
+        let editor = edit(vec![press(8, 2), press(8, 2)], "x");
+        assert_eq!(editor.document.text(), "hello \nfoo bar");
+        let editor = edit(vec![press(8, 2), press(8, 2), press(8, 2)], "x");
+        assert_eq!(editor.document.text(), "foo bar");
+
+        let drag = vec![
+            Event::Mouse(MouseEvent::Press(MouseButton::Left, 3, 2)),
+            Event::Mouse(MouseEvent::Hold(4, 2)),
+            Event::Mouse(MouseEvent::Hold(2, 3)),
+            Event::Mouse(MouseEvent::Release(2, 3)),
+        ];
+        let editor = edit(vec![drag], "x");
+        assert_eq!(editor.document.text(), "heo bar");
+    }
+
+    #[test]
+    fn test_wheel() {
+        let wheel = |button| Event::Mouse(MouseEvent::Press(button, 1, 2));
+        let lines: Vec<String> = (1..=30).map(|n| n.to_string()).collect();
+        let backend = MemoryBackend::new(40, 10);
+        backend.push_keys(typed("i"));
+        backend.push_keys(typed(&lines.join("\n")));
+        backend.push_keys([Key::Ctrl('c')]);
+        // Seven lines of text fit, the cursor on the last one only moves
+        // once it would go out of view
+        backend.push_events([wheel(MouseButton::WheelUp)]);
+        let mut editor = Editor::headless(&backend, &[]).unwrap();
+        assert_eq!(editor.offset.y, 20);
+        assert_eq!(editor.cursor_position.y, 26);
+        backend.push_events([
+            wheel(MouseButton::WheelUp),
+            wheel(MouseButton::WheelDown),
+        ]);
+        while backend.has_keys() {
+            editor.process_keypress().unwrap();
+        }
+        assert_eq!(editor.offset.y, 20);
+        assert_eq!(editor.cursor_position.y, 23);
+    }
+
+    #[test]
+    fn test_paste() {
+        let paste = |text: &str| {
+            let mut events = vec![Event::Unsupported(PASTE_START.to_vec())];
+            events.extend(text.chars().map(|c| Event::Key(Key::Char(c))));
+            events.push(Event::Unsupported(PASTE_END.to_vec()));
+            events
+        };
+        let backend = MemoryBackend::new(40, 10);
+        backend.push_keys(typed("i>"));
+        backend.push_events(paste("ab\n\tcd"));
+        backend.push_keys(typed("<"));
+        let editor = Editor::headless(&backend, &[]).unwrap();
+        assert_eq!(editor.document.text(), ">ab\n\tcd<");
+        assert_eq!(editor.cursor_position, Position { x: 4, y: 1 });
+
+        // A paste in normal mode is undone at once
+        backend.push_events(paste("one\ntwo"));
+        let mut editor = Editor::headless(&backend, &[]).unwrap();
+        assert_eq!(editor.document.text(), "one\ntwo");
+        assert_eq!(editor.cursor_position, Position { x: 2, y: 1 });
+        backend.push_keys(typed("u"));
+        editor.process_keypress().unwrap();
+        assert_eq!(editor.document.text(), "");
+    }
+
+    #[test]
+    fn test_resize() {
+        let lines: Vec<String> = (1..=12).map(|n| n.to_string()).collect();
+        let keys = [
+            typed("i"),
+            typed(&lines.join("\n")),
+            vec![Key::Ctrl('c')],
+            typed(":sp\n"),
+        ];
+        let (mut editor, backend) = run(keys.concat());
+        backend.resize(20, 8);
+        editor.resize().unwrap();
+        // Both windows got shorter and still show the cursor's line
+        let rows: Vec<String> = (0..8)
+            .map(|y| backend.line(y).trim_end().to_string())
+            .collect();
+        assert!(rows.iter().all(|row| row.chars().count() <= 20));
+        assert_eq!(rows.iter().filter(|row| *row == "12").count(), 2);
+        assert_eq!(backend.line(backend.cursor().y).trim_end(), "12");
+        assert!(rows[3].starts_with("[No_name]"));
+        assert!(rows[6].starts_with("[No_name]"));
+        assert_eq!(backend.cursor(), Position { x: 2, y: 2 });
+    }
+
+    #[test]
+    fn test_set() {
+        // Tabs go to the next tabstop, line numbers take a gutter
+        let keys = [
+            typed("ia\tb"),
+            vec![Key::Ctrl('c')],
+            typed("h:set ts=4 nu\n"),
+        ];
+        let (editor, backend) = run(keys.concat());
+        assert_eq!(backend.line(1).trim_end(), "  1 a   b");
+        assert_eq!(backend.cursor(), Position { x: 8, y: 1 });
+        assert_eq!(editor.option(Opt::TabStop), Value::Number(4));
+
+        let keys = [typed(":set ts?\n")];
+        let (editor, _) = run(keys.concat());
+        assert_eq!(editor.status_message.text, "tabstop=8");
+        let (editor, _) = run(typed(":set ts=x\n"));
+        assert_eq!(editor.status_message.text, "Invalid argument: tabstop=x");
+
+        // :setlocal only changes the current window
+        let keys = [
+            typed("iab"),
+            vec![Key::Ctrl('c')],
+            typed(":vsp\n:setlocal nu\n"),
+        ];
+        let (_, backend) = run(keys.concat());
+        assert!(backend.line(1).starts_with("  1 ab "));
+        assert_eq!(backend.line(1).matches("  1 ").count(), 1);
+
+        // Long lines go on in the rows below
+        let keys = [typed(":set wrap\ni"), typed(&"x".repeat(50))];
+        let (_, backend) = run(keys.concat());
+        assert_eq!(backend.line(1).trim_end(), "x".repeat(40));
+        assert_eq!(backend.line(2).trim_end(), "x".repeat(10));
+        assert_eq!(backend.cursor(), Position { x: 10, y: 2 });
+    }
+
+    #[test]
+    fn test_map() {
+        let text = || [typed("iab\ncd"), vec![Key::Ctrl('c')]].concat();
+        let edit = |keys: &[&str]| {
+            let keys: Vec<Key> = keys.iter().flat_map(|k| typed(k)).collect();
+            run([text(), keys].concat()).0
+        };
+
+        // The leader, a mapping to a mapping, and mapping to ex commands
+        let editor = edit(&[":nnoremap <Leader>x dd\n", " x"]);
+        assert_eq!(editor.document.text(), "ab");
+        let editor = edit(&[":nmap Q dd\n:nmap X Q\n", "X"]);
+        assert_eq!(editor.document.text(), "ab");
+        let editor = edit(&[":nnoremap Q dd\n:nnoremap X Q\n", "X"]);
+        assert_eq!(editor.document.text(), "ab\ncd");
+        let editor =
+            edit(&[":set leader=,\n:nnoremap <Leader>s :%s/./x/g<CR>\n", ",s"]);
+        assert_eq!(editor.document.text(), "xx\nxx");
+        // In insert mode, after an operator and on the command line
+        let editor = edit(&[":inoremap jk <C-c>\n", "ojkyjx"]);
+        assert_eq!(editor.document.text(), "ab\ncd\n");
+        assert_eq!(editor.mode, Mode::Normal);
+        let editor = edit(&[":inoremap jk <C-c>\n", "ojx"]);
+        assert_eq!(editor.document.text(), "ab\ncd\njx");
+        let editor = edit(&[":onoremap l d\n", "kdl"]);
+        assert_eq!(editor.document.text(), "cd");
+        let editor = edit(&[":cnoremap %% %s/\n", ":%%c/y/\n"]);
+        assert_eq!(editor.document.text(), "ab\nyd");
+
+        let editor = edit(&[":nmap a b\n:nmap b a\n", "a"]);
+        assert_eq!(editor.status_message.text, "Recursive mapping");
+        let editor = edit(&[":nunmap Q\n"]);
+        assert_eq!(editor.status_message.text, "No such mapping");
+        let editor = edit(&[":nnoremap Q dd\n:nunmap Q\n", "Q"]);
+        assert_eq!(editor.document.text(), "ab\ncd");
+        let editor = edit(&[":nnoremap Q <lt>C-w>\n:nmap Q\n"]);
+        assert_eq!(editor.status_message.text, "n  Q            *<lt>C-w>");
+    }
+
+    #[test]
+    fn test_config() {
+        let (mut editor, _) = run(Vec::new());
+        let errors = editor.configure(
+            "mtx.conf",
+            "\" Indentation\nset ts=4 nu\nset frob\n:set scrolloff=3\nquit\nnnoremap Q dd\n",
+        );
+        assert_eq!(
+            errors,
+            vec![
+                "mtx.conf:3: Unknown option: frob".to_string(),
+                "mtx.conf:5: Not an editor command: quit".to_string(),
+            ]
+        );
+        assert_eq!(editor.option(Opt::TabStop), Value::Number(4));
+        assert!(editor.option(Opt::Number).flag());
+        assert_eq!(editor.option(Opt::ScrollOff), Value::Number(3));
+        assert_eq!(editor.keymap.list(&[MapMode::Normal], &[]).len(), 1);
+    }
+
+    #[test]
+    fn test_reload_config() {
+        let (mut editor, _) = run(Vec::new());
+        let file =
+            |text: &str| vec![("mtx.conf".to_string(), text.to_string())];
+        assert_eq!(
+            editor.reconfigure(&file("set ts=4 number\nnnoremap Q dd\n")),
+            Ok(())
+        );
+        assert_eq!(editor.option(Opt::TabStop), Value::Number(4));
+        // A broken file leaves the last good one in effect
+        assert_eq!(
+            editor.reconfigure(&file("set ts=2\nset frob\n")),
+            Err("mtx.conf:2: Unknown option: frob".to_string())
+        );
+        assert_eq!(editor.option(Opt::TabStop), Value::Number(4));
+        assert!(editor.option(Opt::Number).flag());
+        assert_eq!(editor.keymap.list(&[MapMode::Normal], &[]).len(), 1);
+        // What is no longer in the file goes back to the default
+        assert_eq!(editor.reconfigure(&file("set so=2\n")), Ok(()));
+        assert_eq!(editor.option(Opt::TabStop), Value::Number(8));
+        assert!(!editor.option(Opt::Number).flag());
+        assert_eq!(editor.option(Opt::ScrollOff), Value::Number(2));
+        assert!(editor.keymap.list(&[MapMode::Normal], &[]).is_empty());
+    }
+
+    #[test]
+    fn test_swap() {
+        let file = std::env::temp_dir()
+            .join(format!("mtx-recover-{}.txt", std::process::id()));
+        let name = file.to_string_lossy().to_string();
+        let path = swap::path(&file);
+        fs::write(&file, "saved\n").unwrap();
+        // Left by an editor that is long gone
+        let contents = Contents {
+            pid: u32::MAX,
+            text: vec!["unsaved".to_string()],
+            undo: vec![(vec!["saved".to_string()], Position::default())],
+            redo: Vec::new(),
+        };
+        let open = |keys: &str| {
+            fs::write(&path, contents.encode()).unwrap();
+            let backend = MemoryBackend::new(40, 10);
+            backend.push_keys(typed(keys));
+            Editor::headless(&backend, std::slice::from_ref(&name)).unwrap()
+        };
+
+        let editor = open("r");
+        assert_eq!(editor.document.text(), "unsaved");
+        assert!(editor.document.is_dirty());
+        let swapped = Contents::decode(&fs::read_to_string(&path).unwrap());
+        assert_eq!(swapped.unwrap().pid, std::process::id());
+        let editor = open("ru");
+        assert_eq!(editor.document.text(), "saved");
+
+        let mut editor = open("xo");
+        assert!(editor.document.read_only);
+        assert!(path.exists());
+        assert!(editor
+            .document
+            .save(None, Layout::default(), false)
+            .is_err());
+        assert_eq!(fs::read_to_string(&file).unwrap(), "saved\n");
+        editor.remove_swaps();
+        assert!(path.exists());
+        // Changes are refused, not only saving them
+        for keys in ["ox", "odd", "oia", "o:s/a/b/\n", "o:g/a/d\n"] {
+            let editor = open(keys);
+            assert_eq!(editor.document.text(), "saved");
+            assert_eq!(editor.status_message.text, "Buffer is read-only");
+        }
+
+        let editor = open("d");
+        assert_eq!(editor.document.text(), "saved");
+        assert!(!path.exists());
+        fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn test_changed_on_disk() {
+        let file = std::env::temp_dir()
+            .join(format!("mtx-disk-{}.txt", std::process::id()));
+        let name = file.to_string_lossy().to_string();
+        fs::write(&file, "one\n").unwrap();
+        let backend = MemoryBackend::new(40, 10);
+        let mut editor = Editor::headless(&backend, &[name]).unwrap();
+        let feed = |editor: &mut Editor, events: Vec<Event>| {
+            backend.push_events(events);
+            while backend.has_keys() {
+                editor.process_keypress().unwrap();
+            }
+        };
+        let focus = || Event::Unsupported(FOCUS_IN.to_vec());
+        let keys = |text: &str| typed(text).into_iter().map(Event::Key);
+
+        // Without changes of its own the buffer just follows the file
+        fs::write(&file, "two\n2\n").unwrap();
+        feed(&mut editor, vec![focus()]);
+        assert_eq!(editor.document.text(), "two\n2");
+        assert!(!editor.document.is_dirty());
+
+        // With some it asks, and the reload can be undone
+        feed(&mut editor, keys("x").collect());
+        fs::write(&file, "three\n").unwrap();
+        feed(&mut editor, vec![focus(), Event::Key(Key::Char('k'))]);
+        assert_eq!(editor.document.text(), "three");
+        feed(&mut editor, keys("u").collect());
+        assert_eq!(editor.document.text(), "wo\n2");
+
+        // Saving asks too, Esc leaves the file alone
+        fs::write(&file, "four\n").unwrap();
+        feed(&mut editor, vec![focus(), Event::Key(Key::Esc)]);
+        feed(
+            &mut editor,
+            keys(" s").chain([Event::Key(Key::Esc)]).collect(),
+        );
+        assert_eq!(fs::read_to_string(&file).unwrap(), "four\n");
+        feed(&mut editor, keys(" so").collect());
+        assert_eq!(fs::read_to_string(&file).unwrap(), "wo\n2\n");
+        assert!(!editor.document.is_dirty());
+        fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn test_file_format() {
+        let file = std::env::temp_dir()
+            .join(format!("mtx-format-{}.txt", std::process::id()));
+        let name = file.to_string_lossy().to_string();
+        fs::write(&file, "\u{feff}one\r\ntwo").unwrap();
+        let backend = MemoryBackend::new(40, 10);
+        backend.push_keys(typed("x s"));
+        let editor =
+            Editor::headless(&backend, std::slice::from_ref(&name)).unwrap();
+        assert_eq!(editor.document.text(), "ne\ntwo");
+        assert_eq!(fs::read_to_string(&file).unwrap(), "\u{feff}ne\r\ntwo");
+
+        // Converting marks the buffer as modified
+        backend.push_keys(typed(":set ff=unix eol nobomb\n"));
+        let editor =
+            Editor::headless(&backend, std::slice::from_ref(&name)).unwrap();
+        assert!(editor.document.is_dirty());
+        backend.push_keys(typed(":set ff=unix eol nobomb\n s"));
+        Editor::headless(&backend, &[name]).unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "ne\ntwo\n");
+        fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn test_new_file() {
+        // A file that doesn't exist yet still gets its buffer, and saving
+        // it creates the file
+        let dir = std::env::temp_dir();
+        let new = dir.join(format!("mtx-new-{}.txt", std::process::id()));
+        let old = dir.join(format!("mtx-old-{}.txt", std::process::id()));
+        fs::write(&old, "old\n").unwrap();
+        let names = [&new, &old].map(|file| file.to_string_lossy().to_string());
+        let backend = MemoryBackend::new(40, 10);
+        backend.push_keys(
+            [typed("inew"), vec![Key::Ctrl('c')], typed(" s")].concat(),
+        );
+        let editor = Editor::headless(&backend, &names).unwrap();
+        assert_eq!(editor.document.file_name.as_deref(), Some(&*names[0]));
+        assert_eq!(editor.buffers.len(), 2);
+        assert_eq!(fs::read_to_string(&new).unwrap(), "new\n");
+        fs::remove_file(&new).unwrap();
+        fs::remove_file(&old).unwrap();
+    }
+
+    #[test]
+    fn test_file_encoding() {
+        let file = std::env::temp_dir()
+            .join(format!("mtx-encoding-{}.txt", std::process::id()));
+        let name = file.to_string_lossy().to_string();
+        fs::write(&file, b"caf\xe9\n").unwrap();
+        let backend = MemoryBackend::new(40, 10);
+        backend.push_keys(
+            [typed("i!"), vec![Key::Ctrl('c')], typed(" s")].concat(),
+        );
+        let editor =
+            Editor::headless(&backend, std::slice::from_ref(&name)).unwrap();
+        assert_eq!(editor.document.text(), "!caf\u{e9}");
+        assert_eq!(fs::read(&file).unwrap(), b"!caf\xe9\n");
+
+        // Converting to another encoding
+        backend.push_keys(typed(":set fenc=utf-16\n s"));
+        Editor::headless(&backend, std::slice::from_ref(&name)).unwrap();
+        assert_eq!(fs::read(&file).unwrap(), b"\0!\0c\0a\0f\0\xe9\0\n");
+
+        // Bytes that aren't UTF-8 are kept as they were
+        fs::write(&file, b"\x00\xff\n").unwrap();
+        backend.push_keys(
+            [typed("i."), vec![Key::Ctrl('c')], typed(" s")].concat(),
+        );
+        let args = ["--encoding=utf-8".to_string(), name.clone()];
+        let editor = Editor::headless(&backend, &args).unwrap();
+        assert_eq!(editor.option(Opt::FileEncoding).encoding(), Encoding::Utf8);
+        assert_eq!(fs::read(&file).unwrap(), b".\x00\xff\n");
+        let row = editor.document.row(0).unwrap();
+        assert_eq!(view::cells(row.as_str(), 8)[2].2, 4);
+        fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn test_hex() {
+        let file = std::env::temp_dir()
+            .join(format!("mtx-hex-{}.bin", std::process::id()));
+        let name = file.to_string_lossy().to_string();
+        fs::write(&file, b"\x7fELF\x00\xff\nhello world, bytes\n").unwrap();
+        let backend = MemoryBackend::new(80, 10);
+        let keys = [
+            typed(":hex\n"),
+            // Overwriting a nibble, then a byte from the ASCII columns
+            typed("li0"),
+            vec![Key::Ctrl('c')],
+            typed("\t$rZ"),
+            // Searching for bytes
+            typed("/6c 6c\n"),
+        ];
+        backend.push_keys(keys.concat());
+        let mut editor =
+            Editor::headless(&backend, std::slice::from_ref(&name)).unwrap();
+        assert_eq!(
+            editor.document.row(0).unwrap().as_str(),
+            "00000000: 70 45 4c 46 00 ff 0a 68 65 6c 6c 6f 20 77 6f 5a  \
+             pELF...hello woZ"
+        );
+        assert_eq!(
+            editor.document.hex().unwrap().cell(&editor.cursor_position),
+            Some((9, Column::Ascii))
+        );
+        backend.push_keys(typed(":goto 0x13\n"));
+        while backend.has_keys() {
+            editor.process_keypress().unwrap();
+        }
+        let at = &editor.cursor_position;
+        assert_eq!(
+            editor.document.hex().unwrap().cell(at),
+            Some((0x13, Column::Ascii))
+        );
+        // Text commands don't get to change the rows
+        backend.push_keys(typed("ddx:s/0/1/\n"));
+        while backend.has_keys() {
+            editor.process_keypress().unwrap();
+        }
+        backend.push_keys(typed(" s"));
+        while backend.has_keys() {
+            editor.process_keypress().unwrap();
+        }
+        assert_eq!(
+            fs::read(&file).unwrap(),
+            b"pELF\x00\xff\nhello woZld, bytes\n"
+        );
+
+        // Back to the text, with the same bytes
+        backend.push_keys(typed(":hex\n"));
+        while backend.has_keys() {
+            editor.process_keypress().unwrap();
+        }
+        assert!(editor.document.hex().is_none());
+        assert_eq!(
+            editor.document.row(1).unwrap().as_str(),
+            "hello woZld, bytes"
+        );
+        fs::remove_file(&file).unwrap();
+    }
+}
@@ -0,0 +1,424 @@
+// Random edits for checking Document against a model of what its text
+// should end up being. The model is the document as a single String, as
+// it would be saved (every line ending in '\n'), where every edit is a
+// plain removal or insertion of characters. How Document treats positions
+// outside of its text is all in Edit::clamp(), which turns an edit into
+// one the model can apply. The edits are read off bytes, which come from a
+// seeded generator in the tests and from cargo-fuzz in fuzz/, with the
+// `fuzzing` feature that builds this module.
+
+use crate::Document;
+use crate::Position;
+use std::cmp::Ordering;
+
+// Characters that are a grapheme each, whatever is next to them, so the
+// model can count characters where Document counts graphemes
+const ALPHABET: [char; 5] = ['a', 'b', ' ', 'é', '日'];
+// Positions go a little past the end of the lines and of the document,
+// so that the edges are hit often
+const MAX_Y: u8 = 6;
+const MAX_X: u8 = 8;
+// Longest text inserted at once
+const MAX_TEXT: u8 = 6;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Edit {
+    Insert(Position, char),
+    InsertText(Position, String),
+    InsertNewline(Position),
+    Delete(Position),
+    DeleteSlice(Position, Position),
+    DeleteLines(usize, usize),
+    DeleteToEol(Position),
+}
+
+impl Edit {
+    #[must_use]
+    pub fn decode(bytes: &[u8]) -> Vec<Self> {
+        // Every edit takes a byte for its kind and a byte for each of its
+        // numbers and characters, the bytes that are left over at the end
+        // are ignored
+        let mut bytes = bytes.iter().copied();
+        let position = |bytes: &mut dyn Iterator<Item = u8>| {
+            let y = bytes.next()? % MAX_Y;
+            let x = bytes.next()? % MAX_X;
+            Some(Position {
+                x: x.into(),
+                y: y.into(),
+            })
+        };
+        let character = |bytes: &mut dyn Iterator<Item = u8>| {
+            let c = usize::from(bytes.next()?);
+            ALPHABET.get(c % ALPHABET.len()).copied()
+        };
+        let mut edits = Vec::new();
+        while let Some(kind) = bytes.next() {
+            let edit = match kind % 7 {
+                0 => position(&mut bytes).and_then(|at| {
+                    Some(Self::Insert(at, character(&mut bytes)?))
+                }),
+                6 => position(&mut bytes).and_then(|at| {
+                    // Line breaks are as likely as any other character
+                    let len = bytes.next()? % MAX_TEXT;
+                    let text = (0..len)
+                        .map(|_| {
+                            let c = bytes.next()?;
+                            if c % 4 == 0 {
+                                Some('\n')
+                            } else {
+                                character(&mut std::iter::once(c))
+                            }
+                        })
+                        .collect::<Option<String>>()?;
+                    Some(Self::InsertText(at, text))
+                }),
+                1 => position(&mut bytes).map(Self::InsertNewline),
+                2 => position(&mut bytes).map(Self::Delete),
+                3 => position(&mut bytes).and_then(|start| {
+                    Some(Self::DeleteSlice(start, position(&mut bytes)?))
+                }),
+                4 => bytes.next().and_then(|start| {
+                    let end = bytes.next()?;
+                    Some(Self::DeleteLines(
+                        (start % MAX_Y).into(),
+                        (end % MAX_Y).into(),
+                    ))
+                }),
+                _ => position(&mut bytes).map(Self::DeleteToEol),
+            };
+            match edit {
+                Some(edit) => edits.push(edit),
+                None => break,
+            }
+        }
+        edits
+    }
+
+    pub fn apply(&self, doc: &mut Document) {
+        match self {
+            Self::Insert(at, c) => doc.insert(at, *c),
+            Self::InsertText(at, text) => {
+                doc.insert_text(at, text);
+            }
+            Self::InsertNewline(at) => doc.insert_newline(at),
+            Self::Delete(at) => doc.delete(at),
+            Self::DeleteSlice(start, end) => doc.delete_slice(start, end),
+            Self::DeleteLines(start, end) => doc.delete_lines(*start, *end),
+            Self::DeleteToEol(at) => doc.delete_to_eol(at),
+        }
+    }
+
+    #[must_use]
+    pub fn clamp(&self, model: &Model) -> Option<Self> {
+        // What Document does with positions outside of the text, which is
+        // the only place the model knows about it: the edit that has the
+        // same effect on valid positions, or None when nothing changes.
+        // - Positions past the end of a line are at its end, and one line
+        //   past the last line is where inserting adds a new line
+        // - Deleting at the end of the last line does nothing, since there
+        //   is no next line to join
+        // - A slice from the start of a line to the last character of
+        //   another deletes those lines, newlines included, and one that
+        //   reaches past the last line ends at its end
+        let len = model.len();
+        let last = len.checked_sub(1);
+        let on_line = |at: &Position| Position {
+            x: at.x.min(model.line_len(at.y)),
+            y: at.y,
+        };
+        let insert_at = |at: &Position| match at.y.cmp(&len) {
+            Ordering::Less => Some(on_line(at)),
+            Ordering::Equal => Some(Position { x: 0, y: len }),
+            Ordering::Greater => None,
+        };
+        let edit = match self {
+            Self::Insert(at, c) => Self::Insert(insert_at(at)?, *c),
+            Self::InsertText(at, text) => {
+                Self::InsertText(insert_at(at)?, text.clone())
+            }
+            // Just past the last line, it only adds an empty line
+            Self::InsertNewline(at) if at.y == len => {
+                Self::InsertText(insert_at(at)?, String::new())
+            }
+            Self::InsertNewline(at) => Self::InsertNewline(insert_at(at)?),
+            Self::Delete(at) => {
+                let line_len = model.line_len(at.y);
+                let joins = at.x == line_len && Some(at.y) < last;
+                if at.y >= len || (at.x >= line_len && !joins) {
+                    return None;
+                }
+                Self::Delete(at.clone())
+            }
+            Self::DeleteSlice(start, end) => {
+                let last = last.filter(|last| start.y <= *last)?;
+                let start = on_line(start);
+                let mut end = if end.y > last {
+                    Position {
+                        x: model.line_len(last),
+                        y: last,
+                    }
+                } else {
+                    on_line(end)
+                };
+                let end_len = model.line_len(end.y);
+                if start > end {
+                    return None;
+                }
+                if start.x == 0 && end.x.saturating_add(1) >= end_len {
+                    return Some(Self::DeleteLines(start.y, end.y));
+                }
+                if end.y == last && end.x == end_len {
+                    // The last newline stays, so the slice ends before it
+                    end = model.before(&end)?;
+                    if start > end {
+                        return None;
+                    }
+                }
+                Self::DeleteSlice(start, end)
+            }
+            Self::DeleteLines(start, end) => {
+                let end = (*end).min(last?);
+                if *start > end {
+                    return None;
+                }
+                Self::DeleteLines(*start, end)
+            }
+            Self::DeleteToEol(at) => {
+                if at.y >= len {
+                    return None;
+                }
+                let at = on_line(at);
+                if Some(at.y) < last {
+                    Self::DeleteToEol(at)
+                } else {
+                    // The last line keeps its newline
+                    let end = model.before(&Position {
+                        x: model.line_len(at.y),
+                        y: at.y,
+                    })?;
+                    if at > end {
+                        return None;
+                    }
+                    Self::DeleteSlice(at, end)
+                }
+            }
+        };
+        Some(edit)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Model {
+    text: String,
+}
+
+impl Model {
+    #[must_use]
+    pub fn text(&self) -> &str {
+        // Same as Document::text(), without the last newline
+        self.text.strip_suffix('\n').unwrap_or(&self.text)
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.text.matches('\n').count()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    fn line(&self, y: usize) -> Option<&str> {
+        // Without its newline
+        self.text.lines().nth(y)
+    }
+
+    fn line_len(&self, y: usize) -> usize {
+        self.line(y).map_or(0, |line| line.chars().count())
+    }
+
+    fn offset(&self, at: &Position) -> usize {
+        // Byte offset of `at`, where the end of a line is its newline and
+        // the line after the last one is the end of the text
+        let start: usize = self
+            .text
+            .split_inclusive('\n')
+            .take(at.y)
+            .map(str::len)
+            .sum();
+        let line = self.line(at.y).unwrap_or_default();
+        let x = line.char_indices().nth(at.x).map_or(line.len(), |(x, _)| x);
+        start.saturating_add(x)
+    }
+
+    fn after(&self, at: &Position) -> usize {
+        // Byte offset of the character after the one at `at`
+        let offset = self.offset(at);
+        let len = self.text.get(offset..).and_then(|rest| rest.chars().next());
+        offset.saturating_add(len.map_or(0, char::len_utf8))
+    }
+
+    fn before(&self, at: &Position) -> Option<Position> {
+        // The character (or newline) before `at`
+        if let Some(x) = at.x.checked_sub(1) {
+            return Some(Position { x, y: at.y });
+        }
+        let y = at.y.checked_sub(1)?;
+        Some(Position {
+            x: self.line_len(y),
+            y,
+        })
+    }
+
+    pub fn apply(&mut self, edit: &Edit) {
+        // Plain editing of the text, for edits that went through
+        // Edit::clamp(). Every line ends in a newline, so text inserted
+        // after the last line is a line of its own
+        let remove = |text: &mut String, start: usize, end: usize| {
+            text.replace_range(start..end.max(start), "");
+        };
+        match edit {
+            Edit::Insert(at, c) => self.insert(at, c.encode_utf8(&mut [0; 4])),
+            Edit::InsertText(at, text) => self.insert(at, text),
+            Edit::InsertNewline(at) => self.insert(at, "\n"),
+            Edit::Delete(at) => {
+                let (start, end) = (self.offset(at), self.after(at));
+                remove(&mut self.text, start, end);
+            }
+            Edit::DeleteSlice(start, end) => {
+                let (start, end) = (self.offset(start), self.after(end));
+                remove(&mut self.text, start, end);
+            }
+            Edit::DeleteLines(start, end) => {
+                let start = self.offset(&Position { x: 0, y: *start });
+                let end = self.offset(&Position {
+                    x: 0,
+                    y: end.saturating_add(1),
+                });
+                remove(&mut self.text, start, end);
+            }
+            Edit::DeleteToEol(at) => {
+                let start = self.offset(at);
+                let end = self.offset(&Position {
+                    x: 0,
+                    y: at.y.saturating_add(1),
+                });
+                remove(&mut self.text, start, end);
+            }
+        }
+    }
+
+    fn insert(&mut self, at: &Position, text: &str) {
+        let new_line = at.y == self.len();
+        let offset = self.offset(at);
+        self.text.insert_str(offset, text);
+        if new_line {
+            self.text.push('\n');
+        }
+    }
+}
+
+pub fn check(bytes: &[u8]) -> Result<(), String> {
+    // Applies the edits in `bytes` to both a Document and a Model,
+    // failing at the first edit after which they differ
+    let mut doc = Document::default();
+    let mut model = Model::default();
+    let edits = Edit::decode(bytes);
+    for (index, edit) in edits.iter().enumerate() {
+        let clamped = edit.clamp(&model);
+        edit.apply(&mut doc);
+        if let Some(edit) = clamped {
+            model.apply(&edit);
+        }
+        if doc.text() != model.text() || doc.len() != model.len() {
+            return Err(format!(
+                "after {:?}\nexpected {:?} ({} lines)\ngot {:?} ({} lines)",
+                edits.get(..=index).unwrap_or_default(),
+                model.text(),
+                model.len(),
+                doc.text(),
+                doc.len()
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, Edit, Model};
+    use crate::Position;
+
+    struct Random(u64);
+
+    impl Random {
+        fn byte(&mut self) -> u8 {
+            // xorshift64
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0.to_le_bytes()[0]
+        }
+    }
+
+    #[test]
+    fn test_model() {
+        let mut model = Model::default();
+        let at = |x, y| Position { x, y };
+        model.apply(&Edit::Insert(at(0, 0), 'a'));
+        model.apply(&Edit::Insert(at(1, 0), 'b'));
+        model.apply(&Edit::InsertNewline(at(1, 0)));
+        model.apply(&Edit::InsertText(at(0, 2), "日\nc".to_string()));
+        assert_eq!(model.text(), "a\nb\n日\nc");
+        model.apply(&Edit::Delete(at(1, 0)));
+        assert_eq!(model.text(), "ab\n日\nc");
+        model.apply(&Edit::DeleteSlice(at(1, 0), at(0, 1)));
+        assert_eq!(model.text(), "a\nc");
+        model.apply(&Edit::DeleteToEol(at(0, 0)));
+        assert_eq!(model.text(), "c");
+        model.apply(&Edit::DeleteLines(0, 0));
+        assert!(model.is_empty());
+    }
+
+    #[test]
+    fn test_clamp() {
+        let mut model = Model::default();
+        let at = |x, y| Position { x, y };
+        model.apply(&Edit::InsertText(at(0, 0), "ab\ncd".to_string()));
+        let clamp = |edit: Edit| edit.clamp(&model);
+        assert_eq!(
+            clamp(Edit::Insert(at(5, 0), 'x')),
+            Some(Edit::Insert(at(2, 0), 'x'))
+        );
+        assert_eq!(
+            clamp(Edit::InsertNewline(at(3, 2))),
+            Some(Edit::InsertText(at(0, 2), String::new()))
+        );
+        assert_eq!(clamp(Edit::Insert(at(0, 3), 'x')), None);
+        assert_eq!(clamp(Edit::Delete(at(2, 1))), None);
+        assert_eq!(
+            clamp(Edit::DeleteSlice(at(0, 0), at(9, 0))),
+            Some(Edit::DeleteLines(0, 0))
+        );
+        assert_eq!(
+            clamp(Edit::DeleteSlice(at(1, 0), at(0, 5))),
+            Some(Edit::DeleteSlice(at(1, 0), at(1, 1)))
+        );
+        assert_eq!(
+            clamp(Edit::DeleteToEol(at(1, 1))),
+            Some(Edit::DeleteSlice(at(1, 1), at(1, 1)))
+        );
+    }
+
+    #[test]
+    fn test_random_edits() {
+        for seed in 1..=2000 {
+            let mut random = Random(seed);
+            let bytes: Vec<u8> = (0..300).map(|_| random.byte()).collect();
+            if let Err(error) = check(&bytes) {
+                panic!("seed {}: {}", seed, error);
+            }
+        }
+    }
+}
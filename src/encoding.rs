@@ -0,0 +1,295 @@
+// The encodings files are read and written in ('fileencoding'). Which one
+// a file is in is told by its byte order mark, or else by whether it is
+// valid UTF-8, looks like UTF-16 (every other byte zero, as ASCII text in
+// it is) or has NUL bytes (binary, read as UTF-8), and is latin1
+// otherwise, where every byte is a character.
+//
+// Bytes that aren't valid in the file's encoding are kept as characters of
+// their own, one per byte, taken from the end of the private use planes.
+// They are shown as <xx> and written back as the byte they stand for, so
+// any file comes back out as it went in. A real character from that range
+// is kept as its bytes the same way.
+
+use std::convert::TryFrom;
+use std::str;
+
+// Escapes are ESCAPE_BASE + the byte, up to U+10FFFF
+const ESCAPE_BASE: u32 = 0x0010_ff00;
+// Columns an escaped byte takes, as in <xx>
+pub const ESCAPE_WIDTH: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Utf8,
+    Latin1,
+    Utf16Le,
+    Utf16Be,
+}
+
+impl Encoding {
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "utf-8",
+            Encoding::Latin1 => "latin1",
+            Encoding::Utf16Le => "utf-16le",
+            Encoding::Utf16Be => "utf-16",
+        }
+    }
+
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => Some(Encoding::Utf8),
+            "latin1" | "latin-1" | "iso-8859-1" => Some(Encoding::Latin1),
+            "utf-16le" | "ucs-2le" => Some(Encoding::Utf16Le),
+            "utf-16" | "utf-16be" | "ucs-2" => Some(Encoding::Utf16Be),
+            _ => None,
+        }
+    }
+
+    fn bom(self) -> &'static [u8] {
+        match self {
+            Encoding::Utf8 => b"\xef\xbb\xbf",
+            Encoding::Latin1 => b"",
+            Encoding::Utf16Le => b"\xff\xfe",
+            Encoding::Utf16Be => b"\xfe\xff",
+        }
+    }
+}
+
+fn escape(byte: u8) -> char {
+    char::from_u32(ESCAPE_BASE.saturating_add(u32::from(byte)))
+        .unwrap_or(char::REPLACEMENT_CHARACTER)
+}
+
+#[must_use]
+pub fn escaped(c: char) -> Option<u8> {
+    // The byte an escape stands for
+    u32::from(c)
+        .checked_sub(ESCAPE_BASE)
+        .and_then(|byte| u8::try_from(byte).ok())
+}
+
+#[must_use]
+pub fn escaped_grapheme(grapheme: &str) -> Option<u8> {
+    let mut chars = grapheme.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => escaped(c),
+        _ => None,
+    }
+}
+
+fn push_char(text: &mut String, c: char, bytes: &[u8]) {
+    // `bytes` is how `c` was written in the file
+    if escaped(c).is_some() {
+        text.extend(bytes.iter().copied().map(escape));
+    } else {
+        text.push(c);
+    }
+}
+
+fn detect(bytes: &[u8]) -> Encoding {
+    // Zeros at even and odd offsets, from ASCII in big and little endian
+    let (mut even, mut odd) = (0_usize, 0_usize);
+    for pair in bytes.chunks_exact(2) {
+        match pair {
+            [0, c] if *c != 0 => even = even.saturating_add(1),
+            [c, 0] if *c != 0 => odd = odd.saturating_add(1),
+            _ => (),
+        }
+    }
+    // UTF-16 is looked for first since NULs are valid UTF-8 too, and
+    // taken when most of the characters are ASCII
+    let units = bytes.len() / 2;
+    let even_length = bytes.len().is_multiple_of(2);
+    if even_length && even == 0 && odd.saturating_mul(2) > units {
+        Encoding::Utf16Le
+    } else if even_length && odd == 0 && even.saturating_mul(2) > units {
+        Encoding::Utf16Be
+    } else if str::from_utf8(bytes).is_ok() || bytes.contains(&0) {
+        Encoding::Utf8
+    } else {
+        Encoding::Latin1
+    }
+}
+
+fn decode_utf8(text: &mut String, mut bytes: &[u8]) {
+    loop {
+        let (valid, invalid) = match str::from_utf8(bytes) {
+            Ok(valid) => (valid, 0),
+            Err(error) => {
+                let (valid, rest) = bytes.split_at(error.valid_up_to());
+                let valid = str::from_utf8(valid).unwrap_or_default();
+                (valid, error.error_len().unwrap_or(rest.len()))
+            }
+        };
+        for c in valid.chars() {
+            let mut buffer = [0; 4];
+            push_char(text, c, c.encode_utf8(&mut buffer).as_bytes());
+        }
+        let rest = bytes.get(valid.len()..).unwrap_or_default();
+        if invalid == 0 {
+            return;
+        }
+        text.extend(rest.iter().take(invalid).copied().map(escape));
+        bytes = rest.get(invalid..).unwrap_or_default();
+    }
+}
+
+fn decode_utf16(text: &mut String, bytes: &[u8], little_endian: bool) {
+    let pairs = bytes.chunks_exact(2);
+    let odd = pairs.remainder();
+    let mut units = pairs.map(|pair| {
+        let pair = [pair.first().copied(), pair.get(1).copied()]
+            .map(Option::unwrap_or_default);
+        if little_endian {
+            u16::from_le_bytes(pair)
+        } else {
+            u16::from_be_bytes(pair)
+        }
+    });
+    let unit_bytes = |unit: u16| {
+        if little_endian {
+            unit.to_le_bytes()
+        } else {
+            unit.to_be_bytes()
+        }
+    };
+    for result in char::decode_utf16(&mut units) {
+        match result {
+            Ok(c) => {
+                let mut buffer = [0; 2];
+                let bytes: Vec<u8> = c
+                    .encode_utf16(&mut buffer)
+                    .iter()
+                    .flat_map(|unit| unit_bytes(*unit))
+                    .collect();
+                push_char(text, c, &bytes);
+            }
+            Err(error) => text.extend(
+                unit_bytes(error.unpaired_surrogate())
+                    .iter()
+                    .copied()
+                    .map(escape),
+            ),
+        }
+    }
+    text.extend(odd.iter().copied().map(escape));
+}
+
+#[must_use]
+pub fn decode(
+    bytes: &[u8],
+    forced: Option<Encoding>,
+) -> (String, Encoding, bool) {
+    // The text of a file, the encoding it was in and whether it had a byte
+    // order mark
+    let marked = [Encoding::Utf8, Encoding::Utf16Le, Encoding::Utf16Be]
+        .iter()
+        .copied()
+        .filter(|encoding| forced.is_none_or(|forced| forced == *encoding))
+        .find(|encoding| bytes.starts_with(encoding.bom()));
+    let encoding = marked.or(forced).unwrap_or_else(|| detect(bytes));
+    let bom = marked.is_some();
+    let bytes = if bom {
+        bytes.get(encoding.bom().len()..).unwrap_or_default()
+    } else {
+        bytes
+    };
+    let mut text = String::with_capacity(bytes.len());
+    match encoding {
+        Encoding::Utf8 => decode_utf8(&mut text, bytes),
+        Encoding::Latin1 => text.extend(bytes.iter().copied().map(char::from)),
+        Encoding::Utf16Le => decode_utf16(&mut text, bytes, true),
+        Encoding::Utf16Be => decode_utf16(&mut text, bytes, false),
+    }
+    (text, encoding, bom)
+}
+
+pub fn encode(
+    text: &str,
+    encoding: Encoding,
+    bom: bool,
+) -> Result<Vec<u8>, String> {
+    // The opposite of decode(), failing on characters the encoding has no
+    // bytes for
+    let mut bytes = Vec::with_capacity(text.len());
+    if bom {
+        bytes.extend_from_slice(encoding.bom());
+    }
+    for c in text.chars() {
+        if let Some(byte) = escaped(c) {
+            bytes.push(byte);
+            continue;
+        }
+        match encoding {
+            Encoding::Utf8 => {
+                let mut buffer = [0; 4];
+                bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+            }
+            Encoding::Latin1 => bytes.push(
+                u8::try_from(u32::from(c))
+                    .map_err(|_| format!("Can't write {c} in latin1"))?,
+            ),
+            Encoding::Utf16Le | Encoding::Utf16Be => {
+                let mut buffer = [0; 2];
+                for unit in c.encode_utf16(&mut buffer) {
+                    if encoding == Encoding::Utf16Le {
+                        bytes.extend_from_slice(&unit.to_le_bytes());
+                    } else {
+                        bytes.extend_from_slice(&unit.to_be_bytes());
+                    }
+                }
+            }
+        }
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, encode, escaped, Encoding};
+
+    #[test]
+    fn test_round_trip() {
+        let files: [(&[u8], Encoding, bool); 8] = [
+            (b"plain\n", Encoding::Utf8, false),
+            ("caf\u{e9}\n".as_bytes(), Encoding::Utf8, false),
+            (b"\xef\xbb\xbfbom\n", Encoding::Utf8, true),
+            (b"caf\xe9\n", Encoding::Latin1, false),
+            (b"\xff\xfeh\x00i\x00", Encoding::Utf16Le, true),
+            (b"h\x00i\x00\n\x00", Encoding::Utf16Le, false),
+            (b"\x00h\x00i", Encoding::Utf16Be, false),
+            // Binary, with bytes that aren't UTF-8
+            (b"\x7fELF\x02\x00\xff\xc3", Encoding::Utf8, false),
+        ];
+        for (bytes, encoding, bom) in files {
+            let (text, found, has_bom) = decode(bytes, None);
+            assert_eq!((found, has_bom), (encoding, bom), "{bytes:?}");
+            assert_eq!(encode(&text, encoding, bom).unwrap(), bytes);
+        }
+        let (text, ..) = decode(b"\x00\x01\xff\n", None);
+        assert_eq!(
+            text.chars().filter_map(escaped).collect::<Vec<_>>(),
+            [0xff]
+        );
+        // A character that looks like an escape is kept as its bytes
+        let odd = "\u{10ff41}".as_bytes();
+        let (text, ..) = decode(odd, None);
+        assert_eq!(text.chars().count(), 4);
+        assert_eq!(encode(&text, Encoding::Utf8, false).unwrap(), odd);
+    }
+
+    #[test]
+    fn test_convert() {
+        let (text, ..) = decode(b"caf\xe9", Some(Encoding::Latin1));
+        assert_eq!(text, "caf\u{e9}");
+        assert_eq!(
+            encode(&text, Encoding::Utf16Be, true).unwrap(),
+            b"\xfe\xff\x00c\x00a\x00f\x00\xe9"
+        );
+        assert!(encode("\u{263a}", Encoding::Latin1, false).is_err());
+        assert_eq!(Encoding::from_name("ISO-8859-1"), Some(Encoding::Latin1));
+    }
+}
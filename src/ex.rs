@@ -0,0 +1,964 @@
+// Command line (ex) commands, entered with ':' in normal mode.
+//
+// This module only parses a command line into a `Command`, executing it
+// is up to the Editor since most commands need the whole editor state.
+// Line numbers are 1-based in the command line and 0-based everywhere
+// else, the conversion happens in Range::resolve().
+
+use crate::finder::Source;
+use crate::keymap::MapMode;
+use std::convert::TryFrom;
+use std::mem;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Base {
+    Current,
+    Last,
+    Line(usize),
+    SelectionStart,
+    SelectionEnd,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Address {
+    base: Base,
+    offset: isize,
+}
+
+impl Address {
+    fn current() -> Self {
+        Self {
+            base: Base::Current,
+            offset: 0,
+        }
+    }
+
+    fn resolve(
+        &self,
+        current: usize,
+        len: usize,
+        selection: Option<(usize, usize)>,
+    ) -> Result<usize, String> {
+        let line = match self.base {
+            Base::Current => current,
+            Base::Last => len.saturating_sub(1),
+            Base::Line(line) => line.saturating_sub(1),
+            Base::SelectionStart | Base::SelectionEnd => {
+                let Some((start, end)) = selection else {
+                    return Err("Mark not set".to_string());
+                };
+                if self.base == Base::SelectionStart {
+                    start
+                } else {
+                    end
+                }
+            }
+        };
+        match line.checked_add_signed(self.offset) {
+            Some(line) if line < len.max(1) => Ok(line),
+            _ => Err("Invalid range".to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Range {
+    start: Address,
+    end: Address,
+}
+
+impl Range {
+    #[must_use]
+    pub fn current() -> Self {
+        Self {
+            start: Address::current(),
+            end: Address::current(),
+        }
+    }
+
+    #[must_use]
+    pub fn all() -> Self {
+        Self {
+            start: Address {
+                base: Base::Line(1),
+                offset: 0,
+            },
+            end: Address {
+                base: Base::Last,
+                offset: 0,
+            },
+        }
+    }
+
+    pub fn resolve(
+        &self,
+        current: usize,
+        len: usize,
+        selection: Option<(usize, usize)>,
+    ) -> Result<(usize, usize), String> {
+        // Returns the first and last line (inclusive) of the range,
+        // a backwards range is silently swapped
+        let start = self.start.resolve(current, len, selection)?;
+        let end = self.end.resolve(current, len, selection)?;
+        Ok((start.min(end), start.max(end)))
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct SubstituteFlags {
+    pub global: bool,
+    pub confirm: bool,
+    pub count_only: bool,
+    // `i` and `I` override the ignorecase setting for one substitution
+    pub ignore_case: Option<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Substitute {
+        range: Range,
+        // None reuses the last search pattern, as in `:s//new/`
+        pattern: Option<String>,
+        replacement: String,
+        flags: SubstituteFlags,
+    },
+    Delete {
+        range: Range,
+    },
+    // `:g/pattern/command`, or `:v` when `invert` is set
+    Global {
+        range: Range,
+        pattern: Option<String>,
+        invert: bool,
+        command: String,
+    },
+    // `:grep pattern [paths]`, searching the current directory when no
+    // path is given
+    Grep {
+        pattern: Option<String>,
+        paths: Vec<String>,
+    },
+    // `:cn` and `:cp`
+    QuickfixStep {
+        forward: bool,
+    },
+    QuickfixOpen,
+    QuickfixClose,
+    // `:e file`, opening it in a new buffer unless it already has one
+    Edit {
+        file_name: String,
+    },
+    ListBuffers,
+    // `:b name`, where name can also be a buffer number
+    Buffer {
+        name: Option<String>,
+    },
+    // `:bn` and `:bp`
+    BufferStep {
+        forward: bool,
+    },
+    // `:bd[!] [name]`, deletes the current buffer when no name is given
+    BufferDelete {
+        name: Option<String>,
+        force: bool,
+    },
+    // `:sp [file]` and `:vsp [file]`
+    Split {
+        vertical: bool,
+        file_name: Option<String>,
+    },
+    CloseWindow,
+    OnlyWindow,
+    // `:tabnew [file]`
+    NewTab {
+        file_name: Option<String>,
+    },
+    // `:tabn` and `:tabp`
+    TabStep {
+        forward: bool,
+    },
+    CloseTab,
+    // `:Files`, `:Buffers` and `:History` open the fuzzy finder
+    Find {
+        source: Source,
+    },
+    // `:Explore [dir]`, without a directory it lists the one of the current
+    // file
+    Explore {
+        dir: Option<String>,
+    },
+    // `:set opt ...`, or `:setlocal` to only change the current buffer or
+    // window, see options.rs for what the arguments can be
+    Set {
+        args: Vec<String>,
+        local: bool,
+    },
+    // `:map lhs rhs` and `:noremap lhs rhs`, or the versions for a single
+    // mode like `:nnoremap`, both sides in key notation. Without a rhs
+    // they list the mappings
+    Map {
+        modes: Vec<MapMode>,
+        lhs: Option<String>,
+        rhs: Option<String>,
+        noremap: bool,
+    },
+    // `:unmap lhs`, `:nunmap lhs` and so on
+    Unmap {
+        modes: Vec<MapMode>,
+        lhs: String,
+    },
+    // `:hex` shows the buffer as bytes, or as text again when it already is
+    Hex,
+    // `:goto [offset]`, to a byte of the file counted from 0 (in decimal,
+    // or hex after `0x`) unlike vim's, which counts from 1
+    Goto {
+        offset: usize,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MapKind {
+    Map,
+    Noremap,
+    Unmap,
+}
+
+fn map_command(name: &str) -> Option<(Option<MapMode>, MapKind)> {
+    // The mode a mapping command is for, None being the modes of :map
+    let command = match name {
+        "map" => (None, MapKind::Map),
+        "no" | "noremap" => (None, MapKind::Noremap),
+        "unm" | "unmap" => (None, MapKind::Unmap),
+        "nm" | "nmap" => (Some(MapMode::Normal), MapKind::Map),
+        "nn" | "nnoremap" => (Some(MapMode::Normal), MapKind::Noremap),
+        "nun" | "nunmap" => (Some(MapMode::Normal), MapKind::Unmap),
+        "vm" | "vmap" | "xm" | "xmap" => (Some(MapMode::Visual), MapKind::Map),
+        "vn" | "vnoremap" | "xn" | "xnoremap" => {
+            (Some(MapMode::Visual), MapKind::Noremap)
+        }
+        "vu" | "vunmap" | "xu" | "xunmap" => {
+            (Some(MapMode::Visual), MapKind::Unmap)
+        }
+        "om" | "omap" => (Some(MapMode::OperatorPending), MapKind::Map),
+        "ono" | "onoremap" => {
+            (Some(MapMode::OperatorPending), MapKind::Noremap)
+        }
+        "ou" | "ounmap" => (Some(MapMode::OperatorPending), MapKind::Unmap),
+        "im" | "imap" => (Some(MapMode::Insert), MapKind::Map),
+        "ino" | "inoremap" => (Some(MapMode::Insert), MapKind::Noremap),
+        "iu" | "iunmap" => (Some(MapMode::Insert), MapKind::Unmap),
+        "cm" | "cmap" => (Some(MapMode::CommandLine), MapKind::Map),
+        "cno" | "cnoremap" => (Some(MapMode::CommandLine), MapKind::Noremap),
+        "cu" | "cunmap" => (Some(MapMode::CommandLine), MapKind::Unmap),
+        _ => return None,
+    };
+    Some(command)
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek();
+        if c.is_some() {
+            self.pos = self.pos.saturating_add(1);
+        }
+        c
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos = self.pos.saturating_add(1);
+            return true;
+        }
+        false
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos = self.pos.saturating_add(1);
+        }
+    }
+
+    fn rest(&self) -> String {
+        self.chars.get(self.pos..).unwrap_or(&[]).iter().collect()
+    }
+
+    fn number(&mut self) -> Option<usize> {
+        let mut number: Option<usize> = None;
+        while let Some(digit) = self.peek().and_then(|c| c.to_digit(10)) {
+            self.pos = self.pos.saturating_add(1);
+            number = Some(
+                number
+                    .unwrap_or(0)
+                    .saturating_mul(10)
+                    .saturating_add(digit as usize),
+            );
+        }
+        number
+    }
+
+    fn range(&mut self) -> Result<Option<Range>, String> {
+        self.skip_whitespace();
+        if self.eat('%') {
+            return Ok(Some(Range::all()));
+        }
+        let Some(start) = self.address()? else {
+            return Ok(None);
+        };
+        let end = if self.eat(',') {
+            match self.address()? {
+                Some(end) => end,
+                None => return Err("Invalid range".to_string()),
+            }
+        } else {
+            start.clone()
+        };
+        Ok(Some(Range { start, end }))
+    }
+
+    fn address(&mut self) -> Result<Option<Address>, String> {
+        let base = match self.peek() {
+            Some('.') => {
+                self.pos = self.pos.saturating_add(1);
+                Some(Base::Current)
+            }
+            Some('$') => {
+                self.pos = self.pos.saturating_add(1);
+                Some(Base::Last)
+            }
+            Some('\'') => {
+                self.pos = self.pos.saturating_add(1);
+                match self.bump() {
+                    Some('<') => Some(Base::SelectionStart),
+                    Some('>') => Some(Base::SelectionEnd),
+                    _ => return Err("Unknown mark".to_string()),
+                }
+            }
+            Some(c) if c.is_ascii_digit() => self.number().map(Base::Line),
+            _ => None,
+        };
+
+        let mut offset: isize = 0;
+        let mut has_offset = false;
+        while let Some(sign) = self.peek().filter(|c| *c == '+' || *c == '-') {
+            self.pos = self.pos.saturating_add(1);
+            has_offset = true;
+            let amount = self.number().unwrap_or(1);
+            let amount = isize::try_from(amount)
+                .map_err(|_| "Invalid range".to_string())?;
+            offset = if sign == '+' {
+                offset.saturating_add(amount)
+            } else {
+                offset.saturating_sub(amount)
+            };
+        }
+
+        match base {
+            Some(base) => Ok(Some(Address { base, offset })),
+            None if has_offset => Ok(Some(Address {
+                base: Base::Current,
+                offset,
+            })),
+            None => Ok(None),
+        }
+    }
+
+    fn name(&mut self) -> String {
+        let mut name = String::new();
+        while let Some(c) = self.peek().filter(char::is_ascii_alphabetic) {
+            self.pos = self.pos.saturating_add(1);
+            name.push(c);
+        }
+        name
+    }
+
+    fn delimited(&mut self, delimiter: char) -> (String, bool) {
+        // Reads until an unescaped `delimiter`, an escaped delimiter is
+        // unescaped while every other escape is kept as is. Also returns
+        // whether the closing delimiter was found
+        let mut result = String::new();
+        while let Some(c) = self.bump() {
+            if c == delimiter {
+                return (result, true);
+            }
+            if c == '\\' {
+                match self.bump() {
+                    Some(next) if next == delimiter => result.push(next),
+                    Some(next) => {
+                        result.push('\\');
+                        result.push(next);
+                    }
+                    None => result.push('\\'),
+                }
+            } else {
+                result.push(c);
+            }
+        }
+        (result, false)
+    }
+
+    fn delimiter(&mut self) -> Result<char, String> {
+        match self.bump() {
+            None => Ok('/'),
+            Some(c) if c.is_alphanumeric() || c == '\\' || c == '"' => {
+                Err("Invalid pattern delimiter".to_string())
+            }
+            Some(c) => Ok(c),
+        }
+    }
+
+    fn argument(&mut self) -> Option<String> {
+        // Everything up to the end of the line, if there is anything
+        let argument = self.rest().trim().to_string();
+        self.pos = self.chars.len();
+        if argument.is_empty() {
+            None
+        } else {
+            Some(argument)
+        }
+    }
+
+    fn words(&mut self) -> Vec<String> {
+        // The rest of the line split on whitespace, which a backslash
+        // keeps in a word
+        let mut words = Vec::new();
+        let mut word = String::new();
+        while let Some(c) = self.bump() {
+            match c {
+                '\\' if self.peek().is_some_and(char::is_whitespace) => {
+                    word.extend(self.bump());
+                }
+                c if c.is_whitespace() => {
+                    if !word.is_empty() {
+                        words.push(mem::take(&mut word));
+                    }
+                }
+                c => word.push(c),
+            }
+        }
+        if !word.is_empty() {
+            words.push(word);
+        }
+        words
+    }
+
+    fn map(
+        &mut self,
+        mode: Option<MapMode>,
+        kind: MapKind,
+    ) -> Result<Command, String> {
+        // `!` after :map, :noremap and :unmap is for insert and command
+        // line mode instead
+        let modes = match mode {
+            Some(mode) => vec![mode],
+            None if self.eat('!') => {
+                vec![MapMode::Insert, MapMode::CommandLine]
+            }
+            None => {
+                vec![MapMode::Normal, MapMode::Visual, MapMode::OperatorPending]
+            }
+        };
+        self.skip_whitespace();
+        let mut lhs = String::new();
+        while let Some(c) = self.peek().filter(|c| !c.is_whitespace()) {
+            lhs.push(c);
+            self.pos = self.pos.saturating_add(1);
+        }
+        let rhs = self.argument();
+        if kind == MapKind::Unmap {
+            if lhs.is_empty() {
+                return Err("Argument required".to_string());
+            }
+            return Ok(Command::Unmap { modes, lhs });
+        }
+        Ok(Command::Map {
+            modes,
+            lhs: if lhs.is_empty() { None } else { Some(lhs) },
+            rhs,
+            noremap: kind == MapKind::Noremap,
+        })
+    }
+
+    fn end(&mut self) -> Result<(), String> {
+        self.skip_whitespace();
+        if self.peek().is_some() {
+            return Err(format!("Trailing characters: {}", self.rest()));
+        }
+        Ok(())
+    }
+
+    fn global(
+        &mut self,
+        range: Range,
+        invert: bool,
+    ) -> Result<Command, String> {
+        let invert = self.eat('!') || invert;
+        let delimiter = self.delimiter()?;
+        let (pattern, _) = self.delimited(delimiter);
+        let command = self.rest().trim_start().to_string();
+        if command.is_empty() {
+            return Err("Missing command after pattern".to_string());
+        }
+        Ok(Command::Global {
+            range,
+            pattern: if pattern.is_empty() {
+                None
+            } else {
+                Some(pattern)
+            },
+            invert,
+            command,
+        })
+    }
+
+    fn grep(&mut self) -> Result<Command, String> {
+        // The pattern is either between slashes like in `:grep /a b/ src`
+        // or runs until the first whitespace
+        self.skip_whitespace();
+        let pattern = match self.peek() {
+            None => return Err("Missing pattern".to_string()),
+            Some('/') => {
+                self.pos = self.pos.saturating_add(1);
+                self.delimited('/').0
+            }
+            Some(_) => {
+                let mut pattern = String::new();
+                while let Some(c) = self.peek().filter(|c| !c.is_whitespace()) {
+                    self.pos = self.pos.saturating_add(1);
+                    pattern.push(c);
+                }
+                pattern
+            }
+        };
+        let paths = self.rest().split_whitespace().map(String::from).collect();
+        Ok(Command::Grep {
+            pattern: if pattern.is_empty() {
+                None
+            } else {
+                Some(pattern)
+            },
+            paths,
+        })
+    }
+
+    fn substitute(&mut self, range: Range) -> Result<Command, String> {
+        let delimiter = self.delimiter()?;
+        let (pattern, _) = self.delimited(delimiter);
+        let (replacement, closed) = self.delimited(delimiter);
+
+        let mut flags = SubstituteFlags::default();
+        if closed {
+            while let Some(c) = self.bump() {
+                match c {
+                    'g' => flags.global = true,
+                    'c' => flags.confirm = true,
+                    'n' => flags.count_only = true,
+                    'i' => flags.ignore_case = Some(true),
+                    'I' => flags.ignore_case = Some(false),
+                    c if c.is_whitespace() => (),
+                    c => return Err(format!("Invalid flag: {c}")),
+                }
+            }
+        }
+
+        Ok(Command::Substitute {
+            range,
+            pattern: if pattern.is_empty() {
+                None
+            } else {
+                Some(pattern)
+            },
+            replacement,
+            flags,
+        })
+    }
+}
+
+fn parse_offset(offset: &str) -> Option<usize> {
+    match offset
+        .strip_prefix("0x")
+        .or_else(|| offset.strip_prefix("0X"))
+    {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => offset.parse().ok(),
+    }
+}
+
+#[allow(clippy::too_many_lines)]
+pub fn parse(line: &str) -> Result<Command, String> {
+    let mut parser = Parser {
+        chars: line.chars().collect(),
+        pos: 0,
+    };
+    let range = parser.range()?;
+    parser.skip_whitespace();
+    let name = parser.name();
+    match name.as_str() {
+        "s" | "substitute" => {
+            parser.substitute(range.unwrap_or_else(Range::current))
+        }
+        "d" | "delete" => {
+            parser.end()?;
+            Ok(Command::Delete {
+                range: range.unwrap_or_else(Range::current),
+            })
+        }
+        "g" | "global" => {
+            parser.global(range.unwrap_or_else(Range::all), false)
+        }
+        "v" | "vglobal" => {
+            parser.global(range.unwrap_or_else(Range::all), true)
+        }
+        "gr" | "grep" => parser.grep(),
+        "cn" | "cnext" | "cp" | "cprevious" | "cN" | "cNext" => {
+            parser.end()?;
+            Ok(Command::QuickfixStep {
+                forward: name.starts_with("cn"),
+            })
+        }
+        "cope" | "copen" => {
+            parser.end()?;
+            Ok(Command::QuickfixOpen)
+        }
+        "ccl" | "cclose" => {
+            parser.end()?;
+            Ok(Command::QuickfixClose)
+        }
+        "e" | "edit" => match parser.argument() {
+            Some(file_name) => Ok(Command::Edit { file_name }),
+            None => Err("No file name".to_string()),
+        },
+        "ls" | "buffers" | "files" => {
+            parser.end()?;
+            Ok(Command::ListBuffers)
+        }
+        "b" | "buffer" => Ok(Command::Buffer {
+            name: parser.argument(),
+        }),
+        "bn" | "bnext" | "bp" | "bprevious" | "bN" | "bNext" => {
+            parser.end()?;
+            Ok(Command::BufferStep {
+                forward: name.starts_with("bn"),
+            })
+        }
+        "bd" | "bdelete" => {
+            let force = parser.eat('!');
+            Ok(Command::BufferDelete {
+                name: parser.argument(),
+                force,
+            })
+        }
+        "sp" | "split" | "vs" | "vsp" | "vsplit" => Ok(Command::Split {
+            vertical: name.starts_with('v'),
+            file_name: parser.argument(),
+        }),
+        "clo" | "close" => {
+            parser.eat('!');
+            parser.end()?;
+            Ok(Command::CloseWindow)
+        }
+        "on" | "only" => {
+            parser.eat('!');
+            parser.end()?;
+            Ok(Command::OnlyWindow)
+        }
+        "tabnew" | "tabe" | "tabedit" => Ok(Command::NewTab {
+            file_name: parser.argument(),
+        }),
+        "tabn" | "tabnext" | "tabp" | "tabprevious" | "tabN" | "tabNext" => {
+            parser.end()?;
+            Ok(Command::TabStep {
+                forward: name.starts_with("tabn"),
+            })
+        }
+        "tabc" | "tabclose" => {
+            parser.eat('!');
+            parser.end()?;
+            Ok(Command::CloseTab)
+        }
+        "Files" | "Buffers" | "History" => {
+            parser.end()?;
+            Ok(Command::Find {
+                source: match name.as_str() {
+                    "Files" => Source::Files,
+                    "Buffers" => Source::Buffers,
+                    _ => Source::Recent,
+                },
+            })
+        }
+        "Ex" | "Explore" => Ok(Command::Explore {
+            dir: parser.argument(),
+        }),
+        "se" | "set" | "setl" | "setlocal" => Ok(Command::Set {
+            args: parser.words(),
+            local: name.starts_with("setl"),
+        }),
+        "hex" => {
+            parser.end()?;
+            Ok(Command::Hex)
+        }
+        "go" | "goto" => {
+            let offset = match parser.argument() {
+                Some(offset) => parse_offset(&offset)
+                    .ok_or_else(|| format!("Invalid offset: {offset}"))?,
+                None => 0,
+            };
+            Ok(Command::Goto { offset })
+        }
+        "" => Err("Missing command".to_string()),
+        _ => match map_command(&name) {
+            Some((mode, kind)) => parser.map(mode, kind),
+            None => {
+                Err(format!("Not an editor command: {}{}", name, parser.rest()))
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, Address, Base, Command, Range, SubstituteFlags};
+    use crate::finder::Source;
+    use crate::keymap::MapMode;
+
+    fn range(command: &str) -> Range {
+        let Ok(Command::Substitute { range, .. }) = parse(command) else {
+            panic!("{} is not a substitution", command);
+        };
+        range
+    }
+
+    #[test]
+    fn test_ranges() {
+        let selection = Some((4, 6));
+        assert_eq!(range("s/a/b/").resolve(3, 10, None), Ok((3, 3)));
+        assert_eq!(range("%s/a/b/").resolve(3, 10, None), Ok((0, 9)));
+        assert_eq!(range("2,$s/a/b/").resolve(3, 10, None), Ok((1, 9)));
+        assert_eq!(range(".,+2s/a/b/").resolve(3, 10, None), Ok((3, 5)));
+        assert_eq!(range("'<,'>s/a/b/").resolve(0, 10, selection), Ok((4, 6)));
+        assert_eq!(range("5,2s/a/b/").resolve(0, 10, None), Ok((1, 4)));
+        assert!(range("20s/a/b/").resolve(0, 10, None).is_err());
+    }
+
+    #[test]
+    fn test_substitute() {
+        assert_eq!(
+            parse("s#a/b#c\\#d#gc"),
+            Ok(Command::Substitute {
+                range: Range::current(),
+                pattern: Some("a/b".to_string()),
+                replacement: "c#d".to_string(),
+                flags: SubstituteFlags {
+                    global: true,
+                    confirm: true,
+                    count_only: false,
+                    ignore_case: None,
+                },
+            })
+        );
+        assert!(matches!(
+            parse("s//x"),
+            Ok(Command::Substitute { pattern: None, .. })
+        ));
+        assert!(parse("s/a/b/z").is_err());
+        assert!(parse("frobnicate").is_err());
+    }
+
+    #[test]
+    fn test_global() {
+        assert_eq!(
+            parse("g!/^$/d"),
+            Ok(Command::Global {
+                range: Range::all(),
+                pattern: Some("^$".to_string()),
+                invert: true,
+                command: "d".to_string(),
+            })
+        );
+        assert!(matches!(
+            parse("2,3v/TODO/ s/a/b/"),
+            Ok(Command::Global { invert: true, ref command, .. })
+                if command == "s/a/b/"
+        ));
+        assert!(parse("g/TODO/").is_err());
+        assert_eq!(
+            parse(".,$d"),
+            Ok(Command::Delete {
+                range: Range {
+                    start: Address::current(),
+                    end: Address {
+                        base: Base::Last,
+                        offset: 0
+                    },
+                },
+            })
+        );
+        assert!(parse("d x").is_err());
+    }
+
+    #[test]
+    fn test_quickfix() {
+        assert_eq!(
+            parse("grep fn\\s\\+main src Cargo.toml"),
+            Ok(Command::Grep {
+                pattern: Some("fn\\s\\+main".to_string()),
+                paths: vec!["src".to_string(), "Cargo.toml".to_string()],
+            })
+        );
+        assert_eq!(
+            parse("grep /a b/"),
+            Ok(Command::Grep {
+                pattern: Some("a b".to_string()),
+                paths: Vec::new(),
+            })
+        );
+        for pattern in ["^fn", ".*foo", "(a|b)", "[ab]c"] {
+            assert_eq!(
+                parse(&format!("grep {pattern} src")),
+                Ok(Command::Grep {
+                    pattern: Some(pattern.to_string()),
+                    paths: vec!["src".to_string()],
+                })
+            );
+        }
+        assert_eq!(parse("cN"), Ok(Command::QuickfixStep { forward: false }));
+        assert_eq!(parse("copen"), Ok(Command::QuickfixOpen));
+        assert!(parse("grep").is_err());
+        assert!(parse("cn x").is_err());
+    }
+
+    #[test]
+    fn test_buffers() {
+        assert_eq!(
+            parse("b2"),
+            Ok(Command::Buffer {
+                name: Some("2".to_string())
+            })
+        );
+        assert_eq!(
+            parse("bd! main.rs "),
+            Ok(Command::BufferDelete {
+                name: Some("main.rs".to_string()),
+                force: true,
+            })
+        );
+        assert_eq!(parse("bp"), Ok(Command::BufferStep { forward: false }));
+        assert_eq!(parse("ls"), Ok(Command::ListBuffers));
+        assert!(parse("e").is_err());
+    }
+
+    #[test]
+    fn test_windows() {
+        assert_eq!(
+            parse("vsp src/main.rs"),
+            Ok(Command::Split {
+                vertical: true,
+                file_name: Some("src/main.rs".to_string()),
+            })
+        );
+        assert_eq!(
+            parse("sp"),
+            Ok(Command::Split {
+                vertical: false,
+                file_name: None,
+            })
+        );
+        assert_eq!(parse("close!"), Ok(Command::CloseWindow));
+        assert_eq!(parse("only"), Ok(Command::OnlyWindow));
+        assert_eq!(parse("tabnew"), Ok(Command::NewTab { file_name: None }));
+        assert_eq!(parse("tabN"), Ok(Command::TabStep { forward: false }));
+        assert_eq!(parse("tabclose"), Ok(Command::CloseTab));
+    }
+
+    #[test]
+    fn test_explore() {
+        assert_eq!(parse("Ex"), Ok(Command::Explore { dir: None }));
+        assert_eq!(
+            parse("Explore src/"),
+            Ok(Command::Explore {
+                dir: Some("src/".to_string())
+            })
+        );
+    }
+
+    #[test]
+    fn test_find() {
+        assert_eq!(
+            parse("History"),
+            Ok(Command::Find {
+                source: Source::Recent
+            })
+        );
+        assert!(parse("Files src").is_err());
+    }
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(parse("hex"), Ok(Command::Hex));
+        assert_eq!(parse("goto"), Ok(Command::Goto { offset: 0 }));
+        assert_eq!(parse("go 0x1f0"), Ok(Command::Goto { offset: 0x1f0 }));
+        assert_eq!(parse("goto 42"), Ok(Command::Goto { offset: 42 }));
+        assert!(parse("goto 0xzz").is_err());
+        assert!(parse("hex 1").is_err());
+    }
+
+    #[test]
+    fn test_set() {
+        assert_eq!(
+            parse("set ts=4  noet\\ x"),
+            Ok(Command::Set {
+                args: vec!["ts=4".to_string(), "noet x".to_string()],
+                local: false,
+            })
+        );
+        assert_eq!(
+            parse("setl"),
+            Ok(Command::Set {
+                args: Vec::new(),
+                local: true,
+            })
+        );
+    }
+
+    #[test]
+    fn test_map() {
+        assert_eq!(
+            parse("nnoremap <Leader>q  :bd<CR>"),
+            Ok(Command::Map {
+                modes: vec![MapMode::Normal],
+                lhs: Some("<Leader>q".to_string()),
+                rhs: Some(":bd<CR>".to_string()),
+                noremap: true,
+            })
+        );
+        assert_eq!(
+            parse("map! jk"),
+            Ok(Command::Map {
+                modes: vec![MapMode::Insert, MapMode::CommandLine],
+                lhs: Some("jk".to_string()),
+                rhs: None,
+                noremap: false,
+            })
+        );
+        assert_eq!(
+            parse("xu gq"),
+            Ok(Command::Unmap {
+                modes: vec![MapMode::Visual],
+                lhs: "gq".to_string(),
+            })
+        );
+        assert!(matches!(
+            parse("map"),
+            Ok(Command::Map { ref modes, lhs: None, .. }) if modes.len() == 3
+        ));
+        assert!(parse("iunmap").is_err());
+    }
+}
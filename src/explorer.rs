@@ -0,0 +1,381 @@
+// Directory listings that can be edited like any other text, in the style
+// of oil.nvim. Every line is the name of an entry (directories end with a
+// '/'), and saving the listing turns the edits into file operations:
+// a changed line renames its entry, a deleted line deletes it and a new
+// line creates a file, or a directory when it ends with a '/'.
+//
+// Rows of a listing remember the name they were loaded with (see
+// Row::origin()), which is how a renamed line is told apart from a deleted
+// line plus a new one.
+
+use crate::Row;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Listing {
+    pub path: PathBuf,
+    // Names of the entries when the listing was read
+    pub entries: Vec<String>,
+}
+
+impl Listing {
+    pub fn read(path: &Path) -> Result<Self, io::Error> {
+        // Directories first, each group sorted by name
+        let mut dirs = Vec::new();
+        let mut files = Vec::new();
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if entry.file_type()?.is_dir() {
+                dirs.push(format!("{name}/"));
+            } else {
+                files.push(name);
+            }
+        }
+        dirs.sort();
+        files.sort();
+        dirs.append(&mut files);
+        Ok(Self {
+            path: path.to_path_buf(),
+            entries: dirs,
+        })
+    }
+
+    #[must_use]
+    pub fn rows(&self) -> Vec<Row> {
+        self.entries
+            .iter()
+            .map(|name| {
+                let mut row = Row::from(name.as_str());
+                row.set_origin(Some(name.clone()));
+                row
+            })
+            .collect()
+    }
+
+    #[must_use]
+    pub fn join(&self, name: &str) -> PathBuf {
+        // Paths inside "." are left relative to it
+        if self.path == Path::new(".") {
+            PathBuf::from(name)
+        } else {
+            self.path.join(name)
+        }
+    }
+}
+
+fn is_entry_name(name: &str) -> bool {
+    // A name in a listing is that of an entry right inside its directory,
+    // so it can't point anywhere else (like "../x" or "/etc/x" would)
+    let bare = name.strip_suffix('/').unwrap_or(name);
+    let mut components = Path::new(bare).components();
+    !bare.contains(['/', std::path::MAIN_SEPARATOR])
+        && matches!(components.next(), Some(Component::Normal(_)))
+        && components.next().is_none()
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Changes {
+    pub deleted: Vec<String>,
+    pub renamed: Vec<(String, String)>,
+    pub created: Vec<String>,
+}
+
+impl Changes {
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.deleted.is_empty()
+            && self.renamed.is_empty()
+            && self.created.is_empty()
+    }
+
+    #[must_use]
+    pub fn describe(&self) -> Vec<String> {
+        let deleted = self.deleted.iter().map(|name| format!("DELETE {name}"));
+        let renamed = self
+            .renamed
+            .iter()
+            .map(|(from, to)| format!("RENAME {from} -> {to}"));
+        let created = self.created.iter().map(|name| format!("CREATE {name}"));
+        deleted.chain(renamed).chain(created).collect()
+    }
+
+    pub fn diff<'a>(
+        listing: &Listing,
+        rows: impl Iterator<Item = &'a Row>,
+    ) -> Result<Self, String> {
+        // Compares the edited rows with the entries the listing was read
+        // with. Blank lines are ignored
+        let mut changes = Self::default();
+        let mut seen: HashSet<&str> = HashSet::new();
+        let mut names: HashSet<String> = HashSet::new();
+        let mut untagged = Vec::new();
+        for row in rows {
+            let name = row.as_str().trim();
+            if name.is_empty() {
+                continue;
+            }
+            if !is_entry_name(name) {
+                return Err(format!("Invalid file name: {name}"));
+            }
+            if !names.insert(name.trim_end_matches('/').to_string()) {
+                return Err(format!("Duplicate file name: {name}"));
+            }
+            match row.origin() {
+                Some(origin) if seen.insert(origin) => {
+                    if origin != name {
+                        changes
+                            .renamed
+                            .push((origin.to_string(), name.to_string()));
+                    }
+                }
+                _ => untagged.push(name.to_string()),
+            }
+        }
+        // A new line with the name of an entry whose own line is gone is
+        // that same entry, as when splitting a line at its start
+        for name in untagged {
+            let original = listing.entries.iter().find(|entry| **entry == name);
+            match original {
+                Some(entry) if !seen.contains(entry.as_str()) => {
+                    seen.insert(entry);
+                }
+                _ => changes.created.push(name),
+            }
+        }
+        changes.deleted = listing
+            .entries
+            .iter()
+            .filter(|entry| !seen.contains(entry.as_str()))
+            .cloned()
+            .collect();
+        Ok(changes)
+    }
+
+    pub fn apply(&self, listing: &Listing) -> Result<(), io::Error> {
+        // Deletions go first so their names can be reused, and renames go
+        // through temporary names so entries can swap names
+        let path = |name: &str| listing.join(name.trim_end_matches('/'));
+        let sources: HashSet<PathBuf> =
+            self.renamed.iter().map(|(from, _)| path(from)).collect();
+        for (_, to) in &self.renamed {
+            let target = path(to);
+            let deleted = self.deleted.iter().any(|name| path(name) == target);
+            if target.exists() && !deleted && !sources.contains(&target) {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{to} already exists"),
+                ));
+            }
+        }
+
+        for name in &self.deleted {
+            let target = path(name);
+            if name.ends_with('/') {
+                fs::remove_dir_all(target)?;
+            } else {
+                fs::remove_file(target)?;
+            }
+        }
+        let mut moved = Vec::new();
+        if let Err(error) = self.rename(&path, &mut moved) {
+            return Err(undo_renames(&moved, &error));
+        }
+        for name in &self.created {
+            let target = path(name);
+            if name.ends_with('/') {
+                fs::create_dir_all(target)?;
+            } else {
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(target)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn rename(
+        &self,
+        path: &impl Fn(&str) -> PathBuf,
+        moved: &mut Vec<Moved>,
+    ) -> Result<(), io::Error> {
+        for (index, (from, _)) in self.renamed.iter().enumerate() {
+            let name = format!(".mtx-rename-{}-{index}", std::process::id());
+            fs::rename(path(from), path(&name))?;
+            moved.push(Moved {
+                from: path(from),
+                temporary: path(&name),
+                to: None,
+            });
+        }
+        for ((_, to), moved) in self.renamed.iter().zip(moved.iter_mut()) {
+            let target = path(to);
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::rename(&moved.temporary, &target)?;
+            moved.to = Some(target);
+        }
+        Ok(())
+    }
+}
+
+// Where a renamed entry was, the temporary name it went through and, once
+// it got there, its new path
+struct Moved {
+    from: PathBuf,
+    temporary: PathBuf,
+    to: Option<PathBuf>,
+}
+
+fn undo_renames(moved: &[Moved], error: &io::Error) -> io::Error {
+    // Puts the entries a failed rename had moved back where they were,
+    // through their temporary names again so that every original name is
+    // free by then. Those that can't be are named in the error
+    let mut left = Vec::new();
+    let mut restore = Vec::new();
+    for entry in moved {
+        match &entry.to {
+            Some(to) if fs::rename(to, &entry.temporary).is_err() => {
+                left.push(format!(
+                    "{} is left as {}",
+                    entry.from.display(),
+                    to.display()
+                ));
+            }
+            _ => restore.push(entry),
+        }
+    }
+    for entry in restore {
+        if fs::rename(&entry.temporary, &entry.from).is_err() {
+            left.push(format!(
+                "{} is left as {}",
+                entry.from.display(),
+                entry.temporary.display()
+            ));
+        }
+    }
+    let message = if left.is_empty() {
+        format!("{error}, renames undone")
+    } else {
+        format!("{error}, {}", left.join(", "))
+    };
+    io::Error::new(error.kind(), message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Changes, Listing};
+    use crate::Row;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn listing(entries: &[&str]) -> Listing {
+        Listing {
+            path: PathBuf::from("."),
+            entries: entries.iter().map(ToString::to_string).collect(),
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        let listing = listing(&["src/", "a.txt", "b.txt", "c.txt"]);
+        let mut rows = listing.rows();
+        // Rename src/, delete b.txt, split c.txt at its start and add two
+        // new entries
+        rows[0] = {
+            let mut row = Row::from("lib/");
+            row.set_origin(Some("src/".to_string()));
+            row
+        };
+        rows.remove(2);
+        rows[2] = {
+            let mut row = Row::from("");
+            row.set_origin(Some("c.txt".to_string()));
+            row
+        };
+        rows.push(Row::from("c.txt"));
+        rows.push(Row::from("new/"));
+        rows.push(Row::from("d.txt"));
+
+        let changes = Changes::diff(&listing, rows.iter()).unwrap();
+        assert_eq!(
+            changes,
+            Changes {
+                deleted: vec!["b.txt".to_string()],
+                renamed: vec![("src/".to_string(), "lib/".to_string())],
+                created: vec!["new/".to_string(), "d.txt".to_string()],
+            }
+        );
+
+        let rows = [Row::from("x"), Row::from("x")];
+        assert!(Changes::diff(&listing, rows.iter()).is_err());
+        for name in ["../x", "..", ".", "/", "/tmp/x", "sub/x", "x//"] {
+            let rows = [Row::from(name)];
+            assert!(Changes::diff(&listing, rows.iter()).is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_apply() {
+        let dir = std::env::temp_dir()
+            .join(format!("mtx-explorer-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("a"), "a").unwrap();
+        fs::write(dir.join("b"), "b").unwrap();
+        fs::write(dir.join("gone"), "").unwrap();
+
+        let listing = Listing::read(&dir).unwrap();
+        assert_eq!(listing.entries, ["sub/", "a", "b", "gone"]);
+        // Swapping a and b needs the temporary names
+        let changes = Changes {
+            deleted: vec!["gone".to_string()],
+            renamed: vec![
+                ("a".to_string(), "b".to_string()),
+                ("b".to_string(), "a".to_string()),
+                ("sub/".to_string(), "moved/".to_string()),
+            ],
+            created: vec!["moved/new".to_string()],
+        };
+        changes.apply(&listing).unwrap();
+        assert_eq!(fs::read_to_string(dir.join("a")).unwrap(), "b");
+        assert_eq!(fs::read_to_string(dir.join("b")).unwrap(), "a");
+        assert!(!dir.join("gone").exists());
+        assert!(dir.join("moved/new").is_file());
+
+        let changes = Changes {
+            renamed: vec![("a".to_string(), "b".to_string())],
+            ..Changes::default()
+        };
+        assert!(changes.apply(&Listing::read(&dir).unwrap()).is_err());
+
+        // The last rename fails since "plain" isn't a directory, which
+        // undoes the swap before it
+        fs::write(dir.join("plain"), "").unwrap();
+        let changes = Changes {
+            renamed: vec![
+                ("a".to_string(), "b".to_string()),
+                ("b".to_string(), "a".to_string()),
+                ("moved/".to_string(), "plain/moved/".to_string()),
+            ],
+            ..Changes::default()
+        };
+        let error = changes.apply(&Listing::read(&dir).unwrap()).unwrap_err();
+        assert!(error.to_string().ends_with("renames undone"), "{}", error);
+        assert_eq!(fs::read_to_string(dir.join("a")).unwrap(), "b");
+        assert_eq!(fs::read_to_string(dir.join("b")).unwrap(), "a");
+        assert_eq!(
+            Listing::read(&dir).unwrap().entries,
+            ["moved/", "a", "b", "plain"]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,178 @@
+// How the lines of a file are laid out beyond the text itself: what ends
+// them ('fileformat'), whether the last one is ended too ('endofline'),
+// whether the file starts with a byte order mark ('bomb') and the encoding
+// it is in ('fileencoding', see encoding.rs). They are found out when a
+// file is read and kept as the buffer's options, so saving puts them back
+// as they were unless they are changed with `:set`.
+//
+// As in vim, a file is only taken as dos when every line ends in "\r\n",
+// otherwise the '\r's stay in the text so nothing is lost.
+
+use crate::encoding::{self, Encoding};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileFormat {
+    Unix,
+    Dos,
+    Mac,
+}
+
+impl FileFormat {
+    const ALL: [Self; 3] = [FileFormat::Unix, FileFormat::Dos, FileFormat::Mac];
+
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            FileFormat::Unix => "unix",
+            FileFormat::Dos => "dos",
+            FileFormat::Mac => "mac",
+        }
+    }
+
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|format| format.name() == name)
+    }
+
+    #[must_use]
+    pub fn ending(self) -> &'static str {
+        match self {
+            FileFormat::Unix => "\n",
+            FileFormat::Dos => "\r\n",
+            FileFormat::Mac => "\r",
+        }
+    }
+
+    fn detect(text: &str) -> Self {
+        let lf = text.matches('\n').count();
+        if lf > 0 {
+            if text.matches("\r\n").count() == lf {
+                FileFormat::Dos
+            } else {
+                FileFormat::Unix
+            }
+        } else if text.contains('\r') {
+            FileFormat::Mac
+        } else {
+            FileFormat::Unix
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layout {
+    pub format: FileFormat,
+    pub end_of_line: bool,
+    pub bom: bool,
+    pub encoding: Encoding,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self {
+            format: FileFormat::Unix,
+            end_of_line: true,
+            bom: false,
+            encoding: Encoding::Utf8,
+        }
+    }
+}
+
+#[must_use]
+pub fn read(
+    contents: &[u8],
+    encoding: Option<Encoding>,
+) -> (Vec<String>, Layout) {
+    // The lines of a file and how they were laid out, in `encoding` if it
+    // is given instead of the one found
+    let (text, encoding, bom) = encoding::decode(contents, encoding);
+    let format = FileFormat::detect(&text);
+    let ending = format.ending();
+    let layout = Layout {
+        format,
+        end_of_line: text.is_empty() || text.ends_with(ending),
+        bom,
+        encoding,
+    };
+    if text.is_empty() {
+        return (Vec::new(), layout);
+    }
+    let text = text.strip_suffix(ending).unwrap_or(&text);
+    (text.split(ending).map(str::to_string).collect(), layout)
+}
+
+pub fn write<'a>(
+    lines: impl Iterator<Item = &'a str>,
+    layout: Layout,
+) -> Result<Vec<u8>, String> {
+    // The opposite of read()
+    let mut contents = String::new();
+    let ending = layout.format.ending();
+    let mut lines = lines.peekable();
+    while let Some(line) = lines.next() {
+        contents.push_str(line);
+        if layout.end_of_line || lines.peek().is_some() {
+            contents.push_str(ending);
+        }
+    }
+    encoding::encode(&contents, layout.encoding, layout.bom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read, write, FileFormat, Layout};
+    use crate::encoding::Encoding;
+
+    #[test]
+    fn test_round_trip() {
+        let files: [(&[u8], Layout); 6] = [
+            (b"", Layout::default()),
+            (b"a\nb\n", Layout::default()),
+            (
+                b"\xef\xbb\xbfa\r\n\r\nb",
+                Layout {
+                    format: FileFormat::Dos,
+                    end_of_line: false,
+                    bom: true,
+                    ..Layout::default()
+                },
+            ),
+            (
+                b"a\rb\r",
+                Layout {
+                    format: FileFormat::Mac,
+                    ..Layout::default()
+                },
+            ),
+            // Mixed endings keep their '\r'
+            (b"a\r\nb\n", Layout::default()),
+            (
+                b"\xff\xfea\x00\r\x00\n\x00",
+                Layout {
+                    format: FileFormat::Dos,
+                    bom: true,
+                    encoding: Encoding::Utf16Le,
+                    ..Layout::default()
+                },
+            ),
+        ];
+        for (contents, layout) in files {
+            let (lines, read_layout) = read(contents, None);
+            assert_eq!(read_layout, layout, "{contents:?}");
+            let lines = lines.iter().map(String::as_str);
+            assert_eq!(write(lines, layout).unwrap(), contents);
+        }
+        assert_eq!(read(b"a\r\nb\n", None).0, vec!["a\r", "b"]);
+        assert_eq!(read(b"\n", None).0, vec![""]);
+        let dos = Layout {
+            format: FileFormat::Dos,
+            ..Layout::default()
+        };
+        let (lines, _) = read(b"a\nb\n", None);
+        let lines = lines.iter().map(String::as_str);
+        assert_eq!(write(lines, dos).unwrap(), b"a\r\nb\r\n");
+    }
+}
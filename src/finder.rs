@@ -0,0 +1,347 @@
+// The fuzzy finder, a picker drawn over the windows that narrows a list of
+// file names down as a query is typed. A name matches when the characters
+// of the query appear in it in order, and matches are ranked by where those
+// characters fall: right after a '/' or a separator and next to each other
+// is better than scattered over the middle of words.
+//
+// Files that were opened are also remembered across sessions, most recent
+// first, in $XDG_STATE_HOME/mtx/recent (~/.local/state/mtx/recent).
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const SCORE_MATCH: i64 = 16;
+const SCORE_GAP: i64 = 1;
+const BONUS_SLASH: i64 = 10;
+const BONUS_BOUNDARY: i64 = 8;
+const BONUS_CAMEL: i64 = 7;
+const BONUS_CONSECUTIVE: i64 = 8;
+// Most recent files that are remembered
+const RECENT_FILES: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Source {
+    Files,
+    Buffers,
+    Recent,
+}
+
+impl Source {
+    #[must_use]
+    pub fn title(self) -> &'static str {
+        match self {
+            Self::Files => "Files",
+            Self::Buffers => "Buffers",
+            Self::Recent => "Recent files",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Match {
+    pub item: usize,
+    pub score: i64,
+    // Char indexes of the matched characters in the item
+    pub positions: Vec<usize>,
+}
+
+fn bonus(previous: Option<char>, c: char) -> i64 {
+    match previous {
+        None | Some('/') => BONUS_SLASH,
+        Some('_' | '-' | '.' | ' ') => BONUS_BOUNDARY,
+        Some(previous) if previous.is_lowercase() && c.is_uppercase() => {
+            BONUS_CAMEL
+        }
+        Some(_) => 0,
+    }
+}
+
+fn better(
+    a: Option<(i64, usize)>,
+    b: Option<(i64, usize)>,
+) -> Option<(i64, usize)> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(if b.0 > a.0 { b } else { a }),
+        (a, b) => a.or(b),
+    }
+}
+
+#[must_use]
+#[allow(clippy::indexing_slicing)]
+pub fn score(query: &str, candidate: &str) -> Option<Match> {
+    // Finds the best scoring way to match the query as a subsequence of
+    // the candidate. The query only matches case sensitively when it has
+    // uppercase characters in it
+    let smart_case = query.chars().any(char::is_uppercase);
+    let fold = |c: char| {
+        if smart_case {
+            c
+        } else {
+            c.to_lowercase().next().unwrap_or(c)
+        }
+    };
+    let query: Vec<char> = query.chars().map(fold).collect();
+    let original: Vec<char> = candidate.chars().collect();
+    let candidate: Vec<char> = original.iter().copied().map(fold).collect();
+    let Some(last) = query.len().checked_sub(1) else {
+        return Some(Match {
+            item: 0,
+            score: 0,
+            positions: Vec::new(),
+        });
+    };
+
+    // best[i][j] is the best score for the query up to its i-th character
+    // with that one matched at j, and from[i][j] is where the character
+    // before it was matched
+    let width = candidate.len();
+    let mut best = vec![vec![None; width]; query.len()];
+    let mut from = vec![vec![0; width]; query.len()];
+    for (i, q) in query.iter().enumerate() {
+        // Best score of the previous character matched at least two
+        // characters before j, minus the gap
+        let mut gapped = None;
+        for j in 0..width {
+            let previous = j.checked_sub(1);
+            if let (Some(above), Some(k)) = (i.checked_sub(1), j.checked_sub(2))
+            {
+                let decay =
+                    |(s, k): (i64, usize)| (s.saturating_sub(SCORE_GAP), k);
+                gapped = better(
+                    best[above][k].map(|s| decay((s, k))),
+                    gapped.map(decay),
+                );
+            }
+            if *q != candidate[j] {
+                continue;
+            }
+            let bonus = bonus(previous.map(|p| original[p]), original[j]);
+            let Some(above) = i.checked_sub(1) else {
+                best[i][j] = Some(SCORE_MATCH.saturating_add(bonus));
+                continue;
+            };
+            let consecutive = previous.and_then(|p| {
+                best[above][p].map(|s: i64| {
+                    (s.saturating_add(BONUS_CONSECUTIVE.max(bonus)), p)
+                })
+            });
+            let gap = gapped.map(|(s, k)| (s.saturating_add(bonus), k));
+            if let Some((s, k)) = better(consecutive, gap) {
+                best[i][j] = Some(s.saturating_add(SCORE_MATCH));
+                from[i][j] = k;
+            }
+        }
+    }
+
+    let (mut j, score) = best[last]
+        .iter()
+        .enumerate()
+        .filter_map(|(j, s)| s.map(|s| (j, s)))
+        .max_by_key(|(j, s)| (*s, std::cmp::Reverse(*j)))?;
+    let mut positions = vec![j];
+    for i in (1..=last).rev() {
+        j = from[i][j];
+        positions.push(j);
+    }
+    positions.reverse();
+    Some(Match {
+        item: 0,
+        score,
+        positions,
+    })
+}
+
+pub struct Finder {
+    source: Source,
+    items: Vec<String>,
+    query: String,
+    matches: Vec<Match>,
+    selected: usize,
+}
+
+impl Finder {
+    #[must_use]
+    pub fn new(source: Source, items: Vec<String>) -> Self {
+        let mut finder = Self {
+            source,
+            items,
+            query: String::new(),
+            matches: Vec::new(),
+            selected: 0,
+        };
+        finder.filter();
+        finder
+    }
+
+    #[must_use]
+    pub fn source(&self) -> Source {
+        self.source
+    }
+
+    #[must_use]
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    #[must_use]
+    pub fn matches(&self) -> &[Match] {
+        &self.matches
+    }
+
+    #[must_use]
+    pub fn item(&self, index: usize) -> Option<&str> {
+        self.items.get(index).map(String::as_str)
+    }
+
+    #[must_use]
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    #[must_use]
+    pub fn selection(&self) -> Option<&str> {
+        self.matches
+            .get(self.selected)
+            .and_then(|found| self.item(found.item))
+    }
+
+    pub fn push(&mut self, c: char) {
+        self.query.push(c);
+        self.filter();
+    }
+
+    pub fn pop(&mut self) {
+        self.query.pop();
+        self.filter();
+    }
+
+    pub fn select(&mut self, forward: bool) {
+        // Moves the selection, stopping at either end of the list
+        self.selected = if forward {
+            self.selected
+                .saturating_add(1)
+                .min(self.matches.len().saturating_sub(1))
+        } else {
+            self.selected.saturating_sub(1)
+        };
+    }
+
+    fn filter(&mut self) {
+        // Best matches first, then shorter names and then the order of the
+        // items
+        self.matches = self
+            .items
+            .iter()
+            .enumerate()
+            .filter_map(|(item, name)| {
+                score(&self.query, name).map(|found| Match { item, ..found })
+            })
+            .collect();
+        // Without a query everything is left in the order of the items
+        if !self.query.is_empty() {
+            let items = &self.items;
+            self.matches.sort_by_key(|found| {
+                let len = items.get(found.item).map_or(0, String::len);
+                (std::cmp::Reverse(found.score), len, found.item)
+            });
+        }
+        self.selected = 0;
+    }
+}
+
+fn recent_path() -> Option<PathBuf> {
+    // Tests don't get to touch the real list
+    if cfg!(test) {
+        return None;
+    }
+    let state =
+        env::var_os("XDG_STATE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| {
+                env::var_os("HOME")
+                    .map(|home| Path::new(&home).join(".local/state"))
+            })?;
+    Some(state.join("mtx").join("recent"))
+}
+
+#[must_use]
+pub fn recent_files() -> Vec<String> {
+    // Most recent first
+    recent_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .map(|text| text.lines().map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+fn add_recent(mut files: Vec<String>, file: String) -> Vec<String> {
+    files.retain(|other| *other != file);
+    files.insert(0, file);
+    files.truncate(RECENT_FILES);
+    files
+}
+
+pub fn remember(path: &Path) {
+    // Failing to remember a file is not worth bothering anyone about
+    let (Some(recent), Ok(path)) = (recent_path(), path.canonicalize()) else {
+        return;
+    };
+    let files = add_recent(recent_files(), path.to_string_lossy().to_string());
+    if let Some(dir) = recent.parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+    let _ = fs::write(recent, files.join("\n") + "\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{add_recent, score, Finder, Source};
+
+    #[test]
+    fn test_score() {
+        let found = score("edrs", "src/editor.rs").unwrap();
+        assert_eq!(found.positions, [4, 5, 11, 12]);
+        assert!(score("xyz", "src/editor.rs").is_none());
+        assert!(score("Ed", "src/editor.rs").is_none());
+        assert!(score("ed", "src/Editor.rs").is_some());
+
+        // Matches at the start of a name beat scattered ones
+        let start = score("main", "src/main.rs").unwrap();
+        let scattered = score("main", "src/make_it_now.rs").unwrap();
+        assert!(start.score > scattered.score);
+        let consecutive = score("doc", "src/document.rs").unwrap();
+        let apart = score("doc", "src/do_cache.rs").unwrap();
+        assert!(consecutive.score > apart.score);
+    }
+
+    #[test]
+    fn test_finder() {
+        let items = ["src/make_it_now.rs", "README.md", "src/main.rs"];
+        let mut finder = Finder::new(
+            Source::Files,
+            items.iter().map(|item| (*item).to_string()).collect(),
+        );
+        assert_eq!(finder.selection(), Some("src/make_it_now.rs"));
+        for c in "main".chars() {
+            finder.push(c);
+        }
+        assert_eq!(finder.matches().len(), 2);
+        assert_eq!(finder.selection(), Some("src/main.rs"));
+        finder.select(true);
+        finder.select(true);
+        assert_eq!(finder.selection(), Some("src/make_it_now.rs"));
+        finder.push('q');
+        assert_eq!(finder.selection(), None);
+        finder.pop();
+        assert_eq!(finder.selected(), 0);
+
+        let files =
+            add_recent(vec!["a".to_string(), "b".to_string()], "b".to_string());
+        assert_eq!(files, ["b", "a"]);
+    }
+}
@@ -0,0 +1,162 @@
+// Golden tests: every tests/N.keys fixture is typed into the editor, with
+// tests/N.in open (an empty buffer when there is none), and the buffer it
+// leaves behind has to match tests/N.out. A fixture looks like
+//
+//     # Comments start with '#'
+//     keys: :s/a/b/g<CR>
+//     cursor: 1,1
+//
+// where `keys` is in the notation of keys.rs (more than one `keys` line
+// are typed one after the other), and the optional `cursor` is the line
+// and column (both from 1) the cursor has to end up at. The screen can be
+// checked too by adding a tests/N.screen snapshot, taken on a 40x10
+// terminal unless the fixture has a `size: 80x24` line.
+//
+// Running the tests with MTX_BLESS=1 writes the results to N.out and to
+// the N.screen files that exist instead of comparing them, an empty
+// N.screen is enough to get its snapshot taken.
+
+use crate::backend::MemoryBackend;
+use crate::editor::Editor;
+use crate::keys;
+use std::convert::TryFrom;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const FIXTURES: &str = "tests";
+
+struct Fixture {
+    keys: String,
+    cursor: Option<(usize, usize)>,
+    size: (u16, u16),
+}
+
+fn pair(value: &str, separator: char) -> Result<(usize, usize), String> {
+    let (a, b) = value
+        .split_once(separator)
+        .ok_or_else(|| format!("Expected two numbers in {value}"))?;
+    let number = |text: &str| {
+        text.trim()
+            .parse()
+            .map_err(|_| format!("Not a number: {text}"))
+    };
+    Ok((number(a)?, number(b)?))
+}
+
+fn fixture(text: &str) -> Result<Fixture, String> {
+    let mut fixture = Fixture {
+        keys: String::new(),
+        cursor: None,
+        size: (40, 10),
+    };
+    for line in text.lines() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| format!("Expected `name: value`: {line}"))?;
+        let value = value.strip_prefix(' ').unwrap_or(value);
+        match name {
+            "keys" => fixture.keys.push_str(value),
+            "cursor" => fixture.cursor = Some(pair(value, ',')?),
+            "size" => {
+                let (width, height) = pair(value, 'x')?;
+                let size = |n: usize| {
+                    u16::try_from(n).map_err(|_| format!("Too big: {n}"))
+                };
+                fixture.size = (size(width)?, size(height)?);
+            }
+            _ => return Err(format!("Unknown field {name}")),
+        }
+    }
+    Ok(fixture)
+}
+
+fn screen(backend: &MemoryBackend, height: u16) -> String {
+    (0..usize::from(height))
+        .map(|y| backend.line(y).trim_end().to_string() + "\n")
+        .collect()
+}
+
+fn check(keys_file: &Path, bless: bool) -> Result<(), String> {
+    let read = |path: &Path| {
+        fs::read_to_string(path)
+            .map_err(|error| format!("{}: {error}", path.display()))
+    };
+    let fixture = fixture(&read(keys_file)?)?;
+    let input = keys_file.with_extension("in");
+    let output = keys_file.with_extension("out");
+    let snapshot = keys_file.with_extension("screen");
+
+    let backend = MemoryBackend::new(fixture.size.0, fixture.size.1);
+    backend.push_keys(keys::parse(&fixture.keys)?);
+    let file_names: Vec<String> = if input.exists() {
+        vec![input.to_string_lossy().to_string()]
+    } else {
+        Vec::new()
+    };
+    let editor = Editor::headless(&backend, &file_names)
+        .map_err(|error| error.to_string())?;
+    // Files end with a newline, which the document doesn't have as a line
+    let mut text = editor.document().text();
+    if !text.is_empty() {
+        text.push('\n');
+    }
+    let shown = screen(&backend, fixture.size.1);
+
+    if bless {
+        let write = |path: &Path, contents: &str| {
+            fs::write(path, contents)
+                .map_err(|error| format!("{}: {error}", path.display()))
+        };
+        write(&output, &text)?;
+        if snapshot.exists() {
+            write(&snapshot, &shown)?;
+        }
+        return Ok(());
+    }
+    let expected = read(&output)?;
+    if text != expected {
+        return Err(format!("expected buffer\n{expected}got\n{text}"));
+    }
+    if let Some((line, column)) = fixture.cursor {
+        let cursor = editor.cursor_position();
+        let got = (cursor.y.saturating_add(1), cursor.x.saturating_add(1));
+        if got != (line, column) {
+            return Err(format!(
+                "expected the cursor at {line},{column}, got {},{}",
+                got.0, got.1
+            ));
+        }
+    }
+    if snapshot.exists() {
+        let expected = read(&snapshot)?;
+        if shown != expected {
+            return Err(format!("expected screen\n{expected}got\n{shown}"));
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_golden() {
+    let bless = env::var_os("MTX_BLESS").is_some();
+    let mut fixtures: Vec<PathBuf> = fs::read_dir(FIXTURES)
+        .unwrap()
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "keys"))
+        .collect();
+    fixtures.sort();
+    assert!(!fixtures.is_empty());
+    let failures: Vec<String> = fixtures
+        .iter()
+        .filter_map(|path| {
+            check(path, bless)
+                .err()
+                .map(|error| format!("{}: {error}", path.display()))
+        })
+        .collect();
+    assert!(failures.is_empty(), "\n{}", failures.join("\n\n"));
+}
@@ -0,0 +1,316 @@
+// Hex mode (`:hex`), which shows a buffer as the bytes of its file. Every
+// line has the offset of its first byte, sixteen bytes in hex and the same
+// bytes as ASCII, with '.' for the ones that aren't printable:
+//
+//     00000000: 7f 45 4c 46 02 01 01 00 00 00 00 00 00 00 00 00  .ELF............
+//
+// The bytes are kept here and the rows of the document are made from
+// them, so editing works on bytes and saving writes them back as they are.
+// Bytes can only be overwritten, a nibble at a time in the hex columns or
+// a character at a time in the ASCII ones.
+
+use crate::Position;
+use std::convert::TryFrom;
+use std::fmt::Write;
+
+pub const BYTES_PER_LINE: usize = 16;
+// Where the hex columns start, after "00000000: "
+const HEX_START: usize = 10;
+// Where the ASCII columns start, after every byte's "xx " and a space
+const ASCII_START: usize = HEX_START + BYTES_PER_LINE * 3 + 1;
+
+// The part of a byte the cursor is on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Column {
+    High,
+    Low,
+    Ascii,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Hex {
+    bytes: Vec<u8>,
+}
+
+impl Hex {
+    #[must_use]
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Self { bytes }
+    }
+
+    #[must_use]
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    #[must_use]
+    pub fn lines(&self) -> Vec<String> {
+        // An empty file still gets a line, with only the offset
+        let count = self.bytes.len().div_ceil(BYTES_PER_LINE).max(1);
+        (0..count).map(|y| self.line(y)).collect()
+    }
+
+    #[must_use]
+    pub fn line(&self, y: usize) -> String {
+        let start = y.saturating_mul(BYTES_PER_LINE);
+        let end = start.saturating_add(BYTES_PER_LINE).min(self.bytes.len());
+        let bytes = self.bytes.get(start..end).unwrap_or_default();
+        let mut line = format!("{start:08x}: ");
+        if bytes.is_empty() {
+            return line;
+        }
+        for byte in bytes {
+            let _ = write!(line, "{byte:02x} ");
+        }
+        line.push_str(
+            &"   ".repeat(BYTES_PER_LINE.saturating_sub(bytes.len())),
+        );
+        line.push(' ');
+        line.extend(bytes.iter().map(|byte| {
+            if byte.is_ascii_graphic() || *byte == b' ' {
+                char::from(*byte)
+            } else {
+                '.'
+            }
+        }));
+        line
+    }
+
+    #[must_use]
+    pub fn position(offset: usize, column: Column) -> Position {
+        let index = offset % BYTES_PER_LINE;
+        let x = match column {
+            Column::High => HEX_START.saturating_add(index.saturating_mul(3)),
+            Column::Low => HEX_START
+                .saturating_add(index.saturating_mul(3))
+                .saturating_add(1),
+            Column::Ascii => ASCII_START.saturating_add(index),
+        };
+        Position {
+            x,
+            y: offset / BYTES_PER_LINE,
+        }
+    }
+
+    #[must_use]
+    pub fn cell(&self, at: &Position) -> Option<(usize, Column)> {
+        // The byte `at` is on and which part of it
+        let (index, column) = if at.x >= ASCII_START {
+            (at.x.saturating_sub(ASCII_START), Column::Ascii)
+        } else {
+            let x = at.x.checked_sub(HEX_START)?;
+            match x % 3 {
+                0 => (x / 3, Column::High),
+                1 => (x / 3, Column::Low),
+                _ => return None,
+            }
+        };
+        let offset = at.y.saturating_mul(BYTES_PER_LINE).saturating_add(index);
+        if index < BYTES_PER_LINE && offset < self.bytes.len() {
+            Some((offset, column))
+        } else {
+            None
+        }
+    }
+
+    #[must_use]
+    pub fn snap(&self, at: &Position) -> Position {
+        // The closest place to `at` that is on a byte, for after the cursor
+        // was moved as in text
+        if self.bytes.is_empty() {
+            return Position { x: HEX_START, y: 0 };
+        }
+        let last = self.bytes.len().saturating_sub(1);
+        let y = at.y.min(last / BYTES_PER_LINE);
+        let (index, column) = if at.x >= ASCII_START {
+            (at.x.saturating_sub(ASCII_START), Column::Ascii)
+        } else {
+            let x = at.x.saturating_sub(HEX_START);
+            let column = if x.is_multiple_of(3) {
+                Column::High
+            } else {
+                Column::Low
+            };
+            (x / 3, column)
+        };
+        let start = y.saturating_mul(BYTES_PER_LINE);
+        let offset = start
+            .saturating_add(index.min(BYTES_PER_LINE.saturating_sub(1)))
+            .min(last);
+        Self::position(offset, column)
+    }
+
+    #[must_use]
+    pub fn step(&self, at: &Position, forward: bool) -> Position {
+        // The next (or previous) nibble in the hex columns, or character in
+        // the ASCII ones
+        let Some((offset, column)) = self.cell(at) else {
+            return self.snap(at);
+        };
+        let last = self.bytes.len().saturating_sub(1);
+        let (offset, column) = match (column, forward) {
+            (Column::High, true) => (offset, Column::Low),
+            (Column::Low, false) => (offset, Column::High),
+            (Column::Low, true) if offset < last => {
+                (offset.saturating_add(1), Column::High)
+            }
+            (Column::High, false) if offset > 0 => {
+                (offset.saturating_sub(1), Column::Low)
+            }
+            (Column::Ascii, true) => {
+                (offset.saturating_add(1).min(last), column)
+            }
+            (Column::Ascii, false) => (offset.saturating_sub(1), column),
+            _ => (offset, column),
+        };
+        Self::position(offset, column)
+    }
+
+    #[must_use]
+    pub fn toggle(&self, at: &Position) -> Position {
+        // The same byte in the other set of columns
+        match self.cell(at) {
+            Some((offset, Column::Ascii)) => {
+                Self::position(offset, Column::High)
+            }
+            Some((offset, _)) => Self::position(offset, Column::Ascii),
+            None => self.snap(at),
+        }
+    }
+
+    pub fn overwrite(&mut self, at: &Position, c: char) -> Option<Position> {
+        // Puts `c` where `at` is, returning where the cursor goes next.
+        // Nothing changes when `c` doesn't fit there
+        let (offset, column) = self.cell(at)?;
+        let byte = self.bytes.get_mut(offset)?;
+        let digit = c.to_digit(16).and_then(|digit| u8::try_from(digit).ok());
+        *byte = match (column, digit) {
+            (Column::High, Some(digit)) => (digit << 4) | (*byte & 0x0f),
+            (Column::Low, Some(digit)) => (*byte & 0xf0) | digit,
+            (Column::Ascii, _) if c.is_ascii() => u8::try_from(c).ok()?,
+            _ => return None,
+        };
+        Some(self.step(at, true))
+    }
+
+    #[must_use]
+    pub fn find(
+        &self,
+        needle: &[u8],
+        from: usize,
+        forward: bool,
+    ) -> Option<(usize, bool)> {
+        // The offset of the next match after (or before) `from` and whether
+        // the search wrapped around the end to find it
+        if needle.is_empty() {
+            return None;
+        }
+        let mut matches = self
+            .bytes
+            .windows(needle.len())
+            .enumerate()
+            .filter(|(_, window)| *window == needle)
+            .map(|(offset, _)| offset);
+        if forward {
+            let first = matches.next()?;
+            if first > from {
+                return Some((first, false));
+            }
+            Some(
+                matches
+                    .find(|offset| *offset > from)
+                    .map_or((first, true), |offset| (offset, false)),
+            )
+        } else {
+            let matches: Vec<usize> = matches.collect();
+            let before = matches.iter().rev().find(|offset| **offset < from);
+            match before {
+                Some(offset) => Some((*offset, false)),
+                None => matches.last().map(|offset| (*offset, true)),
+            }
+        }
+    }
+}
+
+pub fn pattern(text: &str) -> Result<Vec<u8>, String> {
+    // The bytes a search in hex mode looks for: hex digits in pairs, with
+    // spaces anywhere between them, or text after a '"'
+    if let Some(text) = text.strip_prefix('"') {
+        return Ok(text.as_bytes().to_vec());
+    }
+    let invalid = || format!("Invalid hex pattern: {text}");
+    let digits: Vec<u8> = text
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| c.to_digit(16).and_then(|digit| u8::try_from(digit).ok()))
+        .collect::<Option<_>>()
+        .ok_or_else(invalid)?;
+    if digits.is_empty() || !digits.len().is_multiple_of(2) {
+        return Err(invalid());
+    }
+    Ok(digits
+        .chunks_exact(2)
+        .map(|pair| match pair {
+            [high, low] => (high << 4) | low,
+            _ => 0,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{pattern, Column, Hex};
+    use crate::Position;
+
+    #[test]
+    fn test_lines() {
+        let hex =
+            Hex::new(b"\x7fELF\x02\x01\x01\0\0\0\0\0\0\0\0\0hi\n".to_vec());
+        assert_eq!(
+            hex.lines(),
+            vec![
+                "00000000: 7f 45 4c 46 02 01 01 00 00 00 00 00 00 00 00 00  \
+                 .ELF............",
+                "00000010: 68 69 0a                                         \
+                 hi.",
+            ]
+        );
+        assert_eq!(Hex::default().lines(), vec!["00000000: "]);
+    }
+
+    #[test]
+    fn test_edit() {
+        let mut hex = Hex::new(b"abc".to_vec());
+        let start = Hex::position(0, Column::High);
+        assert_eq!(hex.cell(&start), Some((0, Column::High)));
+        let next = hex.overwrite(&start, 'f').unwrap();
+        assert_eq!(hex.cell(&next), Some((0, Column::Low)));
+        let next = hex.overwrite(&next, '0').unwrap();
+        assert_eq!(hex.cell(&next), Some((1, Column::High)));
+        assert!(hex.overwrite(&next, 'g').is_none());
+        let ascii = hex.toggle(&next);
+        hex.overwrite(&ascii, 'B').unwrap();
+        assert_eq!(hex.bytes(), b"\xf0Bc");
+        // Past the last byte, and between bytes
+        let end = hex.snap(&Position { x: 70, y: 3 });
+        assert_eq!(hex.cell(&end), Some((2, Column::Ascii)));
+        let gap = hex.snap(&Position { x: 12, y: 0 });
+        assert_eq!(hex.cell(&gap), Some((0, Column::Low)));
+        assert_eq!(hex.step(&end, true), end);
+    }
+
+    #[test]
+    fn test_find() {
+        let hex = Hex::new(b"abcabc".to_vec());
+        assert_eq!(hex.find(b"bc", 0, true), Some((1, false)));
+        assert_eq!(hex.find(b"bc", 1, true), Some((4, false)));
+        assert_eq!(hex.find(b"bc", 4, true), Some((1, true)));
+        assert_eq!(hex.find(b"bc", 4, false), Some((1, false)));
+        assert_eq!(hex.find(b"bc", 1, false), Some((4, true)));
+        assert_eq!(hex.find(b"x", 0, true), None);
+        assert_eq!(pattern("7f 45 4C"), Ok(b"\x7fEL".to_vec()));
+        assert_eq!(pattern("\"ELF"), Ok(b"ELF".to_vec()));
+        assert!(pattern("7f4").is_err());
+        assert!(pattern("zz").is_err());
+    }
+}
@@ -0,0 +1,159 @@
+// Key mappings made with :map and friends. Every mapping belongs to one
+// mode and turns a sequence of keys (the lhs) into another one (the rhs),
+// which is read as if it had been typed. The rhs of a :map is mapped
+// again, the rhs of a :noremap isn't.
+//
+// This module only holds the table, reading keys through it is up to the
+// Editor, which waits 'timeoutlen' for the rest of a sequence when the
+// keys so far start a longer mapping.
+
+use crate::keys;
+use termion::event::Key;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapMode {
+    Normal,
+    Visual,
+    // After an operator like `d` or `>`, waiting for what it acts on
+    OperatorPending,
+    Insert,
+    CommandLine,
+}
+
+impl MapMode {
+    #[must_use]
+    pub fn letter(self) -> char {
+        // As :map lists them
+        match self {
+            MapMode::Normal => 'n',
+            MapMode::Visual => 'v',
+            MapMode::OperatorPending => 'o',
+            MapMode::Insert => 'i',
+            MapMode::CommandLine => 'c',
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mapping {
+    pub mode: MapMode,
+    pub lhs: Vec<Key>,
+    pub rhs: Vec<Key>,
+    pub noremap: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Lookup<'a> {
+    // No mapping starts with the keys
+    None,
+    // A longer mapping starts with the keys, `exact` is the one they
+    // already make if no more keys come
+    Prefix { exact: Option<&'a Mapping> },
+    Exact(&'a Mapping),
+}
+
+#[derive(Debug, Default)]
+pub struct Keymap {
+    mappings: Vec<Mapping>,
+}
+
+impl Keymap {
+    pub fn map(&mut self, mapping: Mapping) {
+        // Replaces the mapping with the same keys in the same mode
+        self.unmap(mapping.mode, &mapping.lhs);
+        self.mappings.push(mapping);
+    }
+
+    pub fn unmap(&mut self, mode: MapMode, lhs: &[Key]) -> bool {
+        let len = self.mappings.len();
+        self.mappings
+            .retain(|mapping| mapping.mode != mode || mapping.lhs != lhs);
+        self.mappings.len() != len
+    }
+
+    #[must_use]
+    pub fn lookup(&self, mode: MapMode, keys: &[Key]) -> Lookup<'_> {
+        let mut exact = None;
+        let mut longer = false;
+        for mapping in self.mappings.iter().filter(|m| m.mode == mode) {
+            if mapping.lhs == keys {
+                exact = Some(mapping);
+            } else if mapping.lhs.starts_with(keys) {
+                longer = true;
+            }
+        }
+        match (longer, exact) {
+            (true, exact) => Lookup::Prefix { exact },
+            (false, Some(mapping)) => Lookup::Exact(mapping),
+            (false, None) => Lookup::None,
+        }
+    }
+
+    #[must_use]
+    pub fn list(&self, modes: &[MapMode], lhs: &[Key]) -> Vec<String> {
+        // The mappings of `modes` whose keys start with `lhs`, as :map
+        // shows them
+        self.mappings
+            .iter()
+            .filter(|m| modes.contains(&m.mode) && m.lhs.starts_with(lhs))
+            .map(|m| {
+                format!(
+                    "{}  {:<12} {}{}",
+                    m.mode.letter(),
+                    keys::notation(&m.lhs),
+                    if m.noremap { "*" } else { " " },
+                    keys::notation(&m.rhs)
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Keymap, Lookup, MapMode, Mapping};
+    use termion::event::Key;
+
+    fn keys(text: &str) -> Vec<Key> {
+        text.chars().map(Key::Char).collect()
+    }
+
+    fn mapping(lhs: &str, rhs: &str) -> Mapping {
+        Mapping {
+            mode: MapMode::Normal,
+            lhs: keys(lhs),
+            rhs: keys(rhs),
+            noremap: true,
+        }
+    }
+
+    #[test]
+    fn test_lookup() {
+        let mut keymap = Keymap::default();
+        keymap.map(mapping("g", "x"));
+        keymap.map(mapping("gq", "y"));
+        keymap.map(mapping("gq", "z"));
+        assert_eq!(
+            keymap.lookup(MapMode::Normal, &keys("g")),
+            Lookup::Prefix {
+                exact: Some(&mapping("g", "x"))
+            }
+        );
+        assert_eq!(
+            keymap.lookup(MapMode::Normal, &keys("gq")),
+            Lookup::Exact(&mapping("gq", "z"))
+        );
+        assert_eq!(keymap.lookup(MapMode::Normal, &keys("gx")), Lookup::None);
+        assert_eq!(keymap.lookup(MapMode::Insert, &keys("g")), Lookup::None);
+        assert!(keymap.unmap(MapMode::Normal, &keys("g")));
+        assert!(!keymap.unmap(MapMode::Normal, &keys("g")));
+        assert_eq!(
+            keymap.lookup(MapMode::Normal, &keys("g")),
+            Lookup::Prefix { exact: None }
+        );
+        assert_eq!(
+            keymap.list(&[MapMode::Normal], &[]),
+            vec!["n  gq           *z".to_string()]
+        );
+    }
+}
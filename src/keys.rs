@@ -0,0 +1,159 @@
+// Vim style key notation: characters stand for themselves and other keys
+// are written between angle brackets, like <CR>, <Esc>, <C-w> or <lt> for
+// a '<' that would otherwise start a key name. A '<' that isn't closed is
+// taken as is. In mappings, <Leader> stands for the 'leader' option.
+
+use termion::event::Key;
+
+fn named(name: &str) -> Option<Key> {
+    let lower = name.to_ascii_lowercase();
+    let key = match lower.as_str() {
+        "cr" | "enter" | "return" | "nl" => Key::Char('\n'),
+        "esc" => Key::Esc,
+        "bs" | "backspace" => Key::Backspace,
+        "tab" => Key::Char('\t'),
+        "s-tab" => Key::BackTab,
+        "nul" => Key::Null,
+        "space" => Key::Char(' '),
+        "lt" => Key::Char('<'),
+        "bar" => Key::Char('|'),
+        "bslash" => Key::Char('\\'),
+        "del" | "delete" => Key::Delete,
+        "insert" => Key::Insert,
+        "up" => Key::Up,
+        "down" => Key::Down,
+        "left" => Key::Left,
+        "right" => Key::Right,
+        "home" => Key::Home,
+        "end" => Key::End,
+        "pageup" => Key::PageUp,
+        "pagedown" => Key::PageDown,
+        _ => {
+            let mut chars = name.chars();
+            return match (
+                chars.next(),
+                chars.next(),
+                chars.next(),
+                chars.next(),
+            ) {
+                (Some('C' | 'c'), Some('-'), Some(c), None) => {
+                    Some(Key::Ctrl(c.to_ascii_lowercase()))
+                }
+                (Some('M' | 'm' | 'A' | 'a'), Some('-'), Some(c), None) => {
+                    Some(Key::Alt(c))
+                }
+                (Some('F' | 'f'), Some(_), _, _) => {
+                    name.get(1..)?.parse().ok().map(Key::F)
+                }
+                _ => None,
+            };
+        }
+    };
+    Some(key)
+}
+
+pub fn parse(notation: &str) -> Result<Vec<Key>, String> {
+    parse_with(notation, None)
+}
+
+pub fn parse_mapping(notation: &str, leader: Key) -> Result<Vec<Key>, String> {
+    parse_with(notation, Some(leader))
+}
+
+fn parse_with(notation: &str, leader: Option<Key>) -> Result<Vec<Key>, String> {
+    let mut keys = Vec::new();
+    let mut rest = notation;
+    while let Some(c) = rest.chars().next() {
+        let name = rest
+            .strip_prefix('<')
+            .and_then(|after| after.split_once('>'));
+        if let Some((name, after)) = name {
+            // <Nop> is no key at all, for mapping keys to nothing
+            if !name.eq_ignore_ascii_case("nop") {
+                let key = leader
+                    .filter(|_| name.eq_ignore_ascii_case("leader"))
+                    .or_else(|| named(name))
+                    .ok_or_else(|| format!("Unknown key <{name}>"))?;
+                keys.push(key);
+            }
+            rest = after;
+        } else {
+            keys.push(Key::Char(c));
+            rest = rest.get(c.len_utf8()..).unwrap_or_default();
+        }
+    }
+    Ok(keys)
+}
+
+#[must_use]
+pub fn notation(keys: &[Key]) -> String {
+    // The opposite of parse()
+    keys.iter()
+        .map(|key| match key {
+            Key::Char('\n') => "<CR>".to_string(),
+            Key::Char('\t') => "<Tab>".to_string(),
+            Key::Char(' ') => "<Space>".to_string(),
+            Key::Char('<') => "<lt>".to_string(),
+            Key::Char(c) => c.to_string(),
+            Key::Ctrl(c) => format!("<C-{c}>"),
+            Key::Alt(c) => format!("<M-{c}>"),
+            Key::F(n) => format!("<F{n}>"),
+            Key::Esc => "<Esc>".to_string(),
+            Key::Backspace => "<BS>".to_string(),
+            Key::Delete => "<Del>".to_string(),
+            Key::Insert => "<Insert>".to_string(),
+            Key::Up => "<Up>".to_string(),
+            Key::Down => "<Down>".to_string(),
+            Key::Left => "<Left>".to_string(),
+            Key::Right => "<Right>".to_string(),
+            Key::Home => "<Home>".to_string(),
+            Key::End => "<End>".to_string(),
+            Key::PageUp => "<PageUp>".to_string(),
+            Key::PageDown => "<PageDown>".to_string(),
+            Key::BackTab => "<S-Tab>".to_string(),
+            Key::Null => "<Nul>".to_string(),
+            _ => String::new(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{notation, parse, parse_mapping};
+    use termion::event::Key;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse(":s/a/b/g<CR>"),
+            Ok(":s/a/b/g\n".chars().map(Key::Char).collect())
+        );
+        assert_eq!(
+            parse("ié<C-c><c-W><Esc><M-x><F12><lt>a<"),
+            Ok(vec![
+                Key::Char('i'),
+                Key::Char('é'),
+                Key::Ctrl('c'),
+                Key::Ctrl('w'),
+                Key::Esc,
+                Key::Alt('x'),
+                Key::F(12),
+                Key::Char('<'),
+                Key::Char('a'),
+                Key::Char('<'),
+            ])
+        );
+        assert!(parse("<nope>").is_err());
+    }
+
+    #[test]
+    fn test_notation() {
+        let keys =
+            parse_mapping("<leader>w<C-w><lt> x<CR>", Key::Char(',')).unwrap();
+        assert_eq!(keys.first(), Some(&Key::Char(',')));
+        assert_eq!(notation(&keys), ",w<C-w><lt><Space>x<CR>");
+        assert_eq!(parse(&notation(&keys)), Ok(keys));
+        assert!(parse("<Leader>").is_err());
+        assert_eq!(parse("<Nop>"), Ok(Vec::new()));
+    }
+}
@@ -0,0 +1,50 @@
+#![warn(clippy::all, clippy::pedantic, clippy::perf)]
+#![allow(
+    clippy::missing_errors_doc,
+    clippy::implicit_return,
+    clippy::missing_docs_in_private_items,
+    clippy::shadow_reuse,
+    clippy::print_stdout,
+    clippy::wildcard_enum_match_arm
+)]
+
+mod backend;
+mod buffer;
+mod commands;
+mod config;
+mod document;
+mod editor;
+#[cfg(any(test, feature = "fuzzing"))]
+mod edits;
+mod encoding;
+mod ex;
+mod explorer;
+mod fileformat;
+mod finder;
+#[cfg(test)]
+mod golden;
+mod hex;
+mod keymap;
+mod keys;
+mod options;
+mod quickfix;
+mod regex;
+mod row;
+mod save;
+mod screen;
+mod swap;
+mod tab;
+mod terminal;
+mod view;
+mod walk;
+mod window;
+// mod tests;
+pub use document::Document;
+pub use editor::Editor;
+pub use editor::Mode;
+pub use editor::Position;
+pub use editor::SelectedText;
+#[cfg(any(test, feature = "fuzzing"))]
+pub use edits::{check, Edit, Model};
+pub use row::Row;
+pub use terminal::Terminal;
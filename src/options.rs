@@ -0,0 +1,557 @@
+// Options changed with `:set`, as in vim. Every option has a type (on/off,
+// a number, a color, a key, a file format or an encoding) and a scope:
+// global options have a single value, while buffer and window options can
+// also be overridden for a single buffer or window with `:setlocal`. `:set`
+// on a local option changes the value every buffer (or window) without an
+// override gets, and drops the override of the current one.
+//
+// The values themselves are only looked up here, what they do is up to
+// the Editor.
+
+use crate::encoding::Encoding;
+use crate::fileformat::FileFormat;
+use crate::keys;
+use std::collections::HashMap;
+use std::fmt;
+use termion::color;
+use termion::event::Key;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Opt {
+    TabStop,
+    Number,
+    Wrap,
+    ScrollOff,
+    IgnoreCase,
+    SmartCase,
+    StatusBg,
+    StatusFg,
+    StatusNcFg,
+    HighlightBg,
+    NumberFg,
+    FinderMatchFg,
+    TimeoutLen,
+    Leader,
+    Backup,
+    FileFormat,
+    EndOfLine,
+    Bomb,
+    FileEncoding,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    Global,
+    Buffer,
+    Window,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Value {
+    Bool(bool),
+    Number(usize),
+    // Written as #rrggbb
+    Color(u8, u8, u8),
+    // Written in key notation, see keys.rs
+    Key(Key),
+    // unix, dos or mac
+    Format(FileFormat),
+    // utf-8, latin1, utf-16 or utf-16le
+    Encoding(Encoding),
+}
+
+impl Value {
+    #[must_use]
+    pub fn flag(self) -> bool {
+        matches!(self, Value::Bool(true))
+    }
+
+    #[must_use]
+    pub fn number(self) -> usize {
+        match self {
+            Value::Number(number) => number,
+            _ => 0,
+        }
+    }
+
+    #[must_use]
+    pub fn color(self) -> color::Rgb {
+        match self {
+            Value::Color(r, g, b) => color::Rgb(r, g, b),
+            _ => color::Rgb(0, 0, 0),
+        }
+    }
+
+    #[must_use]
+    pub fn key(self) -> Key {
+        match self {
+            Value::Key(key) => key,
+            _ => Key::Null,
+        }
+    }
+
+    #[must_use]
+    pub fn format(self) -> FileFormat {
+        match self {
+            Value::Format(format) => format,
+            _ => FileFormat::Unix,
+        }
+    }
+
+    #[must_use]
+    pub fn encoding(self) -> Encoding {
+        match self {
+            Value::Encoding(encoding) => encoding,
+            _ => Encoding::Utf8,
+        }
+    }
+}
+
+struct Definition {
+    opt: Opt,
+    name: &'static str,
+    short: &'static str,
+    scope: Scope,
+    default: Value,
+}
+
+const DEFINITIONS: [Definition; 19] = [
+    Definition {
+        opt: Opt::TabStop,
+        name: "tabstop",
+        short: "ts",
+        scope: Scope::Buffer,
+        default: Value::Number(8),
+    },
+    Definition {
+        opt: Opt::Number,
+        name: "number",
+        short: "nu",
+        scope: Scope::Window,
+        default: Value::Bool(false),
+    },
+    Definition {
+        opt: Opt::Wrap,
+        name: "wrap",
+        short: "",
+        scope: Scope::Window,
+        default: Value::Bool(false),
+    },
+    Definition {
+        opt: Opt::ScrollOff,
+        name: "scrolloff",
+        short: "so",
+        scope: Scope::Global,
+        default: Value::Number(0),
+    },
+    Definition {
+        opt: Opt::IgnoreCase,
+        name: "ignorecase",
+        short: "ic",
+        scope: Scope::Global,
+        default: Value::Bool(true),
+    },
+    Definition {
+        opt: Opt::SmartCase,
+        name: "smartcase",
+        short: "scs",
+        scope: Scope::Global,
+        default: Value::Bool(true),
+    },
+    Definition {
+        opt: Opt::StatusBg,
+        name: "statusbg",
+        short: "",
+        scope: Scope::Global,
+        default: Value::Color(75, 75, 75),
+    },
+    Definition {
+        opt: Opt::StatusFg,
+        name: "statusfg",
+        short: "",
+        scope: Scope::Global,
+        default: Value::Color(200, 200, 200),
+    },
+    Definition {
+        opt: Opt::StatusNcFg,
+        name: "statusncfg",
+        short: "",
+        scope: Scope::Global,
+        default: Value::Color(130, 130, 130),
+    },
+    Definition {
+        opt: Opt::HighlightBg,
+        name: "highlightbg",
+        short: "",
+        scope: Scope::Global,
+        default: Value::Color(75, 75, 75),
+    },
+    Definition {
+        opt: Opt::NumberFg,
+        name: "numberfg",
+        short: "",
+        scope: Scope::Global,
+        default: Value::Color(130, 130, 130),
+    },
+    Definition {
+        opt: Opt::FinderMatchFg,
+        name: "findermatchfg",
+        short: "",
+        scope: Scope::Global,
+        default: Value::Color(230, 180, 80),
+    },
+    Definition {
+        opt: Opt::TimeoutLen,
+        name: "timeoutlen",
+        short: "tm",
+        scope: Scope::Global,
+        default: Value::Number(1000),
+    },
+    Definition {
+        opt: Opt::Leader,
+        name: "leader",
+        short: "",
+        scope: Scope::Global,
+        default: Value::Key(Key::Char(' ')),
+    },
+    Definition {
+        opt: Opt::Backup,
+        name: "backup",
+        short: "bk",
+        scope: Scope::Global,
+        default: Value::Bool(false),
+    },
+    Definition {
+        opt: Opt::FileFormat,
+        name: "fileformat",
+        short: "ff",
+        scope: Scope::Buffer,
+        default: Value::Format(FileFormat::Unix),
+    },
+    Definition {
+        opt: Opt::EndOfLine,
+        name: "endofline",
+        short: "eol",
+        scope: Scope::Buffer,
+        default: Value::Bool(true),
+    },
+    Definition {
+        opt: Opt::Bomb,
+        name: "bomb",
+        short: "",
+        scope: Scope::Buffer,
+        default: Value::Bool(false),
+    },
+    Definition {
+        opt: Opt::FileEncoding,
+        name: "fileencoding",
+        short: "fenc",
+        scope: Scope::Buffer,
+        default: Value::Encoding(Encoding::Utf8),
+    },
+];
+
+impl Opt {
+    fn definition(self) -> &'static Definition {
+        DEFINITIONS
+            .iter()
+            .find(|definition| definition.opt == self)
+            .unwrap_or(&DEFINITIONS[0])
+    }
+
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<Self> {
+        DEFINITIONS
+            .iter()
+            .find(|definition| {
+                definition.name == name
+                    || (!definition.short.is_empty()
+                        && definition.short == name)
+            })
+            .map(|definition| definition.opt)
+    }
+
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        self.definition().name
+    }
+
+    #[must_use]
+    pub fn scope(self) -> Scope {
+        self.definition().scope
+    }
+
+    #[must_use]
+    pub fn default(self) -> Value {
+        self.definition().default
+    }
+
+    pub fn all() -> impl Iterator<Item = Self> {
+        DEFINITIONS.iter().map(|definition| definition.opt)
+    }
+
+    pub fn parse(self, value: &str) -> Result<Value, String> {
+        // Reads a value of the option's type
+        let invalid = || format!("Invalid argument: {}={}", self.name(), value);
+        match self.default() {
+            Value::Bool(_) => Err(invalid()),
+            Value::Number(_) => {
+                value.parse().map(Value::Number).map_err(|_| invalid())
+            }
+            Value::Color(..) => {
+                let hex = value
+                    .strip_prefix('#')
+                    .filter(|hex| hex.len() == 6 && hex.is_ascii())
+                    .ok_or_else(invalid)?;
+                let channel = |at: usize| {
+                    hex.get(at..at.saturating_add(2))
+                        .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+                        .ok_or_else(invalid)
+                };
+                Ok(Value::Color(channel(0)?, channel(2)?, channel(4)?))
+            }
+            Value::Key(_) => match keys::parse(value)?.as_slice() {
+                [key] => Ok(Value::Key(*key)),
+                _ => Err(invalid()),
+            },
+            Value::Format(_) => FileFormat::from_name(value)
+                .map(Value::Format)
+                .ok_or_else(invalid),
+            Value::Encoding(_) => Encoding::from_name(value)
+                .map(Value::Encoding)
+                .ok_or_else(invalid),
+        }
+    }
+
+    #[must_use]
+    pub fn show(self, value: Value) -> String {
+        // As `:set opt?` shows it
+        match value {
+            Value::Bool(true) => self.name().to_string(),
+            Value::Bool(false) => format!("no{}", self.name()),
+            value => format!("{}={}", self.name(), value),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Bool(on) => write!(f, "{on}"),
+            Value::Number(number) => write!(f, "{number}"),
+            Value::Color(r, g, b) => write!(f, "#{r:02x}{g:02x}{b:02x}"),
+            Value::Key(key) => write!(f, "{}", keys::notation(&[*key])),
+            Value::Format(format) => write!(f, "{}", format.name()),
+            Value::Encoding(encoding) => write!(f, "{}", encoding.name()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Setting {
+    // `opt?`, and `opt` for options that aren't on/off
+    Show,
+    // `opt&`
+    Reset,
+    // `opt`, `noopt` and `opt=value`
+    Assign(Value),
+    // `invopt` and `opt!`
+    Toggle,
+    // `opt+=n` and `opt-=n`
+    Add(usize),
+    Subtract(usize),
+}
+
+pub fn parse_setting(argument: &str) -> Result<(Opt, Setting), String> {
+    // One of the arguments of `:set`
+    let unknown = || format!("Unknown option: {argument}");
+    let name_end = argument
+        .find(|c: char| !c.is_ascii_alphanumeric())
+        .unwrap_or(argument.len());
+    let (name, rest) = argument.split_at(name_end);
+    let (opt, setting) = if let Some(opt) = Opt::from_name(name) {
+        let setting = match rest {
+            "" => match opt.default() {
+                Value::Bool(_) => Setting::Assign(Value::Bool(true)),
+                _ => Setting::Show,
+            },
+            "?" => Setting::Show,
+            "&" => Setting::Reset,
+            "!" => Setting::Toggle,
+            _ => {
+                if let Some(value) = rest.strip_prefix("+=") {
+                    Setting::Add(number(opt, value)?)
+                } else if let Some(value) = rest.strip_prefix("-=") {
+                    Setting::Subtract(number(opt, value)?)
+                } else if let Some(value) =
+                    rest.strip_prefix('=').or_else(|| rest.strip_prefix(':'))
+                {
+                    Setting::Assign(opt.parse(value)?)
+                } else {
+                    return Err(format!("Trailing characters: {rest}"));
+                }
+            }
+        };
+        (opt, setting)
+    } else if let Some(opt) = name.strip_prefix("no").and_then(Opt::from_name) {
+        (opt, Setting::Assign(Value::Bool(false)))
+    } else if let Some(opt) = name.strip_prefix("inv").and_then(Opt::from_name)
+    {
+        (opt, Setting::Toggle)
+    } else {
+        return Err(unknown());
+    };
+    // Only on/off options can be switched off or toggled, and `noopt` and
+    // `invopt` take nothing after the name
+    let switch =
+        matches!(setting, Setting::Toggle | Setting::Assign(Value::Bool(_)));
+    if switch && !matches!(opt.default(), Value::Bool(_)) {
+        return Err(format!("Invalid argument: {argument}"));
+    }
+    if name != opt.name()
+        && Opt::from_name(name) != Some(opt)
+        && !rest.is_empty()
+    {
+        return Err(format!("Trailing characters: {rest}"));
+    }
+    Ok((opt, setting))
+}
+
+fn number(opt: Opt, value: &str) -> Result<usize, String> {
+    match opt.parse(value)? {
+        Value::Number(number) => Ok(number),
+        _ => Err(format!("Invalid argument: {}={}", opt.name(), value)),
+    }
+}
+
+impl Setting {
+    #[must_use]
+    pub fn apply(&self, opt: Opt, current: Value) -> Value {
+        // The value the option gets, Show leaves it as it is
+        match (self, current) {
+            (Setting::Reset, _) => opt.default(),
+            (Setting::Assign(value), _) => *value,
+            (Setting::Toggle, Value::Bool(on)) => Value::Bool(!on),
+            (Setting::Add(n), Value::Number(number)) => {
+                Value::Number(number.saturating_add(*n))
+            }
+            (Setting::Subtract(n), Value::Number(number)) => {
+                Value::Number(number.saturating_sub(*n))
+            }
+            _ => current,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    // Options set on this scope, the others come from the scope above it
+    // (for local options) or are at their default (for global ones)
+    values: HashMap<Opt, Value>,
+}
+
+impl Options {
+    #[must_use]
+    pub fn get(&self, opt: Opt) -> Option<Value> {
+        self.values.get(&opt).copied()
+    }
+
+    pub fn set(&mut self, opt: Opt, value: Value) {
+        self.values.insert(opt, value);
+    }
+
+    pub fn unset(&mut self, opt: Opt) {
+        self.values.remove(&opt);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_setting, Opt, Setting, Value};
+    use crate::encoding::Encoding;
+    use crate::fileformat::FileFormat;
+    use termion::event::Key;
+
+    #[test]
+    fn test_parse_setting() {
+        assert_eq!(
+            parse_setting("ts=4"),
+            Ok((Opt::TabStop, Setting::Assign(Value::Number(4))))
+        );
+        assert_eq!(
+            parse_setting("wrap"),
+            Ok((Opt::Wrap, Setting::Assign(Value::Bool(true))))
+        );
+        assert_eq!(
+            parse_setting("nonu"),
+            Ok((Opt::Number, Setting::Assign(Value::Bool(false))))
+        );
+        assert_eq!(parse_setting("invwrap"), Ok((Opt::Wrap, Setting::Toggle)));
+        assert_eq!(
+            parse_setting("ic!"),
+            Ok((Opt::IgnoreCase, Setting::Toggle))
+        );
+        assert_eq!(parse_setting("so?"), Ok((Opt::ScrollOff, Setting::Show)));
+        assert_eq!(parse_setting("tabstop"), Ok((Opt::TabStop, Setting::Show)));
+        assert_eq!(parse_setting("ts&"), Ok((Opt::TabStop, Setting::Reset)));
+        assert_eq!(
+            parse_setting("so+=3"),
+            Ok((Opt::ScrollOff, Setting::Add(3)))
+        );
+        assert_eq!(
+            parse_setting("statusbg=#1e2030"),
+            Ok((Opt::StatusBg, Setting::Assign(Value::Color(30, 32, 48))))
+        );
+        assert_eq!(
+            parse_setting("ff=dos"),
+            Ok((
+                Opt::FileFormat,
+                Setting::Assign(Value::Format(FileFormat::Dos))
+            ))
+        );
+        assert!(parse_setting("ff=vms").is_err());
+        assert_eq!(
+            parse_setting("fenc=latin1"),
+            Ok((
+                Opt::FileEncoding,
+                Setting::Assign(Value::Encoding(Encoding::Latin1))
+            ))
+        );
+        assert!(parse_setting("frobnicate").is_err());
+        assert!(parse_setting("ts=x").is_err());
+        assert!(parse_setting("nots").is_err());
+        assert!(parse_setting("wrap=1").is_err());
+        assert!(parse_setting("statusbg=red").is_err());
+        assert_eq!(
+            parse_setting("leader=<Bslash>"),
+            Ok((Opt::Leader, Setting::Assign(Value::Key(Key::Char('\\')))))
+        );
+        assert!(parse_setting("leader=ab").is_err());
+    }
+
+    #[test]
+    fn test_apply() {
+        let number = Value::Number(4);
+        assert_eq!(
+            Setting::Add(2).apply(Opt::TabStop, number),
+            Value::Number(6)
+        );
+        assert_eq!(
+            Setting::Subtract(9).apply(Opt::TabStop, number),
+            Value::Number(0)
+        );
+        assert_eq!(
+            Setting::Reset.apply(Opt::TabStop, number),
+            Value::Number(8)
+        );
+        assert_eq!(
+            Setting::Toggle.apply(Opt::Wrap, Value::Bool(false)),
+            Value::Bool(true)
+        );
+        assert_eq!(Opt::Number.show(Value::Bool(false)), "nonumber");
+        assert_eq!(
+            Opt::StatusBg.show(Opt::StatusBg.default()),
+            "statusbg=#4b4b4b"
+        );
+    }
+}
@@ -0,0 +1,76 @@
+// The quickfix list holds locations in files, filled by :grep and walked
+// through with :cn and :cp. Entries only point into the files, opening
+// them is up to the Editor.
+
+use crate::Position;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub path: PathBuf,
+    pub position: Position,
+    // The line the location is in, shown in the quickfix pane
+    pub text: String,
+}
+
+#[derive(Default, Debug)]
+pub struct Quickfix {
+    // The command that filled the list
+    title: String,
+    entries: Vec<Entry>,
+    current: usize,
+}
+
+impl Quickfix {
+    #[must_use]
+    pub fn new(title: String, entries: Vec<Entry>) -> Self {
+        Self {
+            title,
+            entries,
+            current: 0,
+        }
+    }
+
+    #[must_use]
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    #[must_use]
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    #[must_use]
+    pub fn index(&self) -> usize {
+        self.current
+    }
+
+    // Index of the entry `forward` or backwards from the current one,
+    // None when already at the end of the list in that direction
+    #[must_use]
+    pub fn step(&self, forward: bool) -> Option<usize> {
+        let index = if forward {
+            self.current.saturating_add(1)
+        } else {
+            self.current.checked_sub(1)?
+        };
+        (index < self.entries.len()).then_some(index)
+    }
+
+    pub fn select(&mut self, index: usize) {
+        if index < self.entries.len() {
+            self.current = index;
+        }
+    }
+}
@@ -0,0 +1,1177 @@
+// Small backtracking regex engine used by search and :substitute.
+//
+// A pattern is parsed according to its `Syntax` into a tree of `Node`s,
+// which is then compiled into a flat list of instructions run by a
+// backtracking matcher. Every instruction executed costs one step, and a
+// search gives up with `Error::TooExpensive` once it runs out of steps,
+// so pathological patterns such as `\(a*\)*b` can't hang the editor. A
+// search that looks for several matches counts its steps across all of
+// them (see `Regex::captures_from`).
+//
+// The haystack is a single string with rows joined by '\n', which lets
+// patterns span multiple lines (e.g. `foo\nbar`). Every position handed
+// in or out of this module is a byte offset into that string.
+
+use std::fmt;
+
+// Default amount of steps a single search may take before aborting
+const STEP_LIMIT: usize = 10_000_000;
+
+// Upper bound on the size of a compiled program, counted repetitions
+// such as `a{1000}` are expanded so this keeps them in check
+const PROGRAM_LIMIT: usize = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Syntax {
+    // Vim's "magic" syntax: `\(\)`, `\|`, `\+`, `\=`, `\{n,m}`, `\<\>`.
+    // A leading `\v` switches the rest of the pattern to `Pcre`
+    Vim,
+    // Perl-like syntax: `()`, `(?:)`, `|`, `+`, `?`, `{n,m}`, `\b`
+    Pcre,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CaseMode {
+    Sensitive,
+    Insensitive,
+    // Insensitive unless the pattern contains an uppercase letter
+    Smart,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    Invalid(String),
+    TooExpensive,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Invalid(reason) => write!(f, "Invalid pattern: {reason}"),
+            Error::TooExpensive => {
+                write!(f, "Pattern too expensive, search aborted")
+            }
+        }
+    }
+}
+
+fn invalid<T>(reason: &str) -> Result<T, Error> {
+    Err(Error::Invalid(reason.to_string()))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Named {
+    Digit,
+    Word,
+    Space,
+    Alpha,
+    Alnum,
+    Lower,
+    Upper,
+    Hex,
+    Punct,
+}
+
+impl Named {
+    fn matches(self, c: char) -> bool {
+        match self {
+            Named::Digit => c.is_ascii_digit(),
+            Named::Word => c.is_alphanumeric() || c == '_',
+            Named::Space => c.is_whitespace(),
+            Named::Alpha => c.is_alphabetic(),
+            Named::Alnum => c.is_alphanumeric(),
+            Named::Lower => c.is_lowercase(),
+            Named::Upper => c.is_uppercase(),
+            Named::Hex => c.is_ascii_hexdigit(),
+            Named::Punct => c.is_ascii_punctuation(),
+        }
+    }
+
+    fn from_posix(name: &str) -> Option<Self> {
+        match name {
+            "digit" => Some(Named::Digit),
+            "space" => Some(Named::Space),
+            "alpha" => Some(Named::Alpha),
+            "alnum" => Some(Named::Alnum),
+            "lower" => Some(Named::Lower),
+            "upper" => Some(Named::Upper),
+            "xdigit" => Some(Named::Hex),
+            "punct" => Some(Named::Punct),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ClassItem {
+    Range(char, char),
+    Named(Named, bool),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Class {
+    items: Vec<ClassItem>,
+    negated: bool,
+    // Whether the class also matches '\n', negated classes never do
+    newline: bool,
+}
+
+impl Class {
+    fn matches(&self, c: char, ignore_case: bool) -> bool {
+        if c == '\n' {
+            return self.newline;
+        }
+        let hit = |c: char| {
+            self.items.iter().any(|item| match *item {
+                ClassItem::Range(lo, hi) => lo <= c && c <= hi,
+                ClassItem::Named(named, negated) => named.matches(c) != negated,
+            })
+        };
+        let found = if ignore_case {
+            hit(c) || hit(lower(c)) || hit(upper(c))
+        } else {
+            hit(c)
+        };
+        found != self.negated
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Assertion {
+    LineStart,
+    LineEnd,
+    WordBoundary,
+    NotWordBoundary,
+    WordStart,
+    WordEnd,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Empty,
+    Literal(char),
+    // `.` never matches a newline, `\_.` does
+    Any(bool),
+    Class(Class),
+    Assert(Assertion),
+    Group(Box<Node>, Option<usize>),
+    Concat(Vec<Node>),
+    Alt(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: usize,
+        max: Option<usize>,
+        greedy: bool,
+    },
+    Backref(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Literal(char),
+    Any(bool),
+    Class(Class),
+    ClassOpen,
+    Assert(Assertion),
+    Caret,
+    Dollar,
+    Repeat(usize, Option<usize>, bool),
+    GroupOpen(bool),
+    GroupClose,
+    Alt,
+    Backref(usize),
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    syntax: Syntax,
+    groups: usize,
+    // Set by inline flags: `\c`/`\C` in vim and `(?i)` in pcre
+    ignore_case: Option<bool>,
+}
+
+impl Parser {
+    fn new(pattern: &str, syntax: Syntax) -> Self {
+        Self {
+            chars: pattern.chars().collect(),
+            pos: 0,
+            syntax,
+            groups: 0,
+            ignore_case: None,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek();
+        if c.is_some() {
+            self.pos = self.pos.saturating_add(1);
+        }
+        c
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos = self.pos.saturating_add(1);
+            return true;
+        }
+        false
+    }
+
+    fn parse(&mut self) -> Result<Node, Error> {
+        let node = self.parse_alt()?;
+        if self.pos < self.chars.len() {
+            return invalid("unmatched )");
+        }
+        Ok(node)
+    }
+
+    fn parse_alt(&mut self) -> Result<Node, Error> {
+        let mut branches = vec![self.parse_concat()?];
+        loop {
+            let start = self.pos;
+            match self.next_token()? {
+                Some(Token::Alt) => branches.push(self.parse_concat()?),
+                Some(Token::GroupClose) => {
+                    self.pos = start;
+                    break;
+                }
+                None => break,
+                Some(_) => unreachable!("parse_concat stops at | or )"),
+            }
+        }
+        if branches.len() == 1 {
+            return Ok(branches.remove(0));
+        }
+        Ok(Node::Alt(branches))
+    }
+
+    fn parse_concat(&mut self) -> Result<Node, Error> {
+        let mut nodes: Vec<Node> = Vec::new();
+        loop {
+            let start = self.pos;
+            let Some(token) = self.next_token()? else {
+                break;
+            };
+            let node = match token {
+                Token::Alt | Token::GroupClose => {
+                    self.pos = start;
+                    break;
+                }
+                Token::Repeat(min, max, greedy) => {
+                    let Some(last) = nodes.pop() else {
+                        return invalid("nothing to repeat");
+                    };
+                    if matches!(last, Node::Repeat { .. } | Node::Assert(_)) {
+                        return invalid("nothing to repeat");
+                    }
+                    Node::Repeat {
+                        node: Box::new(last),
+                        min,
+                        max,
+                        greedy,
+                    }
+                }
+                Token::Caret => {
+                    // Vim only treats '^' as an anchor at the start of
+                    // a branch
+                    if self.syntax == Syntax::Pcre || nodes.is_empty() {
+                        Node::Assert(Assertion::LineStart)
+                    } else {
+                        Node::Literal('^')
+                    }
+                }
+                Token::Dollar => {
+                    if self.syntax == Syntax::Pcre || self.at_branch_end()? {
+                        Node::Assert(Assertion::LineEnd)
+                    } else {
+                        Node::Literal('$')
+                    }
+                }
+                Token::GroupOpen(capturing) => self.parse_group(capturing)?,
+                Token::ClassOpen => Node::Class(self.parse_class()?),
+                Token::Literal(c) => Node::Literal(c),
+                Token::Any(newline) => Node::Any(newline),
+                Token::Class(class) => Node::Class(class),
+                Token::Assert(assertion) => Node::Assert(assertion),
+                Token::Backref(n) => {
+                    if n > self.groups {
+                        return invalid("reference to an unknown group");
+                    }
+                    Node::Backref(n)
+                }
+            };
+            nodes.push(node);
+        }
+        match nodes.len() {
+            0 => Ok(Node::Empty),
+            1 => Ok(nodes.remove(0)),
+            _ => Ok(Node::Concat(nodes)),
+        }
+    }
+
+    fn at_branch_end(&mut self) -> Result<bool, Error> {
+        let start = self.pos;
+        let next = self.next_token()?;
+        self.pos = start;
+        Ok(matches!(next, None | Some(Token::Alt | Token::GroupClose)))
+    }
+
+    fn parse_group(&mut self, capturing: bool) -> Result<Node, Error> {
+        let index = if capturing {
+            self.groups = self.groups.saturating_add(1);
+            Some(self.groups)
+        } else {
+            None
+        };
+        let node = self.parse_alt()?;
+        if self.next_token()? != Some(Token::GroupClose) {
+            return invalid("unmatched (");
+        }
+        Ok(Node::Group(Box::new(node), index))
+    }
+
+    fn parse_class(&mut self) -> Result<Class, Error> {
+        let mut class = Class {
+            items: Vec::new(),
+            negated: self.eat('^'),
+            newline: false,
+        };
+        let mut first = true;
+        loop {
+            let Some(c) = self.bump() else {
+                return invalid("unmatched [");
+            };
+            if c == ']' && !first {
+                break;
+            }
+            first = false;
+            let lo = match c {
+                '[' if self.peek() == Some(':') => {
+                    class.items.push(self.parse_posix_class()?);
+                    continue;
+                }
+                '\\' => match self.bump() {
+                    Some('n') => {
+                        class.newline = true;
+                        continue;
+                    }
+                    Some('t') => '\t',
+                    Some('e') => '\u{1b}',
+                    Some(c) => {
+                        if let Some(item) = class_escape(c) {
+                            class.items.push(item);
+                            continue;
+                        }
+                        c
+                    }
+                    None => return invalid("trailing \\"),
+                },
+                c => c,
+            };
+            let is_range = self.peek() == Some('-')
+                && !matches!(
+                    self.chars.get(self.pos.saturating_add(1)),
+                    Some(']') | None
+                );
+            if is_range {
+                self.pos = self.pos.saturating_add(1);
+                let hi = match self.bump() {
+                    Some('\\') => self.bump(),
+                    c => c,
+                };
+                let Some(hi) = hi else {
+                    return invalid("unmatched [");
+                };
+                if hi < lo {
+                    return invalid("reversed range in []");
+                }
+                class.items.push(ClassItem::Range(lo, hi));
+            } else {
+                class.items.push(ClassItem::Range(lo, lo));
+            }
+        }
+        if class.negated {
+            class.newline = false;
+        }
+        Ok(class)
+    }
+
+    fn parse_posix_class(&mut self) -> Result<ClassItem, Error> {
+        // Called right after '[' with ':' as the next character
+        self.pos = self.pos.saturating_add(1);
+        let mut name = String::new();
+        while let Some(c) = self.bump() {
+            if c == ':' && self.eat(']') {
+                return match Named::from_posix(&name) {
+                    Some(named) => Ok(ClassItem::Named(named, false)),
+                    None => invalid(&format!("unknown class [:{name}:]")),
+                };
+            }
+            name.push(c);
+        }
+        invalid("unmatched [")
+    }
+
+    fn parse_braces(&mut self) -> Result<Token, Error> {
+        // Called right after '{'. Accepts `{n}`, `{n,}`, `{,m}`, `{n,m}`
+        // and vim's `{}` and `{-n,m}` (non greedy)
+        let mut greedy = true;
+        if self.syntax == Syntax::Vim && self.eat('-') {
+            greedy = false;
+        }
+        let min = self.parse_number();
+        let max = if self.eat(',') {
+            self.parse_number()
+        } else if min.is_none() {
+            None
+        } else {
+            min
+        };
+        if self.syntax == Syntax::Vim {
+            self.eat('\\');
+        }
+        if !self.eat('}') {
+            return invalid("unmatched {");
+        }
+        let min = min.unwrap_or(0);
+        if max.is_some_and(|max| max < min) {
+            return invalid("reversed range in {}");
+        }
+        if self.syntax == Syntax::Pcre && self.eat('?') {
+            greedy = false;
+        }
+        Ok(Token::Repeat(min, max, greedy))
+    }
+
+    fn parse_number(&mut self) -> Option<usize> {
+        let mut number: Option<usize> = None;
+        while let Some(digit) = self.peek().and_then(|c| c.to_digit(10)) {
+            self.pos = self.pos.saturating_add(1);
+            number = Some(
+                number
+                    .unwrap_or(0)
+                    .saturating_mul(10)
+                    .saturating_add(digit as usize),
+            );
+        }
+        number
+    }
+
+    fn next_token(&mut self) -> Result<Option<Token>, Error> {
+        loop {
+            let Some(c) = self.bump() else {
+                return Ok(None);
+            };
+            let token = match self.syntax {
+                Syntax::Vim => self.vim_token(c)?,
+                Syntax::Pcre => self.pcre_token(c)?,
+            };
+            // Inline flags produce no token
+            if let Some(token) = token {
+                return Ok(Some(token));
+            }
+        }
+    }
+
+    fn vim_token(&mut self, c: char) -> Result<Option<Token>, Error> {
+        let token = match c {
+            '.' => Token::Any(false),
+            '*' => Token::Repeat(0, None, true),
+            '[' => Token::ClassOpen,
+            '^' => Token::Caret,
+            '$' => Token::Dollar,
+            '\\' => {
+                let Some(c) = self.bump() else {
+                    return invalid("trailing \\");
+                };
+                match c {
+                    '(' => Token::GroupOpen(true),
+                    ')' => Token::GroupClose,
+                    '%' if self.eat('(') => Token::GroupOpen(false),
+                    '|' => Token::Alt,
+                    '+' => Token::Repeat(1, None, true),
+                    '=' | '?' => Token::Repeat(0, Some(1), true),
+                    '{' => self.parse_braces()?,
+                    '<' => Token::Assert(Assertion::WordStart),
+                    '>' => Token::Assert(Assertion::WordEnd),
+                    '_' => match self.bump() {
+                        Some('.') => Token::Any(true),
+                        Some(c) => {
+                            let Some(item) = class_escape(c) else {
+                                return invalid("unknown \\_ class");
+                            };
+                            Token::Class(Class {
+                                items: vec![item],
+                                negated: false,
+                                newline: true,
+                            })
+                        }
+                        None => return invalid("trailing \\_"),
+                    },
+                    'c' => {
+                        self.ignore_case = Some(true);
+                        return Ok(None);
+                    }
+                    'C' => {
+                        self.ignore_case = Some(false);
+                        return Ok(None);
+                    }
+                    c => Self::common_escape(c),
+                }
+            }
+            c => Token::Literal(c),
+        };
+        Ok(Some(token))
+    }
+
+    fn pcre_token(&mut self, c: char) -> Result<Option<Token>, Error> {
+        let token = match c {
+            '.' => Token::Any(false),
+            '*' | '+' | '?' => {
+                let min = usize::from(c == '+');
+                let max = if c == '?' { Some(1) } else { None };
+                Token::Repeat(min, max, !self.eat('?'))
+            }
+            '{' => self.parse_braces()?,
+            '[' => Token::ClassOpen,
+            '^' => Token::Caret,
+            '$' => Token::Dollar,
+            '|' => Token::Alt,
+            ')' => Token::GroupClose,
+            '(' => {
+                if self.eat('?') {
+                    if self.eat(':') {
+                        Token::GroupOpen(false)
+                    } else if self.eat('i') && self.eat(')') {
+                        self.ignore_case = Some(true);
+                        return Ok(None);
+                    } else {
+                        return invalid("unsupported (? group");
+                    }
+                } else {
+                    Token::GroupOpen(true)
+                }
+            }
+            '\\' => {
+                let Some(c) = self.bump() else {
+                    return invalid("trailing \\");
+                };
+                match c {
+                    'b' => Token::Assert(Assertion::WordBoundary),
+                    'B' => Token::Assert(Assertion::NotWordBoundary),
+                    '<' => Token::Assert(Assertion::WordStart),
+                    '>' => Token::Assert(Assertion::WordEnd),
+                    c => Self::common_escape(c),
+                }
+            }
+            c => Token::Literal(c),
+        };
+        Ok(Some(token))
+    }
+
+    fn common_escape(c: char) -> Token {
+        match c {
+            'n' => Token::Class(Class {
+                items: Vec::new(),
+                negated: false,
+                newline: true,
+            }),
+            't' => Token::Literal('\t'),
+            'r' => Token::Literal('\r'),
+            'e' => Token::Literal('\u{1b}'),
+            '1'..='9' => Token::Backref(c as usize - '0' as usize),
+            c => match class_escape(c) {
+                Some(item) => Token::Class(Class {
+                    items: vec![item],
+                    negated: false,
+                    newline: false,
+                }),
+                None => Token::Literal(c),
+            },
+        }
+    }
+}
+
+fn class_escape(c: char) -> Option<ClassItem> {
+    let (named, negated) = match c {
+        'd' => (Named::Digit, false),
+        'D' => (Named::Digit, true),
+        'w' => (Named::Word, false),
+        'W' => (Named::Word, true),
+        's' => (Named::Space, false),
+        'S' => (Named::Space, true),
+        'a' => (Named::Alpha, false),
+        'A' => (Named::Alpha, true),
+        'l' => (Named::Lower, false),
+        'L' => (Named::Lower, true),
+        'u' => (Named::Upper, false),
+        'U' => (Named::Upper, true),
+        'x' => (Named::Hex, false),
+        'X' => (Named::Hex, true),
+        _ => return None,
+    };
+    Some(ClassItem::Named(named, negated))
+}
+
+fn has_uppercase(pattern: &str) -> bool {
+    // Characters right after a '\' are part of an escape (e.g. `\S`)
+    // and don't count
+    let mut escaped = false;
+    for c in pattern.chars() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c.is_uppercase() {
+            return true;
+        }
+    }
+    false
+}
+
+fn lower(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+fn upper(c: char) -> char {
+    c.to_uppercase().next().unwrap_or(c)
+}
+
+fn is_word(c: Option<char>) -> bool {
+    c.is_some_and(|c| Named::Word.matches(c))
+}
+
+#[derive(Debug, Clone)]
+enum Inst {
+    Char(char),
+    Any(bool),
+    Class(Class),
+    Assert(Assertion),
+    // Try the first branch, falling back to the second one
+    Split(usize, usize),
+    Jmp(usize),
+    Save(usize),
+    Backref(usize),
+    // Mark/Progress guard loops whose body can match the empty string,
+    // they would otherwise spin forever without consuming anything
+    Mark(usize),
+    Progress(usize),
+    Match,
+}
+
+enum Frame {
+    Try(usize, usize),
+    Slot(usize, Option<usize>),
+    Mark(usize, usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Captures {
+    slots: Vec<Option<usize>>,
+}
+
+impl Captures {
+    #[must_use]
+    pub fn get(&self, group: usize) -> Option<(usize, usize)> {
+        let start = (*self.slots.get(group.saturating_mul(2))?)?;
+        let end =
+            (*self.slots.get(group.saturating_mul(2).saturating_add(1))?)?;
+        Some((start, end))
+    }
+
+    #[must_use]
+    pub fn start(&self) -> usize {
+        self.get(0).map_or(0, |(start, _)| start)
+    }
+
+    #[must_use]
+    pub fn end(&self) -> usize {
+        self.get(0).map_or(0, |(_, end)| end)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Regex {
+    program: Vec<Inst>,
+    slots: usize,
+    marks: usize,
+    ignore_case: bool,
+    // Literal every match must start with, used to skip ahead quickly
+    first: Option<char>,
+}
+
+impl Regex {
+    pub fn new(
+        pattern: &str,
+        syntax: Syntax,
+        case: CaseMode,
+    ) -> Result<Self, Error> {
+        let mut parser = match pattern.strip_prefix("\\v") {
+            Some(rest) if syntax == Syntax::Vim => {
+                Parser::new(rest, Syntax::Pcre)
+            }
+            _ => Parser::new(pattern, syntax),
+        };
+        let node = parser.parse()?;
+        let ignore_case = parser.ignore_case.unwrap_or(match case {
+            CaseMode::Sensitive => false,
+            CaseMode::Insensitive => true,
+            CaseMode::Smart => !has_uppercase(pattern),
+        });
+
+        let mut regex = Self {
+            program: Vec::new(),
+            slots: parser.groups.saturating_add(1).saturating_mul(2),
+            marks: 0,
+            ignore_case,
+            first: None,
+        };
+        regex.program.push(Inst::Save(0));
+        regex.compile(&node)?;
+        regex.program.push(Inst::Save(1));
+        regex.program.push(Inst::Match);
+        if !ignore_case {
+            regex.first = first_literal(&node);
+        }
+        Ok(regex)
+    }
+
+    fn emit(&mut self, inst: Inst) -> Result<usize, Error> {
+        if self.program.len() >= PROGRAM_LIMIT {
+            return invalid("pattern too large");
+        }
+        self.program.push(inst);
+        Ok(self.program.len().saturating_sub(1))
+    }
+
+    fn patch(&mut self, at: usize, inst: Inst) {
+        if let Some(slot) = self.program.get_mut(at) {
+            *slot = inst;
+        }
+    }
+
+    fn compile(&mut self, node: &Node) -> Result<(), Error> {
+        match node {
+            Node::Empty => (),
+            Node::Literal(c) => {
+                let c = if self.ignore_case { lower(*c) } else { *c };
+                self.emit(Inst::Char(c))?;
+            }
+            Node::Any(newline) => {
+                self.emit(Inst::Any(*newline))?;
+            }
+            Node::Class(class) => {
+                self.emit(Inst::Class(class.clone()))?;
+            }
+            Node::Assert(assertion) => {
+                self.emit(Inst::Assert(*assertion))?;
+            }
+            Node::Backref(n) => {
+                self.emit(Inst::Backref(*n))?;
+            }
+            Node::Group(node, index) => {
+                if let Some(index) = index {
+                    self.emit(Inst::Save(index.saturating_mul(2)))?;
+                    self.compile(node)?;
+                    self.emit(Inst::Save(
+                        index.saturating_mul(2).saturating_add(1),
+                    ))?;
+                } else {
+                    self.compile(node)?;
+                }
+            }
+            Node::Concat(nodes) => {
+                for node in nodes {
+                    self.compile(node)?;
+                }
+            }
+            Node::Alt(branches) => self.compile_alt(branches)?,
+            Node::Repeat {
+                node,
+                min,
+                max,
+                greedy,
+            } => self.compile_repeat(node, *min, *max, *greedy)?,
+        }
+        Ok(())
+    }
+
+    fn compile_alt(&mut self, branches: &[Node]) -> Result<(), Error> {
+        let mut jumps = Vec::new();
+        let last = branches.len().saturating_sub(1);
+        for (index, branch) in branches.iter().enumerate() {
+            if index == last {
+                self.compile(branch)?;
+                break;
+            }
+            let split = self.emit(Inst::Split(0, 0))?;
+            self.compile(branch)?;
+            jumps.push(self.emit(Inst::Jmp(0))?);
+            let next = self.program.len();
+            self.patch(split, Inst::Split(split.saturating_add(1), next));
+        }
+        let end = self.program.len();
+        for jump in jumps {
+            self.patch(jump, Inst::Jmp(end));
+        }
+        Ok(())
+    }
+
+    fn split(&mut self, at: usize, body: usize, out: usize, greedy: bool) {
+        if greedy {
+            self.patch(at, Inst::Split(body, out));
+        } else {
+            self.patch(at, Inst::Split(out, body));
+        }
+    }
+
+    fn compile_repeat(
+        &mut self,
+        node: &Node,
+        min: usize,
+        max: Option<usize>,
+        greedy: bool,
+    ) -> Result<(), Error> {
+        for _ in 0..min {
+            self.compile(node)?;
+        }
+        if let Some(max) = max {
+            // Each optional copy jumps straight to the end when it
+            // doesn't match, `x{1,3}` becomes `x(x(x)?)?`
+            let mut splits = Vec::new();
+            for _ in min..max {
+                splits.push(self.emit(Inst::Split(0, 0))?);
+                self.compile(node)?;
+            }
+            let end = self.program.len();
+            for split in splits {
+                self.split(split, split.saturating_add(1), end, greedy);
+            }
+            return Ok(());
+        }
+
+        let mark = self.marks;
+        self.marks = self.marks.saturating_add(1);
+        let split = self.emit(Inst::Split(0, 0))?;
+        self.emit(Inst::Mark(mark))?;
+        self.compile(node)?;
+        self.emit(Inst::Progress(mark))?;
+        self.emit(Inst::Jmp(split))?;
+        let end = self.program.len();
+        self.split(split, split.saturating_add(1), end, greedy);
+        Ok(())
+    }
+
+    // Leftmost match starting at or after `from`. The steps taken are
+    // added to `steps`, which the calls that make up a single search (as
+    // going backwards, or the scan of :global) share so the limit holds for
+    // all of them together
+    pub fn captures_from(
+        &self,
+        haystack: &str,
+        from: usize,
+        steps: &mut usize,
+    ) -> Result<Option<Captures>, Error> {
+        let mut start = from;
+        while start <= haystack.len() {
+            if let Some(first) = self.first {
+                match haystack.get(start..).and_then(|rest| rest.find(first)) {
+                    Some(skip) => start = start.saturating_add(skip),
+                    None => return Ok(None),
+                }
+            }
+            if let Some(slots) = self.run(haystack, start, steps)? {
+                return Ok(Some(Captures { slots }));
+            }
+            match haystack.get(start..).and_then(|rest| rest.chars().next()) {
+                Some(c) => start = start.saturating_add(c.len_utf8()),
+                None => break,
+            }
+        }
+        Ok(None)
+    }
+
+    fn char_eq(&self, a: char, b: char) -> bool {
+        a == b || (self.ignore_case && lower(a) == lower(b))
+    }
+
+    #[allow(clippy::too_many_lines)]
+    fn run(
+        &self,
+        haystack: &str,
+        start: usize,
+        steps: &mut usize,
+    ) -> Result<Option<Vec<Option<usize>>>, Error> {
+        let mut slots = vec![None; self.slots];
+        let mut marks = vec![usize::MAX; self.marks];
+        let mut stack = vec![Frame::Try(0, start)];
+        let next_char = |pos: usize| {
+            haystack.get(pos..).and_then(|rest| rest.chars().next())
+        };
+        let prev_char = |pos: usize| {
+            haystack
+                .get(..pos)
+                .and_then(|rest| rest.chars().next_back())
+        };
+
+        while let Some(frame) = stack.pop() {
+            let (mut pc, mut pos) = match frame {
+                Frame::Try(pc, pos) => (pc, pos),
+                Frame::Slot(slot, old) => {
+                    if let Some(slot) = slots.get_mut(slot) {
+                        *slot = old;
+                    }
+                    continue;
+                }
+                Frame::Mark(mark, old) => {
+                    if let Some(mark) = marks.get_mut(mark) {
+                        *mark = old;
+                    }
+                    continue;
+                }
+            };
+            loop {
+                *steps = steps.saturating_add(1);
+                if *steps > STEP_LIMIT {
+                    return Err(Error::TooExpensive);
+                }
+                let Some(inst) = self.program.get(pc) else {
+                    break;
+                };
+                match inst {
+                    Inst::Match => return Ok(Some(slots)),
+                    Inst::Char(c) => match next_char(pos) {
+                        Some(h) if self.char_eq(*c, h) => {
+                            pos = pos.saturating_add(h.len_utf8());
+                        }
+                        _ => break,
+                    },
+                    Inst::Any(newline) => match next_char(pos) {
+                        Some(h) if h != '\n' || *newline => {
+                            pos = pos.saturating_add(h.len_utf8());
+                        }
+                        _ => break,
+                    },
+                    Inst::Class(class) => match next_char(pos) {
+                        Some(h) if class.matches(h, self.ignore_case) => {
+                            pos = pos.saturating_add(h.len_utf8());
+                        }
+                        _ => break,
+                    },
+                    Inst::Assert(assertion) => {
+                        let before = prev_char(pos);
+                        let after = next_char(pos);
+                        let ok = match assertion {
+                            Assertion::LineStart => {
+                                before.is_none_or(|c| c == '\n')
+                            }
+                            Assertion::LineEnd => {
+                                after.is_none_or(|c| c == '\n')
+                            }
+                            Assertion::WordBoundary => {
+                                is_word(before) != is_word(after)
+                            }
+                            Assertion::NotWordBoundary => {
+                                is_word(before) == is_word(after)
+                            }
+                            Assertion::WordStart => {
+                                !is_word(before) && is_word(after)
+                            }
+                            Assertion::WordEnd => {
+                                is_word(before) && !is_word(after)
+                            }
+                        };
+                        if !ok {
+                            break;
+                        }
+                    }
+                    Inst::Split(first, second) => {
+                        stack.push(Frame::Try(*second, pos));
+                        pc = *first;
+                        continue;
+                    }
+                    Inst::Jmp(to) => {
+                        pc = *to;
+                        continue;
+                    }
+                    Inst::Save(slot) => {
+                        if let Some(value) = slots.get_mut(*slot) {
+                            stack.push(Frame::Slot(*slot, *value));
+                            *value = Some(pos);
+                        }
+                    }
+                    Inst::Mark(mark) => {
+                        if let Some(value) = marks.get_mut(*mark) {
+                            stack.push(Frame::Mark(*mark, *value));
+                            *value = pos;
+                        }
+                    }
+                    Inst::Progress(mark) => {
+                        if marks.get(*mark) == Some(&pos) {
+                            break;
+                        }
+                    }
+                    Inst::Backref(group) => {
+                        let start = slots.get(group.saturating_mul(2));
+                        let end = slots
+                            .get(group.saturating_mul(2).saturating_add(1));
+                        let (Some(Some(start)), Some(Some(end))) = (start, end)
+                        else {
+                            // Groups that didn't participate match nothing
+                            pc = pc.saturating_add(1);
+                            continue;
+                        };
+                        let Some(captured) = haystack.get(*start..*end) else {
+                            break;
+                        };
+                        let mut rest =
+                            haystack.get(pos..).unwrap_or("").chars();
+                        let mut len = 0;
+                        let equal = captured.chars().all(|c| {
+                            rest.next().is_some_and(|h| {
+                                len += h.len_utf8();
+                                self.char_eq(c, h)
+                            })
+                        });
+                        if !equal {
+                            break;
+                        }
+                        pos = pos.saturating_add(len);
+                    }
+                }
+                pc = pc.saturating_add(1);
+            }
+        }
+        Ok(None)
+    }
+}
+
+fn first_literal(node: &Node) -> Option<char> {
+    match node {
+        Node::Literal(c) => Some(*c),
+        Node::Concat(nodes) => nodes.first().and_then(first_literal),
+        Node::Group(node, _) => first_literal(node),
+        Node::Repeat { node, min, .. } if *min > 0 => first_literal(node),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CaseMode, Error, Regex, Syntax, STEP_LIMIT};
+
+    fn find(pattern: &str, syntax: Syntax, haystack: &str) -> Option<String> {
+        let regex = Regex::new(pattern, syntax, CaseMode::Sensitive).unwrap();
+        let caps = regex.captures_from(haystack, 0, &mut 0).unwrap()?;
+        Some(haystack[caps.start()..caps.end()].to_string())
+    }
+
+    fn matches(regex: &Regex, haystack: &str) -> bool {
+        regex.captures_from(haystack, 0, &mut 0).unwrap().is_some()
+    }
+
+    #[test]
+    fn test_vim_syntax() {
+        let vim = Syntax::Vim;
+        assert_eq!(find("a\\+b", vim, "caaab"), Some("aaab".to_string()));
+        assert_eq!(find("a+b", vim, "aa+b"), Some("a+b".to_string()));
+        assert_eq!(find("\\<is\\>", vim, "this is"), Some("is".to_string()));
+        assert_eq!(find("x\\{2,3}", vim, "xxxxx"), Some("xxx".to_string()));
+        assert_eq!(find("x\\{-2,3}", vim, "xxxxx"), Some("xx".to_string()));
+        assert_eq!(find("\\vfo(o|a)+", vim, "foaoz"), Some("foao".to_string()));
+        assert_eq!(find("a$b", vim, "a$b"), Some("a$b".to_string()));
+    }
+
+    #[test]
+    fn test_pcre_syntax() {
+        let pcre = Syntax::Pcre;
+        assert_eq!(find("\\d+", pcre, "ab 123"), Some("123".to_string()));
+        assert_eq!(find("a.*?b", pcre, "axbxb"), Some("axb".to_string()));
+        assert_eq!(find("(?:ab)+", pcre, "ababa"), Some("abab".to_string()));
+        assert_eq!(find("[^a-c]", pcre, "abcd"), Some("d".to_string()));
+        assert_eq!(find("(\\w)\\1", pcre, "abccd"), Some("cc".to_string()));
+        assert_eq!(find("^b", pcre, "ab"), None);
+    }
+
+    #[test]
+    fn test_multi_line() {
+        let pcre = Syntax::Pcre;
+        assert_eq!(find("o\\nb", pcre, "foo\nbar"), Some("o\nb".to_string()));
+        assert_eq!(find("^bar$", pcre, "foo\nbar"), Some("bar".to_string()));
+        assert_eq!(find("o.b", pcre, "foo\nbar"), None);
+        assert_eq!(
+            find("o\\_.b", Syntax::Vim, "foo\nbar"),
+            Some("o\nb".to_string())
+        );
+    }
+
+    #[test]
+    fn test_case_modes() {
+        let regex = Regex::new("foo", Syntax::Vim, CaseMode::Smart).unwrap();
+        assert!(matches(&regex, "FOO"));
+        let regex = Regex::new("Foo", Syntax::Vim, CaseMode::Smart).unwrap();
+        assert!(!matches(&regex, "FOO"));
+        let regex =
+            Regex::new("foo\\C", Syntax::Vim, CaseMode::Insensitive).unwrap();
+        assert!(!matches(&regex, "FOO"));
+        let regex =
+            Regex::new("(?i)foo", Syntax::Pcre, CaseMode::Sensitive).unwrap();
+        assert!(matches(&regex, "fOo"));
+    }
+
+    #[test]
+    fn test_captures() {
+        let regex =
+            Regex::new("(\\w+)=(\\w*)", Syntax::Pcre, CaseMode::Sensitive)
+                .unwrap();
+        let caps = regex
+            .captures_from("  key=value", 0, &mut 0)
+            .unwrap()
+            .unwrap();
+        assert_eq!(caps.get(1), Some((2, 5)));
+        assert_eq!(caps.get(2), Some((6, 11)));
+        assert_eq!(caps.get(3), None);
+    }
+
+    #[test]
+    fn test_pathological_pattern_aborts() {
+        let regex =
+            Regex::new("(a*)*b", Syntax::Pcre, CaseMode::Sensitive).unwrap();
+        let haystack = "a".repeat(32);
+        assert_eq!(
+            regex.captures_from(&haystack, 0, &mut 0),
+            Err(Error::TooExpensive)
+        );
+    }
+
+    #[test]
+    fn test_shared_steps() {
+        // Steps already taken by the same search count against the limit
+        let regex =
+            Regex::new("ab", Syntax::Pcre, CaseMode::Sensitive).unwrap();
+        let mut steps = 0;
+        assert!(regex.captures_from("xab", 0, &mut steps).is_ok());
+        assert!(steps > 0);
+        let mut steps = STEP_LIMIT;
+        assert_eq!(
+            regex.captures_from("xab", 0, &mut steps),
+            Err(Error::TooExpensive)
+        );
+    }
+
+    #[test]
+    fn test_invalid_patterns() {
+        let pcre = Syntax::Pcre;
+        let sensitive = CaseMode::Sensitive;
+        assert!(Regex::new("(ab", pcre, sensitive).is_err());
+        assert!(Regex::new("ab)", pcre, sensitive).is_err());
+        assert!(Regex::new("*a", pcre, sensitive).is_err());
+        assert!(Regex::new("[b-a]", pcre, sensitive).is_err());
+        assert!(Regex::new("\\(a\\)\\2", Syntax::Vim, sensitive).is_err());
+    }
+}
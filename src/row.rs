@@ -13,10 +13,16 @@
 use std::cmp;
 use unicode_segmentation::UnicodeSegmentation;
 
-#[derive(Default, Debug, PartialEq)]
+#[derive(Default, Debug, Clone)]
 pub struct Row {
     string: String,
     len: usize,
+    // Set by :global on the lines it has to visit. Since the mark lives
+    // in the row itself it stays with the line when others are deleted
+    marked: bool,
+    // Name of the entry a line of a directory listing was read from, see
+    // explorer.rs
+    origin: Option<String>,
 }
 
 impl From<&str> for Row {
@@ -24,10 +30,18 @@ impl From<&str> for Row {
         Self {
             string: String::from(slice),
             len: slice.graphemes(true).count(),
+            marked: false,
+            origin: None,
         }
     }
 }
 
+impl PartialEq for Row {
+    fn eq(&self, other: &Self) -> bool {
+        self.string == other.string
+    }
+}
+
 impl Row {
     #[must_use]
 
@@ -120,6 +134,32 @@ impl Row {
         &self.string[start..end]
     }
 
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        &self.string
+    }
+
+    #[must_use]
+    pub fn byte_index(&self, at: usize) -> usize {
+        // Byte offset of the grapheme at index `at`, or the length of the
+        // row when `at` is past its end
+        self.string[..]
+            .grapheme_indices(true)
+            .nth(at)
+            .map_or(self.string.len(), |(index, _)| index)
+    }
+
+    #[must_use]
+    pub fn grapheme_index(&self, byte: usize) -> usize {
+        // Index of the grapheme containing the byte at offset `byte`
+        self.string[..]
+            .grapheme_indices(true)
+            .take_while(|(index, grapheme)| {
+                index.saturating_add(grapheme.len()) <= byte
+            })
+            .count()
+    }
+
     #[must_use]
     pub fn as_bytes(&self) -> &[u8] {
         self.string.as_bytes()
@@ -135,6 +175,24 @@ impl Row {
         self.len == 0
     }
 
+    #[must_use]
+    pub fn is_marked(&self) -> bool {
+        self.marked
+    }
+
+    pub fn set_marked(&mut self, marked: bool) {
+        self.marked = marked;
+    }
+
+    #[must_use]
+    pub fn origin(&self) -> Option<&str> {
+        self.origin.as_deref()
+    }
+
+    pub fn set_origin(&mut self, origin: Option<String>) {
+        self.origin = origin;
+    }
+
     fn update_len(&mut self) {
         self.len = self.string[..].graphemes(true).count();
     }
@@ -0,0 +1,191 @@
+// Writing files without ever leaving a half written one behind. The text
+// goes to a temporary file next to the target, which is synced to disk and
+// then renamed over the target, so until the rename succeeds the original
+// is untouched, and after it the new text is all there.
+//
+// The new file gets the permissions (and, when allowed, the owner) of the
+// one it replaces. Writing through a symlink replaces the file it points
+// to and leaves the link alone.
+//
+// What a file was like when it was last read or written is kept in a
+// Stamp, to tell when something else changed it since.
+
+use std::collections::hash_map::DefaultHasher;
+use std::fs::{self, File, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::SystemTime;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stamp {
+    modified: Option<SystemTime>,
+    len: u64,
+    hash: u64,
+}
+
+impl Stamp {
+    #[must_use]
+    pub fn new(path: &Path, contents: &[u8]) -> Self {
+        // For `contents`, just read from or written to `path`
+        let mut hasher = DefaultHasher::new();
+        contents.hash(&mut hasher);
+        Self {
+            modified: fs::metadata(path)
+                .and_then(|metadata| metadata.modified())
+                .ok(),
+            len: contents.len() as u64,
+            hash: hasher.finish(),
+        }
+    }
+
+    pub fn read(path: &Path) -> Result<Self, io::Error> {
+        Ok(Self::new(path, &fs::read(path)?))
+    }
+
+    #[must_use]
+    pub fn matches(&self, metadata: &fs::Metadata) -> bool {
+        // Whether the file is likely the same without reading it, which is
+        // only taken for a yes
+        self.modified.is_some()
+            && metadata.modified().ok() == self.modified
+            && metadata.len() == self.len
+    }
+
+    #[must_use]
+    pub fn same_contents(&self, other: &Self) -> bool {
+        self.len == other.len && self.hash == other.hash
+    }
+}
+
+fn target(path: &Path) -> PathBuf {
+    // The file a symlink ends up at, or the path itself for anything else
+    // (including files that don't exist yet)
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_symlink() => {
+            fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+        }
+        _ => path.to_path_buf(),
+    }
+}
+
+fn with_suffix(path: &Path, prefix: &str, suffix: &str) -> PathBuf {
+    let name = path
+        .file_name()
+        .map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+    path.with_file_name(format!("{prefix}{name}{suffix}"))
+}
+
+#[cfg(unix)]
+fn copy_owner(from: &fs::Metadata, to: &Path) {
+    use std::os::unix::fs::MetadataExt;
+    // Only root can give a file away, anyone else keeps owning it
+    let _ = std::os::unix::fs::chown(to, Some(from.uid()), Some(from.gid()));
+}
+
+#[cfg(not(unix))]
+fn copy_owner(_from: &fs::Metadata, _to: &Path) {}
+
+fn sync_dir(path: &Path) {
+    // The rename itself is only on disk once the directory is, not every
+    // system lets directories be opened for that
+    if let Some(dir) = path.parent() {
+        let dir = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        };
+        if let Ok(dir) = File::open(dir) {
+            let _ = dir.sync_all();
+        }
+    }
+}
+
+fn write_temp(
+    temp: &Path,
+    contents: &[u8],
+    metadata: Option<&fs::Metadata>,
+) -> Result<(), io::Error> {
+    let mut file =
+        OpenOptions::new().write(true).create_new(true).open(temp)?;
+    file.write_all(contents)?;
+    if let Some(metadata) = metadata {
+        file.set_permissions(metadata.permissions())?;
+        copy_owner(metadata, temp);
+    }
+    file.sync_all()
+}
+
+pub fn write(
+    path: &Path,
+    contents: &[u8],
+    backup: bool,
+) -> Result<(), io::Error> {
+    // Replaces the file at `path` with `contents`, first copying the old
+    // one to `path~` if `backup` is set
+    let target = target(path);
+    let metadata = fs::metadata(&target).ok();
+    if metadata.as_ref().is_some_and(fs::Metadata::is_dir) {
+        return Err(io::Error::other("Is a directory"));
+    }
+    if backup && metadata.is_some() {
+        fs::copy(&target, with_suffix(&target, "", "~"))?;
+    }
+    let temp =
+        with_suffix(&target, ".", &format!(".{}.mtx-save", process::id()));
+    let result = write_temp(&temp, contents, metadata.as_ref())
+        .and_then(|()| fs::rename(&temp, &target));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result?;
+    sync_dir(&target);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::write;
+    use std::fs;
+
+    #[test]
+    fn test_write() {
+        let dir = std::env::temp_dir()
+            .join(format!("mtx-save-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("file");
+        let backup = dir.join("file~");
+
+        write(&file, b"one\n", true).unwrap();
+        assert_eq!(fs::read(&file).unwrap(), b"one\n");
+        // Nothing to back up yet
+        assert!(!&backup.exists());
+        write(&file, b"two\n", true).unwrap();
+        assert_eq!(fs::read(&file).unwrap(), b"two\n");
+        assert_eq!(fs::read(&backup).unwrap(), b"one\n");
+
+        // A directory can't be replaced, and nothing is left behind
+        assert!(write(&dir, b"x", false).is_err());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::{symlink, PermissionsExt};
+            fs::set_permissions(&file, fs::Permissions::from_mode(0o640))
+                .unwrap();
+            let link = dir.join("link");
+            symlink(&file, &link).unwrap();
+            write(&link, b"three\n", false).unwrap();
+            assert!(fs::symlink_metadata(&link)
+                .unwrap()
+                .file_type()
+                .is_symlink());
+            assert_eq!(fs::read(&file).unwrap(), b"three\n");
+            let mode = fs::metadata(&file).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o640);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,224 @@
+// A grid with a cell for every position of the terminal. The editor draws
+// every frame into one of these instead of writing to the terminal, and
+// the Terminal compares it with the last frame it showed so that only the
+// cells that changed (and the color changes between them) get written out.
+
+use crate::Position;
+use std::fmt::Write;
+use termion::{color, cursor};
+use unicode_segmentation::UnicodeSegmentation;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Style {
+    // None is the terminal's default color
+    pub fg: Option<(u8, u8, u8)>,
+    pub bg: Option<(u8, u8, u8)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Cell {
+    // A whole grapheme, cells are never left empty
+    text: String,
+    style: Style,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Self {
+            text: " ".to_string(),
+            style: Style::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Screen {
+    width: usize,
+    height: usize,
+    cells: Vec<Cell>,
+    // Where the next print goes and the style it is printed in, the
+    // cursor is also where the terminal's cursor is left after a frame
+    pub cursor: Position,
+    pub style: Style,
+}
+
+impl Screen {
+    #[must_use]
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            cells: vec![Cell::default(); width.saturating_mul(height)],
+            cursor: Position::default(),
+            style: Style::default(),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.cells.fill(Cell::default());
+    }
+
+    pub fn clear_line(&mut self) {
+        let start = self.cursor.y.saturating_mul(self.width);
+        let end = start.saturating_add(self.width).min(self.cells.len());
+        if let Some(cells) = self.cells.get_mut(start..end) {
+            cells.fill(Cell::default());
+        }
+    }
+
+    pub fn print(&mut self, text: &str) {
+        // Text past the right edge is cut off instead of wrapping
+        for grapheme in text.graphemes(true) {
+            if self.cursor.x < self.width && self.cursor.y < self.height {
+                let index = self
+                    .cursor
+                    .y
+                    .saturating_mul(self.width)
+                    .saturating_add(self.cursor.x);
+                if let Some(cell) = self.cells.get_mut(index) {
+                    *cell = Cell {
+                        text: grapheme.to_string(),
+                        style: self.style,
+                    };
+                }
+            }
+            self.cursor.x = self.cursor.x.saturating_add(1);
+        }
+    }
+
+    #[cfg(test)]
+    #[must_use]
+    pub fn line(&self, y: usize) -> String {
+        let start = y.saturating_mul(self.width);
+        let end = start.saturating_add(self.width).min(self.cells.len());
+        self.cells
+            .get(start..end)
+            .unwrap_or_default()
+            .iter()
+            .map(|cell| cell.text.as_str())
+            .collect()
+    }
+
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn diff(&self, old: &Self) -> String {
+        // The output that turns what `old` shows into this frame, cells
+        // that didn't change are skipped over. A frame of another size
+        // is redrawn whole
+        let resized = old.width != self.width || old.height != self.height;
+        let mut output = String::new();
+        let mut at = None;
+        // Unknown until the first cell sets both colors
+        let mut style: Option<Style> = None;
+        for (index, cell) in self.cells.iter().enumerate() {
+            if !resized && old.cells.get(index) == Some(cell) {
+                continue;
+            }
+            let position = Position {
+                x: index % self.width,
+                y: index / self.width,
+            };
+            if at.as_ref() != Some(&position) {
+                let x = position.x.saturating_add(1) as u16;
+                let y = position.y.saturating_add(1) as u16;
+                let _ = write!(output, "{}", cursor::Goto(x, y));
+            }
+            if style.is_none_or(|style| style.fg != cell.style.fg) {
+                let _ = match cell.style.fg {
+                    Some((r, g, b)) => {
+                        write!(output, "{}", color::Fg(color::Rgb(r, g, b)))
+                    }
+                    None => write!(output, "{}", color::Fg(color::Reset)),
+                };
+            }
+            if style.is_none_or(|style| style.bg != cell.style.bg) {
+                let _ = match cell.style.bg {
+                    Some((r, g, b)) => {
+                        write!(output, "{}", color::Bg(color::Rgb(r, g, b)))
+                    }
+                    None => write!(output, "{}", color::Bg(color::Reset)),
+                };
+            }
+            style = Some(cell.style);
+            output.push_str(&cell.text);
+            at = Some(Position {
+                x: position.x.saturating_add(1),
+                y: position.y,
+            });
+        }
+        if style.is_some_and(|style| style != Style::default()) {
+            let _ = write!(
+                output,
+                "{}{}",
+                color::Fg(color::Reset),
+                color::Bg(color::Reset)
+            );
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Screen, Style};
+    use crate::Position;
+    use termion::{color, cursor};
+
+    #[test]
+    fn test_print() {
+        let mut screen = Screen::new(4, 2);
+        screen.cursor = Position { x: 2, y: 1 };
+        screen.print("abc");
+        assert_eq!(screen.line(0), "    ");
+        assert_eq!(screen.line(1), "  ab");
+        screen.cursor.y = 1;
+        screen.clear_line();
+        assert_eq!(screen.line(1), "    ");
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = Screen::new(4, 2);
+        let mut new = old.clone();
+        assert_eq!(new.diff(&old), "");
+
+        // Unchanged cells in between are skipped
+        new.cursor = Position { x: 1, y: 1 };
+        new.print("a b");
+        assert_eq!(
+            new.diff(&old),
+            format!(
+                "{}{}{}a{}b",
+                cursor::Goto(2, 2),
+                color::Fg(color::Reset),
+                color::Bg(color::Reset),
+                cursor::Goto(4, 2)
+            )
+        );
+
+        // Only the colors that change are set, and reset at the end
+        let mut old = new.clone();
+        new.cursor = Position { x: 0, y: 0 };
+        new.style = Style {
+            fg: None,
+            bg: Some((1, 2, 3)),
+        };
+        new.print("xy");
+        new.style.fg = Some((4, 5, 6));
+        new.print("z");
+        assert_eq!(
+            new.diff(&old),
+            format!(
+                "{}{}{}xy{}z{}{}",
+                cursor::Goto(1, 1),
+                color::Fg(color::Reset),
+                color::Bg(color::Rgb(1, 2, 3)),
+                color::Fg(color::Rgb(4, 5, 6)),
+                color::Fg(color::Reset),
+                color::Bg(color::Reset)
+            )
+        );
+        old.clone_from(&new);
+        assert_eq!(new.diff(&old), "");
+    }
+}
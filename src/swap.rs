@@ -0,0 +1,270 @@
+// Swap files, which keep the unsaved changes of a buffer on disk so they
+// survive a crash or a dropped connection. As in vim, the swap file of
+// `dir/name` is `dir/.name.swp`. It is written every now and then while
+// the buffer has unsaved changes, and removed once it is saved or closed.
+//
+// A swap file holds the process id of the editor that wrote it, the text
+// and the most recent undo and redo steps that fit in HISTORY_SIZE. Every
+// part starts with a line saying what it is and how many lines of text
+// follow, the text itself never has a '\n' in it since every line is one
+// row.
+
+use crate::save;
+use crate::Document;
+use crate::Position;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Stdio};
+
+const MAGIC: &str = "mtx swap 1";
+// Undo steps are whole copies of the text, so only the last few are kept,
+// and only as many as fit in HISTORY_SIZE bytes since the swap file is
+// written while typing
+pub const UNDO_STEPS: usize = 100;
+pub const HISTORY_SIZE: usize = 1 << 20;
+
+#[derive(Debug, Default, PartialEq)]
+pub enum Swap {
+    // Not looked for yet, which happens when the buffer is first shown
+    #[default]
+    Unchecked,
+    // The buffer doesn't get one, as for unnamed or read-only buffers
+    Disabled,
+    // `written` is the version of the document last written to it, None
+    // when the file isn't there (or isn't ours yet)
+    File {
+        path: PathBuf,
+        written: Option<usize>,
+    },
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Contents {
+    pub pid: u32,
+    pub text: Vec<String>,
+    // Oldest first, with where the cursor goes back to
+    pub undo: Vec<(Vec<String>, Position)>,
+    pub redo: Vec<(Vec<String>, Position)>,
+}
+
+#[must_use]
+pub fn path(file_name: &Path) -> PathBuf {
+    let name = file_name
+        .file_name()
+        .map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+    file_name.with_file_name(format!(".{name}.swp"))
+}
+
+#[must_use]
+pub fn is_running(pid: u32) -> bool {
+    // Whether the editor that wrote a swap file is still around, which
+    // Linux tells through /proc and everything else through kill -0
+    if pid == process::id() {
+        return true;
+    }
+    let proc = Path::new("/proc");
+    if proc.is_dir() {
+        return proc.join(pid.to_string()).exists();
+    }
+    Command::new("kill")
+        .args(["-0", &pid.to_string()])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+fn push_lines(out: &mut String, header: &str, lines: &[String]) {
+    out.push_str(header);
+    out.push(' ');
+    out.push_str(&lines.len().to_string());
+    out.push('\n');
+    for line in lines {
+        out.push_str(line);
+        out.push('\n');
+    }
+}
+
+impl Contents {
+    #[must_use]
+    pub fn encode(&self) -> String {
+        let mut out = format!("{MAGIC}\npid {}\n", self.pid);
+        push_lines(&mut out, "text", &self.text);
+        for (name, steps) in [("undo", &self.undo), ("redo", &self.redo)] {
+            for (text, cursor) in steps {
+                let header = format!("{name} {} {}", cursor.x, cursor.y);
+                push_lines(&mut out, &header, text);
+            }
+        }
+        out
+    }
+
+    pub fn decode(swap: &str) -> Result<Self, String> {
+        let damaged = || "Swap file is damaged".to_string();
+        let number = |word: Option<&str>| -> Result<usize, String> {
+            word.and_then(|word| word.parse().ok()).ok_or_else(damaged)
+        };
+        let mut lines = swap.split('\n');
+        if lines.next() != Some(MAGIC) {
+            return Err("Not a swap file".to_string());
+        }
+        let mut contents = Self::default();
+        let pid = lines.next().and_then(|line| line.strip_prefix("pid "));
+        contents.pid =
+            pid.and_then(|pid| pid.parse().ok()).ok_or_else(damaged)?;
+        while let Some(header) = lines.next().filter(|line| !line.is_empty()) {
+            let mut words = header.split(' ');
+            let name = words.next();
+            let cursor = if name == Some("text") {
+                Position::default()
+            } else {
+                Position {
+                    x: number(words.next())?,
+                    y: number(words.next())?,
+                }
+            };
+            let count = number(words.next())?;
+            let mut text = Vec::with_capacity(count);
+            for _ in 0..count {
+                text.push(lines.next().ok_or_else(damaged)?.to_string());
+            }
+            match name {
+                Some("text") => contents.text = text,
+                Some("undo") => contents.undo.push((text, cursor)),
+                Some("redo") => contents.redo.push((text, cursor)),
+                _ => return Err(damaged()),
+            }
+        }
+        Ok(contents)
+    }
+}
+
+pub fn sync(document: &mut Document) -> Result<(), io::Error> {
+    // Brings the swap file of `document` up to date: written while there
+    // are changes it doesn't have, removed once there are none to keep.
+    // Rows in hex mode aren't the text, so they wait until it is left
+    if document.hex().is_some() {
+        return Ok(());
+    }
+    let version = document.version();
+    let contents = match &document.swap {
+        Swap::File { written, .. }
+            if document.is_dirty() && *written != Some(version) =>
+        {
+            Some(document.swap_contents().encode())
+        }
+        Swap::File {
+            written: Some(_), ..
+        } if !document.is_dirty() => None,
+        _ => return Ok(()),
+    };
+    let Swap::File { path, written } = &mut document.swap else {
+        return Ok(());
+    };
+    if let Some(contents) = contents {
+        save::write(path, contents.as_bytes(), false)?;
+        *written = Some(version);
+    } else {
+        remove(path)?;
+        *written = None;
+    }
+    Ok(())
+}
+
+pub fn discard(document: &mut Document) {
+    // For a buffer that is going away, its changes with it
+    if let Swap::File { path, written } = &mut document.swap {
+        if written.take().is_some() {
+            let _ = remove(path);
+        }
+    }
+}
+
+pub fn remove(path: &Path) -> Result<(), io::Error> {
+    match fs::remove_file(path) {
+        Err(error) if error.kind() != ErrorKind::NotFound => Err(error),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_running, path, sync, Contents, Swap, HISTORY_SIZE};
+    use crate::fileformat::Layout;
+    use crate::Document;
+    use crate::Position;
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn test_contents() {
+        let contents = Contents {
+            pid: 42,
+            text: vec!["a".to_string(), String::new(), "b\r".to_string()],
+            undo: vec![(vec!["a".to_string()], Position { x: 1, y: 0 })],
+            redo: vec![(Vec::new(), Position::default())],
+        };
+        assert_eq!(Contents::decode(&contents.encode()), Ok(contents));
+        assert!(Contents::decode("mtx swap 1\npid 1\ntext 3\na\n").is_err());
+        assert!(Contents::decode("hello").is_err());
+        assert_eq!(path(Path::new("src/a.rs")), Path::new("src/.a.rs.swp"));
+        assert!(is_running(std::process::id()));
+    }
+
+    #[test]
+    fn test_sync() {
+        let file = std::env::temp_dir()
+            .join(format!("mtx-swap-{}.txt", std::process::id()));
+        let swap = path(&file);
+        let mut document = Document::default();
+        document.swap = Swap::File {
+            path: swap.clone(),
+            written: None,
+        };
+        sync(&mut document).unwrap();
+        assert!(!swap.exists());
+        document.checkpoint(&Position::default());
+        document.insert(&Position::default(), 'x');
+        sync(&mut document).unwrap();
+        let contents =
+            Contents::decode(&fs::read_to_string(&swap).unwrap()).unwrap();
+        assert_eq!(contents.text, vec!["x".to_string()]);
+        assert_eq!(contents.undo.len(), 1);
+        document
+            .save(
+                Some(file.to_string_lossy().into()),
+                Layout::default(),
+                false,
+            )
+            .unwrap();
+        sync(&mut document).unwrap();
+        assert!(!swap.exists());
+        fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn test_history_size() {
+        // Only the two most recent of the big undo steps fit, and the small
+        // redo step still does after them
+        let step = |text: String, y| (vec![text], Position { x: 0, y });
+        let big = |y| step("x".repeat(HISTORY_SIZE / 3), y);
+        let mut document = Document::default();
+        document.recover(Contents {
+            pid: 0,
+            text: vec!["now".to_string()],
+            undo: vec![big(0), big(1), big(2)],
+            redo: vec![step("next".to_string(), 3)],
+        });
+        let contents = document.swap_contents();
+        assert_eq!(contents.undo, vec![big(1), big(2)]);
+        assert_eq!(contents.redo, vec![step("next".to_string(), 3)]);
+
+        let huge = step("x".repeat(HISTORY_SIZE), 0);
+        document.recover(Contents {
+            undo: vec![huge],
+            ..Contents::default()
+        });
+        assert!(document.swap_contents().undo.is_empty());
+    }
+}
@@ -0,0 +1,113 @@
+// Tab pages, each one holding its own layout of windows.
+//
+// As with buffers and windows, the current tab's windows live in the
+// Editor while it is current and its slot here only holds a placeholder,
+// switching tabs swaps the windows in and out of the list.
+
+use crate::window::Windows;
+
+pub struct Tabs {
+    list: Vec<Windows>,
+    current: usize,
+}
+
+impl Default for Tabs {
+    fn default() -> Self {
+        Self {
+            list: vec![Windows::default()],
+            current: 0,
+        }
+    }
+}
+
+impl Tabs {
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    #[must_use]
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    #[must_use]
+    pub fn get(&self, index: usize) -> Option<&Windows> {
+        self.list.get(index)
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Windows> {
+        self.list.iter_mut()
+    }
+
+    pub fn add(&mut self, windows: Windows) -> usize {
+        // New tabs go right after the current one, returns the index
+        let index = self.current.saturating_add(1).min(self.list.len());
+        self.list.insert(index, windows);
+        index
+    }
+
+    // Index of the tab after (or before) the current one, wrapping around
+    #[must_use]
+    pub fn step(&self, forward: bool) -> usize {
+        let len = self.list.len().max(1);
+        if forward {
+            self.current.saturating_add(1) % len
+        } else {
+            self.current.checked_sub(1).unwrap_or(len.saturating_sub(1))
+        }
+    }
+
+    pub fn swap(&mut self, index: usize, windows: &mut Windows) {
+        // Exchanges the windows of the tab at `index` with `windows`
+        if let Some(tab) = self.list.get_mut(index) {
+            std::mem::swap(tab, windows);
+        }
+    }
+
+    pub fn set_current(&mut self, index: usize) {
+        if index < self.list.len() {
+            self.current = index;
+        }
+    }
+
+    pub fn remove(&mut self, index: usize) -> Result<(), String> {
+        // Closing the current tab is up to the caller, who has to make
+        // another one current first
+        if self.list.len() == 1 {
+            return Err("Cannot close last tab page".to_string());
+        }
+        if index == self.current || index >= self.list.len() {
+            return Err("Cannot close the current tab page".to_string());
+        }
+        self.list.remove(index);
+        if index < self.current {
+            self.current = self.current.saturating_sub(1);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Tabs;
+    use crate::window::Windows;
+
+    #[test]
+    fn test_tabs() {
+        let mut tabs = Tabs::default();
+        assert_eq!(tabs.add(Windows::new(2)), 1);
+        assert_eq!(tabs.add(Windows::new(3)), 1);
+        assert_eq!(tabs.step(false), 2);
+        tabs.set_current(2);
+        assert_eq!(tabs.step(true), 0);
+        assert!(tabs.remove(2).is_err());
+        assert!(tabs.remove(0).is_ok());
+        assert_eq!(tabs.current(), 1);
+
+        let mut windows = Windows::new(7);
+        tabs.swap(0, &mut windows);
+        let tab = tabs.get(0).unwrap();
+        assert_eq!(tab.get(tab.current()).map(|window| window.buffer), Some(7));
+    }
+}
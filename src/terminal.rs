@@ -1,90 +1,139 @@
+use crate::backend::{Backend, TermionBackend};
+use crate::screen::Screen;
 use crate::Position;
-use std::io::{self, stdout, Write};
+use std::cell::RefCell;
+use std::time::Duration;
 use termion::color;
-use termion::event::Key;
-use termion::input::TermRead;
-use termion::raw::{IntoRawMode, RawTerminal};
+use termion::event::{Event, Key};
 
+#[derive(PartialEq)]
 pub struct Size {
     pub width: u16,
     pub height: u16,
 }
+
+impl Size {
+    fn of(width: u16, height: u16) -> Self {
+        // The rows left for windows once the tab line, the last window's
+        // status line and the message bar are taken out
+        Self {
+            width,
+            height: height.saturating_sub(3),
+        }
+    }
+}
+
 pub struct Terminal {
     size: Size,
-    _stdout: RawTerminal<std::io::Stdout>,
+    // Drawing goes into `frame`, flush() hands it to the backend along
+    // with `shown`, the last frame it was given
+    frame: RefCell<Screen>,
+    shown: RefCell<Screen>,
+    backend: RefCell<Box<dyn Backend>>,
 }
 
 impl Terminal {
+    pub fn new(backend: Box<dyn Backend>) -> Result<Self, std::io::Error> {
+        let (width, height) = backend.size()?;
+        let screen = Screen::new(width.into(), height.into());
+        Ok(Self {
+            size: Size::of(width, height),
+            frame: RefCell::new(screen.clone()),
+            shown: RefCell::new(screen),
+            backend: RefCell::new(backend),
+        })
+    }
+
     #[must_use]
     pub fn size(&self) -> &Size {
         &self.size
     }
 
-    pub fn clear_screen() {
-        print!("{}", termion::clear::All);
+    pub fn clear_screen(&self) -> Result<(), std::io::Error> {
+        // Clears the terminal itself, for when the editor is done with it
+        self.backend.borrow_mut().clear()
     }
 
-    #[allow(clippy::cast_possible_truncation)]
-    pub fn cursor_position(position: &Position) {
-        let Position { mut x, mut y } = position;
-        x = x.saturating_add(1);
-        y = y.saturating_add(1);
-        let x = x as u16;
-        let y = y as u16;
-        print!("{}", termion::cursor::Goto(x, y));
+    pub fn clear(&self) {
+        self.frame.borrow_mut().clear();
     }
 
-    pub fn flush() -> Result<(), std::io::Error> {
-        io::stdout().flush()
+    pub fn cursor_position(&self, position: &Position) {
+        self.frame.borrow_mut().cursor = position.clone();
     }
 
-    pub fn read_key() -> Result<Key, std::io::Error> {
+    pub fn print(&self, text: &str) {
+        self.frame.borrow_mut().print(text);
+    }
+
+    pub fn flush(&self) -> Result<(), std::io::Error> {
+        let frame = self.frame.borrow();
+        let mut shown = self.shown.borrow_mut();
+        self.backend.borrow_mut().draw(&frame, &shown)?;
+        shown.clone_from(&frame);
+        Ok(())
+    }
+
+    pub fn read_event(&self) -> Result<Event, std::io::Error> {
+        self.backend.borrow_mut().read_event()
+    }
+
+    pub fn read_key(&self) -> Result<Key, std::io::Error> {
+        // Whatever isn't a key is skipped
         loop {
-            if let Some(key) = io::stdin().lock().keys().next() {
-                return key;
+            if let Event::Key(key) = self.read_event()? {
+                return Ok(key);
             }
         }
     }
 
-    pub fn cursor_hide() {
-        print!("{}", termion::cursor::Hide);
+    pub fn poll(&self, timeout: Duration) -> Result<bool, std::io::Error> {
+        self.backend.borrow_mut().poll(timeout)
     }
 
-    pub fn cursor_show() {
-        print!("{}", termion::cursor::Show);
+    pub fn resize(&mut self) -> Result<bool, std::io::Error> {
+        // Picks up the size the terminal has now, true when it changed.
+        // What is on the terminal is left over from the old size, so the
+        // next frame is drawn whole, every cell of it
+        let (width, height) = self.backend.get_mut().size()?;
+        let size = Size::of(width, height);
+        if size == self.size {
+            return Ok(false);
+        }
+        self.size = size;
+        *self.frame.get_mut() = Screen::new(width.into(), height.into());
+        *self.shown.get_mut() = Screen::new(0, 0);
+        Ok(true)
     }
 
-    pub fn clear_current_line() {
-        print!("{}", termion::clear::CurrentLine);
+    pub fn clear_current_line(&self) {
+        self.frame.borrow_mut().clear_line();
     }
 
-    pub fn set_bg_color(color: color::Rgb) {
-        print!("{}", color::Bg(color));
+    pub fn set_bg_color(&self, color: color::Rgb) {
+        let color::Rgb(r, g, b) = color;
+        self.frame.borrow_mut().style.bg = Some((r, g, b));
     }
 
-    pub fn reset_bg_color() {
-        print!("{}", color::Bg(color::Reset));
+    pub fn reset_bg_color(&self) {
+        self.frame.borrow_mut().style.bg = None;
     }
 
-    pub fn set_fg_color(color: color::Rgb) {
-        print!("{}", color::Fg(color));
+    pub fn set_fg_color(&self, color: color::Rgb) {
+        let color::Rgb(r, g, b) = color;
+        self.frame.borrow_mut().style.fg = Some((r, g, b));
     }
 
-    pub fn reset_fg_color() {
-        print!("{}", color::Fg(color::Reset));
+    pub fn reset_fg_color(&self) {
+        self.frame.borrow_mut().style.fg = None;
     }
 }
 
 impl Default for Terminal {
     fn default() -> Self {
-        let size = termion::terminal_size().unwrap();
-        Terminal::clear_screen();
-        Self {
-            size: Size {
-                width: size.0,
-                height: size.1.saturating_sub(2),
-            },
-            _stdout: stdout().into_raw_mode().unwrap(),
-        }
+        let backend = TermionBackend::new().unwrap();
+        let terminal = Self::new(Box::new(backend)).unwrap();
+        terminal.clear_screen().unwrap();
+        terminal
     }
 }
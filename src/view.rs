@@ -0,0 +1,344 @@
+// How the lines of a document are laid out in a window. A tab is as wide
+// as it takes to reach the next multiple of 'tabstop', line numbers take a
+// gutter at the left, and lines longer than the window are either cut at
+// its edge (scrolling sideways to follow the cursor) or, with 'wrap', go on
+// in the rows below. A byte that isn't valid in the file's encoding takes
+// four columns, as <xx>.
+//
+// Positions in a document count graphemes, positions in a window count
+// columns, the functions here convert between the two.
+
+use crate::encoding::{self, ESCAPE_WIDTH};
+use crate::Document;
+use crate::Position;
+use unicode_segmentation::UnicodeSegmentation;
+
+// Narrowest gutter, line numbers included, as vim's 'numberwidth'
+const MIN_GUTTER_WIDTH: usize = 4;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct View {
+    // Columns taken by the line numbers, the text gets the rest
+    pub gutter: usize,
+    pub width: usize,
+    pub height: usize,
+    pub tabstop: usize,
+    pub wrap: bool,
+    // Lines kept in view above and below the cursor
+    pub scrolloff: usize,
+}
+
+#[must_use]
+pub fn gutter_width(lines: usize) -> usize {
+    // The widest line number and a space after it
+    lines
+        .to_string()
+        .len()
+        .saturating_add(1)
+        .max(MIN_GUTTER_WIDTH)
+}
+
+fn grapheme_width(grapheme: &str, column: usize, tabstop: usize) -> usize {
+    if grapheme == "\t" {
+        let tabstop = tabstop.max(1);
+        tabstop.saturating_sub(column % tabstop)
+    } else if encoding::escaped_grapheme(grapheme).is_some() {
+        ESCAPE_WIDTH
+    } else {
+        1
+    }
+}
+
+#[must_use]
+pub fn cells(line: &str, tabstop: usize) -> Vec<(&str, usize, usize)> {
+    // Every grapheme of the line with the column it starts at and how many
+    // columns it takes
+    let mut column = 0;
+    line.graphemes(true)
+        .map(|grapheme| {
+            let width = grapheme_width(grapheme, column, tabstop);
+            let cell = (grapheme, column, width);
+            column = column.saturating_add(width);
+            cell
+        })
+        .collect()
+}
+
+#[must_use]
+pub fn width(line: &str, tabstop: usize) -> usize {
+    // Columns the whole line takes
+    cells(line, tabstop)
+        .last()
+        .map_or(0, |(_, column, width)| column.saturating_add(*width))
+}
+
+#[must_use]
+pub fn column(line: &str, x: usize, tabstop: usize) -> usize {
+    // The column where grapheme x starts, past the end of the line every
+    // position takes a column
+    let cells = cells(line, tabstop);
+    match cells.get(x) {
+        Some((_, column, _)) => *column,
+        None => {
+            width(line, tabstop).saturating_add(x.saturating_sub(cells.len()))
+        }
+    }
+}
+
+#[must_use]
+pub fn index(line: &str, column: usize, tabstop: usize) -> usize {
+    // The grapheme shown at `column`, the opposite of column()
+    let cells = cells(line, tabstop);
+    for (x, (_, start, width)) in cells.iter().enumerate() {
+        if column < start.saturating_add(*width) {
+            return x;
+        }
+    }
+    let end = width(line, tabstop);
+    cells.len().saturating_add(column.saturating_sub(end))
+}
+
+impl View {
+    fn text_width(&self) -> usize {
+        self.width.max(1)
+    }
+
+    #[must_use]
+    pub fn rows(&self, line: &str) -> usize {
+        // Rows the line takes in the window
+        if !self.wrap {
+            return 1;
+        }
+        width(line, self.tabstop).div_ceil(self.text_width()).max(1)
+    }
+
+    fn line_rows(&self, document: &Document, y: usize) -> usize {
+        document.row(y).map_or(1, |row| self.rows(row.as_str()))
+    }
+
+    #[must_use]
+    pub fn cell(&self, line: &str, x: usize) -> (usize, usize) {
+        // The row (from the first one of the line) and column grapheme x
+        // is shown at, before any sideways scrolling
+        let column = column(line, x, self.tabstop);
+        if !self.wrap {
+            return (0, column);
+        }
+        // The cursor just past a line that fills its last row is kept on
+        // that row
+        let width = self.text_width();
+        let row = (column / width).min(self.rows(line).saturating_sub(1));
+        let column = column.saturating_sub(row.saturating_mul(width));
+        (row, column.min(width.saturating_sub(1)))
+    }
+
+    #[must_use]
+    pub fn position(
+        &self,
+        document: &Document,
+        offset: &Position,
+        at: &Position,
+    ) -> Option<Position> {
+        // Where `at` is shown in the window's text, if it is in view
+        let line = document.row(at.y).map_or("", |row| row.as_str());
+        let (row, column) = self.cell(line, at.x);
+        let y = (offset.y..at.y)
+            .map(|y| self.line_rows(document, y))
+            .sum::<usize>()
+            .saturating_add(row);
+        let x = column.checked_sub(offset.x)?;
+        if at.y < offset.y || y >= self.height || x >= self.text_width() {
+            return None;
+        }
+        Some(Position { x, y })
+    }
+
+    #[must_use]
+    pub fn at(
+        &self,
+        document: &Document,
+        offset: &Position,
+        x: usize,
+        y: usize,
+    ) -> Position {
+        // The document position shown at column x of row y of the window's
+        // text, past the end of a line are the positions after it
+        let last = document.len().saturating_sub(1);
+        let mut line = offset.y.min(last);
+        let mut row = y;
+        while line < last && row >= self.line_rows(document, line) {
+            row = row.saturating_sub(self.line_rows(document, line));
+            line = line.saturating_add(1);
+        }
+        let row = row.min(self.line_rows(document, line).saturating_sub(1));
+        let column = if self.wrap {
+            row.saturating_mul(self.text_width()).saturating_add(x)
+        } else {
+            offset.x.saturating_add(x)
+        };
+        let text = document.row(line).map_or("", |row| row.as_str());
+        Position {
+            x: index(text, column, self.tabstop),
+            y: line,
+        }
+    }
+
+    #[must_use]
+    pub fn lines(
+        &self,
+        document: &Document,
+        offset: &Position,
+    ) -> Vec<(usize, usize)> {
+        // The line and the column it starts at of each row of the window
+        // that shows text, rows after the last line are left out
+        let mut lines = Vec::new();
+        let mut y = offset.y;
+        while lines.len() < self.height && y < document.len() {
+            for row in 0..self.line_rows(document, y) {
+                let start = if self.wrap {
+                    row.saturating_mul(self.text_width())
+                } else {
+                    offset.x
+                };
+                lines.push((y, start));
+            }
+            y = y.saturating_add(1);
+        }
+        lines.truncate(self.height);
+        lines
+    }
+
+    pub fn scroll(
+        &self,
+        document: &Document,
+        cursor: &Position,
+        offset: &mut Position,
+    ) {
+        // Moves `offset` just enough for the cursor to be in view, with
+        // 'scrolloff' lines around it where the document has them
+        let margin = self.scrolloff.min(self.height.saturating_sub(1) / 2);
+        let top = cursor.y.saturating_sub(margin);
+        if top < offset.y {
+            offset.y = top;
+        } else {
+            let bottom = cursor
+                .y
+                .saturating_add(margin)
+                .min(document.len().saturating_sub(1))
+                .max(cursor.y);
+            let line = document.row(cursor.y).map_or("", |row| row.as_str());
+            let (row, _) = self.cell(line, cursor.x);
+            // Rows from the top of the window to the bottom of what has
+            // to be in view
+            let needed = |offset: usize| {
+                let above: usize = (offset..cursor.y)
+                    .map(|y| self.line_rows(document, y))
+                    .sum();
+                let below: usize = (cursor.y.saturating_add(1)..=bottom)
+                    .map(|y| self.line_rows(document, y))
+                    .sum();
+                above
+                    .saturating_add(row)
+                    .saturating_add(1)
+                    .saturating_add(below)
+            };
+            while offset.y < cursor.y && needed(offset.y) > self.height {
+                offset.y = offset.y.saturating_add(1);
+            }
+        }
+        if self.wrap {
+            offset.x = 0;
+            return;
+        }
+        let width = self.text_width();
+        let (_, column) = self.cell(
+            document.row(cursor.y).map_or("", |row| row.as_str()),
+            cursor.x,
+        );
+        if column < offset.x {
+            offset.x = column;
+        } else if column >= offset.x.saturating_add(width) {
+            offset.x = column.saturating_sub(width).saturating_add(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{column, gutter_width, index, View};
+    use crate::Document;
+    use crate::Position;
+
+    fn document(text: &str) -> Document {
+        let mut document = Document::default();
+        document.set_text(text);
+        document
+    }
+
+    #[test]
+    fn test_columns() {
+        assert_eq!(column("a\tb", 1, 4), 1);
+        assert_eq!(column("a\tb", 2, 4), 4);
+        assert_eq!(column("a\tb", 3, 4), 5);
+        assert_eq!(column("a\tb", 5, 4), 7);
+        assert_eq!(column("\t\t", 1, 8), 8);
+        assert_eq!(index("a\tb", 2, 4), 1);
+        assert_eq!(index("a\tb", 3, 4), 1);
+        assert_eq!(index("a\tb", 4, 4), 2);
+        assert_eq!(index("a\tb", 7, 4), 5);
+        assert_eq!(gutter_width(9), 4);
+        assert_eq!(gutter_width(12345), 6);
+    }
+
+    #[test]
+    fn test_wrap() {
+        let view = View {
+            width: 4,
+            height: 3,
+            tabstop: 8,
+            wrap: true,
+            ..View::default()
+        };
+        let document = document("abcdefghij\nk\nlm");
+        assert_eq!(view.rows("abcdefghij"), 3);
+        assert_eq!(view.cell("abcdefghij", 5), (1, 1));
+        assert_eq!(
+            view.lines(&document, &Position::default()),
+            vec![(0, 0), (0, 4), (0, 8)]
+        );
+        let at = Position { x: 0, y: 2 };
+        assert_eq!(view.position(&document, &Position::default(), &at), None);
+        let mut offset = Position::default();
+        view.scroll(&document, &at, &mut offset);
+        assert_eq!(offset, Position { x: 0, y: 1 });
+        assert_eq!(
+            view.position(&document, &offset, &at),
+            Some(Position { x: 0, y: 1 })
+        );
+        assert_eq!(view.at(&document, &offset, 1, 1), Position { x: 1, y: 2 });
+    }
+
+    #[test]
+    fn test_scrolloff() {
+        let view = View {
+            width: 10,
+            height: 4,
+            tabstop: 8,
+            scrolloff: 1,
+            ..View::default()
+        };
+        let document = document("a\nb\nc\nd\ne\nf");
+        let mut offset = Position::default();
+        view.scroll(&document, &Position { x: 0, y: 3 }, &mut offset);
+        assert_eq!(offset.y, 1);
+        view.scroll(&document, &Position { x: 0, y: 5 }, &mut offset);
+        assert_eq!(offset.y, 2);
+        view.scroll(&document, &Position { x: 0, y: 2 }, &mut offset);
+        assert_eq!(offset.y, 1);
+        // Sideways, the cursor goes by columns
+        let document = self::document("\t\tx");
+        let mut offset = Position::default();
+        view.scroll(&document, &Position { x: 2, y: 0 }, &mut offset);
+        assert_eq!(offset.x, 7);
+    }
+}
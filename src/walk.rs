@@ -0,0 +1,308 @@
+// Recursive directory walking that skips whatever .gitignore files say
+// should be ignored, used to find the files :grep searches through.
+//
+// Only the commonly used part of the gitignore format is supported:
+// `*`, `?`, `**` and `[...]` globs, `!` negation, a trailing '/' for
+// directories only and a leading (or inner) '/' anchoring the pattern
+// to the directory of the .gitignore file it is in.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+struct Pattern {
+    glob: Vec<char>,
+    negated: bool,
+    dir_only: bool,
+    // Anchored patterns match the path relative to the .gitignore file,
+    // the others match the file name at any depth
+    anchored: bool,
+}
+
+impl Pattern {
+    fn parse(line: &str) -> Option<Self> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line.strip_prefix('\\').unwrap_or(line)),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let anchored = line.contains('/');
+        let line = line.strip_prefix('/').unwrap_or(line);
+        if line.is_empty() {
+            return None;
+        }
+        Some(Self {
+            glob: line.chars().collect(),
+            negated,
+            dir_only,
+            anchored,
+        })
+    }
+
+    fn matches(&self, relative: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let text: Vec<char> = if self.anchored {
+            relative.chars().collect()
+        } else {
+            let name = relative.rsplit('/').next().unwrap_or(relative);
+            name.chars().collect()
+        };
+        glob_match(&self.glob, &text)
+    }
+}
+
+fn glob_match(glob: &[char], text: &[char]) -> bool {
+    match glob.split_first() {
+        None => text.is_empty(),
+        Some(('*', rest)) => {
+            if let Some(('*', rest)) = rest.split_first() {
+                // `**` matches across directories, `a/**/b` also matches
+                // `a/b` so the slash after it is optional
+                let after = rest.strip_prefix(&['/']).unwrap_or(rest);
+                return (0..=text.len()).any(|skip| {
+                    text.get(skip..).is_some_and(|text| {
+                        glob_match(after, text) || glob_match(rest, text)
+                    })
+                });
+            }
+            for skip in 0..=text.len() {
+                let Some(remaining) = text.get(skip..) else {
+                    break;
+                };
+                if glob_match(rest, remaining) {
+                    return true;
+                }
+                if text.get(skip) == Some(&'/') {
+                    break;
+                }
+            }
+            false
+        }
+        Some(('?', rest)) => match text.split_first() {
+            Some((c, text)) if *c != '/' => glob_match(rest, text),
+            _ => false,
+        },
+        Some(('[', rest)) => {
+            let Some(close) = rest.iter().skip(1).position(|c| *c == ']')
+            else {
+                return literal_match('[', rest, text);
+            };
+            let close = close.saturating_add(1);
+            let (class, rest) =
+                (&rest[..close], &rest[close.saturating_add(1)..]);
+            match text.split_first() {
+                Some((c, text)) if *c != '/' && class_match(class, *c) => {
+                    glob_match(rest, text)
+                }
+                _ => false,
+            }
+        }
+        Some(('\\', rest)) => match rest.split_first() {
+            Some((c, rest)) => literal_match(*c, rest, text),
+            None => literal_match('\\', rest, text),
+        },
+        Some((c, rest)) => literal_match(*c, rest, text),
+    }
+}
+
+fn literal_match(c: char, glob: &[char], text: &[char]) -> bool {
+    match text.split_first() {
+        Some((first, text)) if *first == c => glob_match(glob, text),
+        _ => false,
+    }
+}
+
+fn class_match(class: &[char], c: char) -> bool {
+    let (negated, class) = match class.split_first() {
+        Some(('!' | '^', rest)) => (true, rest),
+        _ => (false, class),
+    };
+    let mut found = false;
+    let mut index = 0;
+    while let Some(lo) = class.get(index) {
+        if class.get(index.saturating_add(1)) == Some(&'-') {
+            if let Some(hi) = class.get(index.saturating_add(2)) {
+                found |= *lo <= c && c <= *hi;
+                index = index.saturating_add(3);
+                continue;
+            }
+        }
+        found |= *lo == c;
+        index = index.saturating_add(1);
+    }
+    found != negated
+}
+
+struct Ignore {
+    base: PathBuf,
+    patterns: Vec<Pattern>,
+}
+
+impl Ignore {
+    fn load(dir: &Path) -> Option<Self> {
+        let contents = fs::read_to_string(dir.join(".gitignore")).ok()?;
+        Some(Self {
+            base: dir.to_path_buf(),
+            patterns: contents.lines().filter_map(Pattern::parse).collect(),
+        })
+    }
+
+    // None when no pattern applies to the path
+    fn is_ignored(&self, path: &Path, is_dir: bool) -> Option<bool> {
+        let relative = path.strip_prefix(&self.base).ok()?;
+        let relative = relative.to_string_lossy().replace('\\', "/");
+        // The last matching pattern decides
+        self.patterns
+            .iter()
+            .rev()
+            .find(|pattern| pattern.matches(&relative, is_dir))
+            .map(|pattern| !pattern.negated)
+    }
+}
+
+fn is_ignored(stack: &[Ignore], path: &Path, is_dir: bool) -> bool {
+    // Deeper .gitignore files take precedence over the ones above them
+    stack
+        .iter()
+        .rev()
+        .find_map(|ignore| ignore.is_ignored(path, is_dir))
+        .unwrap_or(false)
+}
+
+fn ancestors_ignores(dir: &Path) -> Vec<Ignore> {
+    // .gitignore files above `dir` up to the root of the repository it is
+    // in, outermost first. `dir` has to be canonical
+    let mut ancestors: Vec<&Path> = Vec::new();
+    for ancestor in dir.ancestors().skip(1) {
+        ancestors.push(ancestor);
+        if ancestor.join(".git").exists() {
+            break;
+        }
+    }
+    if !ancestors
+        .last()
+        .is_some_and(|root| root.join(".git").exists())
+    {
+        return Vec::new();
+    }
+    ancestors
+        .iter()
+        .rev()
+        .filter_map(|dir| Ignore::load(dir))
+        .collect()
+}
+
+fn walk_dir(dir: &Path, stack: &mut Vec<Ignore>, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    let pushed = match Ignore::load(dir) {
+        Some(ignore) => {
+            stack.push(ignore);
+            true
+        }
+        None => false,
+    };
+    let mut entries: Vec<(PathBuf, fs::FileType)> = entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            Some((entry.path(), entry.file_type().ok()?))
+        })
+        .collect();
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    for (path, file_type) in entries {
+        if path.file_name().is_some_and(|name| name == ".git") {
+            continue;
+        }
+        // Symlinked directories are not followed so a link to a parent
+        // can't send the walk into a loop
+        let is_dir = file_type.is_dir();
+        if is_ignored(stack, &path, is_dir) {
+            continue;
+        }
+        if is_dir {
+            walk_dir(&path, stack, files);
+        } else if path.is_file() {
+            files.push(path);
+        }
+    }
+    if pushed {
+        stack.pop();
+    }
+}
+
+#[must_use]
+pub fn files(roots: &[PathBuf]) -> Vec<PathBuf> {
+    // Files given explicitly are always included, directories are walked.
+    // The walk happens on the canonical path since that is what ignore
+    // files are relative to, but the paths handed out keep the prefix
+    // the user gave
+    let mut files = Vec::new();
+    for root in roots {
+        if !root.is_dir() {
+            if root.exists() {
+                files.push(root.clone());
+            }
+            continue;
+        }
+        let Ok(canonical) = root.canonicalize() else {
+            continue;
+        };
+        let mut stack = ancestors_ignores(&canonical);
+        let mut found = Vec::new();
+        walk_dir(&canonical, &mut stack, &mut found);
+        for path in found {
+            match path.strip_prefix(&canonical) {
+                Ok(relative) if root == Path::new(".") => {
+                    files.push(relative.to_path_buf());
+                }
+                Ok(relative) => files.push(root.join(relative)),
+                Err(_) => files.push(path),
+            }
+        }
+    }
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{glob_match, Pattern};
+
+    fn glob(glob: &str, text: &str) -> bool {
+        let glob: Vec<char> = glob.chars().collect();
+        let text: Vec<char> = text.chars().collect();
+        glob_match(&glob, &text)
+    }
+
+    #[test]
+    fn test_glob() {
+        assert!(glob("*.rs", "main.rs"));
+        assert!(!glob("*.rs", "src/main.rs"));
+        assert!(glob("src/**/*.rs", "src/a/b/main.rs"));
+        assert!(glob("src/**/*.rs", "src/main.rs"));
+        assert!(glob("**/target", "a/target"));
+        assert!(glob("file?.[ch]", "file1.c"));
+        assert!(!glob("file[!0-9]", "file1"));
+    }
+
+    #[test]
+    fn test_patterns() {
+        let pattern = Pattern::parse("target/").unwrap();
+        assert!(pattern.matches("a/target", true));
+        assert!(!pattern.matches("a/target", false));
+        let pattern = Pattern::parse("/build").unwrap();
+        assert!(pattern.matches("build", false));
+        assert!(!pattern.matches("src/build", false));
+        let pattern = Pattern::parse("!keep.log").unwrap();
+        assert!(pattern.negated);
+        assert!(Pattern::parse("# comment").is_none());
+    }
+}
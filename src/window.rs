@@ -0,0 +1,465 @@
+// Windows are views into buffers laid out on the screen, each one with its
+// own cursor and scroll offset. The layout is a tree where every split
+// holds windows (or more splits) stacked on top of each other or side by
+// side, and the screen area is divided between them by their sizes.
+//
+// Like the current buffer, the current window's cursor and offset live in
+// the Editor while it is current, what is stored here for it is stale.
+
+use crate::options::Options;
+use crate::Position;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    // Windows on top of each other, as made by :split
+    Horizontal,
+    // Windows side by side, as made by :vsplit
+    Vertical,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    // Includes the window's status line, which is its last row
+    pub height: usize,
+}
+
+impl Rect {
+    fn contains(&self, x: usize, y: usize) -> bool {
+        self.x <= x
+            && x < self.x.saturating_add(self.width)
+            && self.y <= y
+            && y < self.y.saturating_add(self.height)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Window {
+    pub id: usize,
+    // Number of the buffer shown in the window
+    pub buffer: usize,
+    pub cursor_position: Position,
+    pub offset: Position,
+    // Options set with :setlocal for this window
+    pub options: Options,
+}
+
+#[derive(Debug)]
+enum Node {
+    Leaf(usize),
+    // Children with their sizes, which only matter relative to each other
+    Split(Direction, Vec<(Node, usize)>),
+}
+
+impl Node {
+    fn is_leaf(&self, id: usize) -> bool {
+        matches!(self, Node::Leaf(leaf) if *leaf == id)
+    }
+
+    fn contains(&self, id: usize) -> bool {
+        match self {
+            Node::Leaf(leaf) => *leaf == id,
+            Node::Split(_, children) => {
+                children.iter().any(|(child, _)| child.contains(id))
+            }
+        }
+    }
+
+    fn split(
+        &mut self,
+        id: usize,
+        new: usize,
+        direction: Direction,
+        size: usize,
+    ) {
+        // Puts `new` before the `id` leaf, both taking half its size
+        let Node::Split(split_direction, children) = self else {
+            if self.is_leaf(id) {
+                let half = size.saturating_div(2).max(1);
+                let rest = size.saturating_sub(half).max(1);
+                *self = Node::Split(
+                    direction,
+                    vec![(Node::Leaf(new), half), (Node::Leaf(id), rest)],
+                );
+            }
+            return;
+        };
+        let Some(index) =
+            children.iter().position(|(child, _)| child.contains(id))
+        else {
+            return;
+        };
+        let direct = children
+            .get(index)
+            .is_some_and(|(child, _)| child.is_leaf(id));
+        if direct && *split_direction == direction {
+            // Split in the same direction as the parent, so the new window
+            // becomes a sibling instead of starting another split
+            let Some((_, weight)) = children.get_mut(index) else {
+                return;
+            };
+            let half = weight.saturating_div(2).max(1);
+            *weight = weight.saturating_sub(half).max(1);
+            children.insert(index, (Node::Leaf(new), half));
+        } else if let Some((child, _)) = children.get_mut(index) {
+            child.split(id, new, direction, size);
+        }
+    }
+
+    fn remove(&mut self, id: usize) {
+        let Node::Split(_, children) = self else {
+            return;
+        };
+        let Some(index) =
+            children.iter().position(|(child, _)| child.contains(id))
+        else {
+            return;
+        };
+        if children
+            .get(index)
+            .is_some_and(|(child, _)| child.is_leaf(id))
+        {
+            // The space is given to a neighbour
+            let (_, weight) = children.remove(index);
+            let neighbour = index.min(children.len().saturating_sub(1));
+            if let Some((_, size)) = children.get_mut(neighbour) {
+                *size = size.saturating_add(weight);
+            }
+        } else if let Some((child, _)) = children.get_mut(index) {
+            child.remove(id);
+        }
+        // A split left with a single child is replaced by that child
+        if children.len() == 1 {
+            if let Some((child, _)) = children.pop() {
+                *self = child;
+            }
+        }
+    }
+
+    fn resize(
+        &mut self,
+        id: usize,
+        direction: Direction,
+        delta: isize,
+    ) -> bool {
+        // Grows (or shrinks) the innermost split in `direction` that holds
+        // `id`, the space comes from the next sibling (or the previous one
+        // for the last child). Returns whether a split was found
+        let Node::Split(split_direction, children) = self else {
+            return false;
+        };
+        let Some(index) =
+            children.iter().position(|(child, _)| child.contains(id))
+        else {
+            return false;
+        };
+        if let Some((child, _)) = children.get_mut(index) {
+            if child.resize(id, direction, delta) {
+                return true;
+            }
+        }
+        if *split_direction != direction || children.len() < 2 {
+            return false;
+        }
+        let other = if index.saturating_add(1) < children.len() {
+            index.saturating_add(1)
+        } else {
+            index.saturating_sub(1)
+        };
+        let (Some(size), Some(other_size)) = (
+            children.get(index).map(|(_, size)| *size),
+            children.get(other).map(|(_, size)| *size),
+        ) else {
+            return false;
+        };
+        let amount = delta.unsigned_abs();
+        let (size, other_size) = if delta > 0 {
+            let amount = amount.min(other_size.saturating_sub(1));
+            (
+                size.saturating_add(amount),
+                other_size.saturating_sub(amount),
+            )
+        } else {
+            let amount = amount.min(size.saturating_sub(1));
+            (
+                size.saturating_sub(amount),
+                other_size.saturating_add(amount),
+            )
+        };
+        if let Some((_, weight)) = children.get_mut(index) {
+            *weight = size;
+        }
+        if let Some((_, weight)) = children.get_mut(other) {
+            *weight = other_size;
+        }
+        true
+    }
+
+    fn equalize(&mut self) {
+        if let Node::Split(_, children) = self {
+            let total: usize = children.iter().map(|(_, size)| *size).sum();
+            let size = total.saturating_div(children.len().max(1)).max(1);
+            for (child, weight) in children {
+                *weight = size;
+                child.equalize();
+            }
+        }
+    }
+
+    fn layout(&self, area: Rect, rects: &mut Vec<(usize, Rect)>) {
+        let (direction, children) = match self {
+            Node::Leaf(id) => {
+                rects.push((*id, area));
+                return;
+            }
+            Node::Split(direction, children) => (direction, children),
+        };
+        // Side by side windows have a column between them for a separator
+        let separators = match direction {
+            Direction::Horizontal => 0,
+            Direction::Vertical => children.len().saturating_sub(1),
+        };
+        let space = match direction {
+            Direction::Horizontal => area.height,
+            Direction::Vertical => area.width.saturating_sub(separators),
+        };
+        let total: usize = children.iter().map(|(_, size)| *size).sum();
+        let total = total.max(1);
+        let mut start = 0;
+        for (index, (child, weight)) in children.iter().enumerate() {
+            let size = if index.saturating_add(1) == children.len() {
+                space.saturating_sub(start)
+            } else {
+                // Rounded to the nearest row or column
+                space
+                    .saturating_mul(*weight)
+                    .saturating_add(total / 2)
+                    .saturating_div(total)
+                    .min(space.saturating_sub(start))
+            };
+            let rect = match direction {
+                Direction::Horizontal => Rect {
+                    y: area.y.saturating_add(start),
+                    height: size,
+                    ..area
+                },
+                Direction::Vertical => Rect {
+                    x: area.x.saturating_add(start).saturating_add(index),
+                    width: size,
+                    ..area
+                },
+            };
+            child.layout(rect, rects);
+            start = start.saturating_add(size);
+        }
+    }
+}
+
+pub struct Windows {
+    list: Vec<Window>,
+    layout: Node,
+    current: usize,
+    last_id: usize,
+}
+
+impl Default for Windows {
+    fn default() -> Self {
+        Self::new(1)
+    }
+}
+
+impl Windows {
+    #[must_use]
+    pub fn new(buffer: usize) -> Self {
+        // A single window showing `buffer`
+        Self {
+            list: vec![Window {
+                id: 1,
+                buffer,
+                ..Window::default()
+            }],
+            layout: Node::Leaf(1),
+            current: 1,
+            last_id: 1,
+        }
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    #[must_use]
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    #[must_use]
+    pub fn get(&self, id: usize) -> Option<&Window> {
+        self.list.iter().find(|window| window.id == id)
+    }
+
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut Window> {
+        self.list.iter_mut().find(|window| window.id == id)
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Window> {
+        self.list.iter_mut()
+    }
+
+    pub fn set_current(&mut self, id: usize) {
+        if self.get(id).is_some() {
+            self.current = id;
+        }
+    }
+
+    pub fn split(&mut self, direction: Direction, size: usize) -> usize {
+        // Splits the current window in two, the new window is a copy of
+        // it placed above (or left of) it. Returns the id of the new one
+        self.last_id = self.last_id.saturating_add(1);
+        let window = Window {
+            id: self.last_id,
+            ..self.get(self.current).cloned().unwrap_or_default()
+        };
+        self.list.push(window);
+        self.layout
+            .split(self.current, self.last_id, direction, size);
+        self.last_id
+    }
+
+    pub fn close(&mut self, id: usize) -> Result<(), String> {
+        // Closing the current window is up to the caller, who has to make
+        // another one current first
+        if self.list.len() == 1 {
+            return Err("Cannot close last window".to_string());
+        }
+        if id == self.current {
+            return Err("Cannot close the current window".to_string());
+        }
+        self.list.retain(|window| window.id != id);
+        self.layout.remove(id);
+        Ok(())
+    }
+
+    pub fn only(&mut self) {
+        let current = self.current;
+        self.list.retain(|window| window.id == current);
+        self.layout = Node::Leaf(self.current);
+    }
+
+    pub fn resize(&mut self, direction: Direction, delta: isize) {
+        self.layout.resize(self.current, direction, delta);
+    }
+
+    pub fn equalize(&mut self) {
+        self.layout.equalize();
+    }
+
+    #[must_use]
+    pub fn layout(&self, area: Rect) -> Vec<(usize, Rect)> {
+        let mut rects = Vec::new();
+        self.layout.layout(area, &mut rects);
+        rects
+    }
+
+    // The window after the current one, in layout order
+    #[must_use]
+    pub fn next(&self, area: Rect) -> usize {
+        let rects = self.layout(area);
+        let index = rects
+            .iter()
+            .position(|(id, _)| *id == self.current)
+            .unwrap_or(0);
+        rects
+            .get(index.saturating_add(1))
+            .or_else(|| rects.first())
+            .map_or(self.current, |(id, _)| *id)
+    }
+
+    #[must_use]
+    pub fn neighbour(
+        &self,
+        area: Rect,
+        cursor: &Position,
+        direction: char,
+    ) -> Option<usize> {
+        // The window next to the current one in the direction of the
+        // h/j/k/l key, on the same line or column as the cursor (which is
+        // relative to the window's top left corner)
+        let rects = self.layout(area);
+        let (_, rect) = rects.iter().find(|(id, _)| *id == self.current)?;
+        let x = rect.x.saturating_add(cursor.x);
+        let y = rect.y.saturating_add(cursor.y);
+        let (x, y) = match direction {
+            'h' => (rect.x.checked_sub(2)?, y),
+            'l' => (rect.x.saturating_add(rect.width).saturating_add(1), y),
+            'k' => (x, rect.y.checked_sub(1)?),
+            'j' => (x, rect.y.saturating_add(rect.height)),
+            _ => return None,
+        };
+        rects
+            .iter()
+            .find(|(_, rect)| rect.contains(x, y))
+            .map(|(id, _)| *id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Direction, Rect, Windows};
+    use crate::Position;
+
+    const AREA: Rect = Rect {
+        x: 0,
+        y: 0,
+        width: 81,
+        height: 24,
+    };
+
+    #[test]
+    fn test_layout() {
+        let mut windows = Windows::default();
+        let top = windows.split(Direction::Horizontal, 24);
+        let rects = windows.layout(AREA);
+        assert_eq!(rects.len(), 2);
+        assert_eq!(rects[0], (top, Rect { height: 12, ..AREA }));
+        assert_eq!(rects[1].1.y, 12);
+
+        // Splitting the bottom window side by side
+        let left = windows.split(Direction::Vertical, 80);
+        let rects = windows.layout(AREA);
+        assert_eq!(rects[1].0, left);
+        assert_eq!(rects[1].1.width, 40);
+        assert_eq!(rects[2].1.x, 41);
+
+        let cursor = Position::default();
+        windows.set_current(left);
+        assert_eq!(windows.neighbour(AREA, &cursor, 'l'), Some(1));
+        assert_eq!(windows.neighbour(AREA, &cursor, 'k'), Some(top));
+        assert_eq!(windows.neighbour(AREA, &cursor, 'h'), None);
+
+        windows.resize(Direction::Vertical, 10);
+        assert_eq!(windows.layout(AREA)[1].1.width, 50);
+        windows.equalize();
+        assert_eq!(windows.layout(AREA)[1].1.width, 40);
+
+        assert!(windows.close(left).is_err());
+        windows.set_current(1);
+        assert!(windows.close(left).is_ok());
+        assert_eq!(
+            windows.layout(AREA)[1],
+            (
+                1,
+                Rect {
+                    y: 12,
+                    height: 12,
+                    ..AREA
+                }
+            )
+        );
+        windows.only();
+        assert_eq!(windows.layout(AREA), vec![(1, AREA)]);
+    }
+}